@@ -0,0 +1,544 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use database::{
+    audit::{AuditAction, AuditEvent},
+    commands::Command,
+    Database,
+};
+use futures::{future::BoxFuture, FutureExt};
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
+use sha2::Sha256;
+use thiserror::Error;
+
+pub const SIGNATURE_HEADER: &str = "X-Speakwith-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Speakwith-Timestamp";
+
+const EXTERNAL_TIMEOUT: Duration = Duration::from_secs(5);
+// reminders are only kept in memory, so they are kept short and few
+const MAX_REMINDER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const MAX_REMINDERS_PER_USER: usize = 10;
+
+/// A parsed `/command args` line typed into the message composer.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub name: String,
+    pub args: String,
+    pub room_id: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// shown only to the user who ran the command
+    Ephemeral(String),
+    /// posted into the room as a message from the user who ran the command
+    Message(String),
+}
+
+type Handler = for<'a> fn(&'a Dispatcher, &'a Invocation) -> BoxFuture<'a, Result<Reply>>;
+
+struct Builtin {
+    usage: &'static str,
+    description: &'static str,
+    handler: Handler,
+}
+
+pub struct Dispatcher {
+    db: Database,
+    room_manager: Arc<rooms::Manager>,
+    client: reqwest::Client,
+    builtins: HashMap<&'static str, Builtin>,
+    // pending reminders per user
+    reminders: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+/// Splits a message into command name and arguments if it starts with `/`.
+pub fn parse(msg: &str) -> Option<(String, String)> {
+    let rest = msg.trim_start().strip_prefix('/')?;
+
+    let (name, args) = match rest.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (rest.trim_end(), ""),
+    };
+
+    if !is_valid_name(name) {
+        return None;
+    }
+
+    Some((name.to_lowercase(), args.to_string()))
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Dispatcher {
+    pub fn new(db: Database, room_manager: Arc<rooms::Manager>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(EXTERNAL_TIMEOUT)
+            .build()?;
+
+        let mut dispatcher = Self {
+            db,
+            room_manager,
+            client,
+            builtins: HashMap::new(),
+            reminders: Default::default(),
+        };
+
+        dispatcher.register("help", "/help", "list available commands", help);
+        dispatcher.register("topic", "/topic <text>", "set the room topic", topic);
+        dispatcher.register(
+            "invite",
+            "/invite @user",
+            "add a user to this private room, or a bot to any room",
+            invite,
+        );
+        dispatcher.register("leave", "/leave", "leave this private room", leave);
+        dispatcher.register("me", "/me <action>", "post an action about yourself", me);
+        dispatcher.register(
+            "remind",
+            "/remind <10s|5m|2h|1d> <text>",
+            "remind yourself about something in this room",
+            remind,
+        );
+
+        Ok(dispatcher)
+    }
+
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        description: &'static str,
+        handler: Handler,
+    ) {
+        self.builtins.insert(
+            name,
+            Builtin {
+                usage,
+                description,
+                handler,
+            },
+        );
+    }
+
+    pub fn is_builtin(&self, name: &str) -> bool {
+        self.builtins.contains_key(name)
+    }
+
+    pub async fn dispatch(&self, invocation: &Invocation) -> Result<Reply> {
+        let result = match self.builtins.get(invocation.name.as_str()) {
+            Some(builtin) => (builtin.handler)(self, invocation).await,
+            None => match database::commands::get_command(&self.db, &invocation.name).await {
+                Ok(command) => self.invoke_external(&command, invocation).await,
+                Err(_) => Err(CommandError::Unknown(invocation.name.clone()).into()),
+            },
+        };
+
+        // errors meant for the caller are shown to them, everything else bubbles up
+        match result {
+            Err(e) => match e.downcast::<CommandError>() {
+                Ok(e) => Ok(Reply::Ephemeral(e.to_string())),
+                Err(e) => Err(e),
+            },
+            reply => reply,
+        }
+    }
+
+    async fn invoke_external(&self, command: &Command, invocation: &Invocation) -> Result<Reply> {
+        let body = serde_json::to_string(&ExternalRequest {
+            command: &invocation.name,
+            text: &invocation.args,
+            room_id: &invocation.room_id,
+            user_id: &invocation.user_id,
+            user_name: &invocation.user_name,
+        })?;
+
+        let timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+        let signature = sign(&command.secret, timestamp, &body)?;
+
+        let response = self
+            .client
+            .post(&command.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, signature)
+            .body(body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!("command /{} failed: {}", command.name, e);
+                return Err(CommandError::External(command.name.clone()).into());
+            }
+        };
+
+        let output: ExternalResponse = response
+            .json()
+            .await
+            .map_err(|_| CommandError::External(command.name.clone()))?;
+
+        Ok(match output.response_type {
+            ResponseType::Ephemeral => Reply::Ephemeral(output.text),
+            ResponseType::InRoom => Reply::Message(output.text),
+        })
+    }
+}
+
+/// Signature sent along with external command requests as `sha256=<hex>`.
+///
+/// Receivers should recompute it over `<timestamp>.<body>` with the secret
+/// handed out when the command was registered.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> Result<String> {
+    let mut mac: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    let output = mac.finalize().into_bytes();
+
+    Ok(format!("sha256={}", hex::encode(output)))
+}
+
+#[derive(serde::Serialize)]
+struct ExternalRequest<'a> {
+    command: &'a str,
+    text: &'a str,
+    room_id: &'a str,
+    user_id: &'a str,
+    user_name: &'a str,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ResponseType {
+    #[default]
+    Ephemeral,
+    InRoom,
+}
+
+#[derive(serde::Deserialize)]
+struct ExternalResponse {
+    text: String,
+    #[serde(default)]
+    response_type: ResponseType,
+}
+
+fn help<'a>(dispatcher: &'a Dispatcher, _: &'a Invocation) -> BoxFuture<'a, Result<Reply>> {
+    async move {
+        let mut builtins = dispatcher.builtins.values().collect::<Vec<_>>();
+        builtins.sort_unstable_by_key(|b| b.usage);
+
+        let mut lines = builtins
+            .into_iter()
+            .map(|b| format!("{} - {}", b.usage, b.description))
+            .collect::<Vec<String>>();
+
+        let external = database::commands::get_commands(&dispatcher.db).await?;
+        lines.extend(
+            external
+                .into_iter()
+                .map(|c| format!("/{} - {}", c.name, c.description)),
+        );
+
+        Ok(Reply::Ephemeral(lines.join("\n")))
+    }
+    .boxed()
+}
+
+fn topic<'a>(dispatcher: &'a Dispatcher, inv: &'a Invocation) -> BoxFuture<'a, Result<Reply>> {
+    async move {
+        if inv.args.is_empty() {
+            return Err(CommandError::Usage("/topic <text>").into());
+        }
+
+        let room = database::rooms::get_room(&dispatcher.db, &inv.room_id, &inv.user_id)
+            .await
+            .map_err(|_| CommandError::NoPermission)?;
+
+        if room.is_user {
+            return Err(CommandError::NotInDirectMessages.into());
+        }
+
+        database::rooms::set_room_description(&dispatcher.db, &room.id, &inv.args).await?;
+
+        Ok(Reply::Message(format!(
+            "changed the topic to: {}",
+            inv.args
+        )))
+    }
+    .boxed()
+}
+
+fn invite<'a>(dispatcher: &'a Dispatcher, inv: &'a Invocation) -> BoxFuture<'a, Result<Reply>> {
+    async move {
+        let username = inv.args.trim_start_matches('@');
+        if username.is_empty() {
+            return Err(CommandError::Usage("/invite @user").into());
+        }
+
+        let room = accessible_room(dispatcher, inv).await?;
+
+        let other = database::users::get_user_by_username(&dispatcher.db, username)
+            .await
+            .map_err(|_| CommandError::UserNotFound(username.to_string()))?;
+
+        // public rooms have no members, except for bots and guests who only see rooms they are invited to
+        if !room.is_private && !other.is_bot && !other.is_guest {
+            return Err(CommandError::OnlyPrivateRooms.into());
+        }
+
+        if database::rooms::is_member_of_room(&dispatcher.db, &room.id, &other.id).await {
+            return Ok(Reply::Ephemeral(format!(
+                "{} is already in #{}",
+                other.username, room.name
+            )));
+        }
+
+        database::blocks::ensure_not_blocked_by(&dispatcher.db, &inv.user_id, &other.id)
+            .await
+            .map_err(|e| match e.downcast::<database::blocks::BlockError>() {
+                Ok(e) => CommandError::Block(e).into(),
+                Err(e) => e,
+            })?;
+
+        database::rooms::add_user_to_room(&dispatcher.db, &room.id, &other.id).await?;
+        record_member_change(
+            dispatcher,
+            inv,
+            AuditAction::RoomMemberAdded,
+            &room.id,
+            &other.username,
+        )
+        .await;
+
+        Ok(Reply::Ephemeral(format!(
+            "added {} to #{}",
+            other.username, room.name
+        )))
+    }
+    .boxed()
+}
+
+// a failed write is logged, the member change itself stands
+async fn record_member_change(
+    dispatcher: &Dispatcher,
+    inv: &Invocation,
+    action: AuditAction,
+    room_id: &str,
+    member: &str,
+) {
+    let event = AuditEvent {
+        actor_id: Some(&inv.user_id),
+        action,
+        target_id: Some(room_id),
+        details: Some(member),
+        ip: None,
+    };
+
+    if let Err(e) = database::audit::record(&dispatcher.db, &event).await {
+        tracing::error!(
+            "failed to write {} to the audit log: {}",
+            action.as_str(),
+            e
+        );
+    }
+}
+
+fn leave<'a>(dispatcher: &'a Dispatcher, inv: &'a Invocation) -> BoxFuture<'a, Result<Reply>> {
+    async move {
+        let room = accessible_room(dispatcher, inv).await?;
+        if !room.is_private {
+            return Err(CommandError::OnlyPrivateRooms.into());
+        }
+
+        database::rooms::remove_user_from_room(&dispatcher.db, &room.id, &inv.user_id)
+            .await
+            .map_err(|e| match e.downcast::<database::rooms::RoomError>() {
+                Ok(e) => CommandError::Room(e).into(),
+                Err(e) => e,
+            })?;
+        record_member_change(
+            dispatcher,
+            inv,
+            AuditAction::RoomMemberRemoved,
+            &room.id,
+            &inv.user_name,
+        )
+        .await;
+
+        Ok(Reply::Ephemeral(format!("you left #{}", room.name)))
+    }
+    .boxed()
+}
+
+fn me<'a>(_: &'a Dispatcher, inv: &'a Invocation) -> BoxFuture<'a, Result<Reply>> {
+    async move {
+        if inv.args.is_empty() {
+            return Err(CommandError::Usage("/me <action>").into());
+        }
+
+        Ok(Reply::Message(format!("* {} {}", inv.user_name, inv.args)))
+    }
+    .boxed()
+}
+
+fn remind<'a>(dispatcher: &'a Dispatcher, inv: &'a Invocation) -> BoxFuture<'a, Result<Reply>> {
+    async move {
+        let usage = CommandError::Usage("/remind <10s|5m|2h|1d> <text>");
+
+        let Some((after, text)) = inv.args.split_once(char::is_whitespace) else {
+            return Err(usage.into());
+        };
+
+        let Some(after) = parse_duration(after) else {
+            return Err(usage.into());
+        };
+
+        if after > MAX_REMINDER {
+            return Err(CommandError::ReminderTooFar.into());
+        }
+
+        {
+            let mut reminders = dispatcher.reminders.lock();
+            let pending = reminders.entry(inv.user_id.clone()).or_default();
+            if *pending >= MAX_REMINDERS_PER_USER {
+                return Err(CommandError::TooManyReminders(MAX_REMINDERS_PER_USER).into());
+            }
+            *pending += 1;
+        }
+
+        // reminders live in memory and are delivered to whoever is connected to the room
+        let room_manager = dispatcher.room_manager.clone();
+        let reminders = dispatcher.reminders.clone();
+        let room_id = inv.room_id.clone();
+        let user_id = inv.user_id.clone();
+        let message = format!("Reminder: {}", text.trim());
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            if let Err(e) = room_manager.send_ephemeral(&room_id, &user_id, &message) {
+                tracing::warn!("failed to deliver reminder to {}: {}", user_id, e);
+            }
+
+            let mut reminders = reminders.lock();
+            if let Some(pending) = reminders.get_mut(&user_id) {
+                *pending -= 1;
+                if *pending == 0 {
+                    reminders.remove(&user_id);
+                }
+            }
+        });
+
+        Ok(Reply::Ephemeral(format!(
+            "I will remind you in {}",
+            inv.args.split_whitespace().next().unwrap_or_default()
+        )))
+    }
+    .boxed()
+}
+
+/// The current room, as long as it is not a direct message and the caller may manage its members.
+async fn accessible_room(
+    dispatcher: &Dispatcher,
+    inv: &Invocation,
+) -> Result<database::rooms::Room, CommandError> {
+    let room = database::rooms::get_room(&dispatcher.db, &inv.room_id, &inv.user_id)
+        .await
+        .map_err(|_| CommandError::NoPermission)?;
+
+    if room.is_user {
+        return Err(CommandError::NotInDirectMessages);
+    }
+
+    if room.is_private
+        && !inv.is_admin
+        && !database::rooms::is_member_of_room(&dispatcher.db, &room.id, &inv.user_id).await
+    {
+        return Err(CommandError::NoPermission);
+    }
+
+    Ok(room)
+}
+
+fn parse_duration(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let amount: u64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+
+    let seconds = match unit {
+        's' => Some(amount),
+        'm' => amount.checked_mul(60),
+        'h' => amount.checked_mul(60 * 60),
+        'd' => amount.checked_mul(60 * 60 * 24),
+        _ => return None,
+    }?;
+
+    Some(Duration::from_secs(seconds))
+}
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("unknown command: /{0}, try /help")]
+    Unknown(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("you do not have permission to do that here")]
+    NoPermission,
+    #[error("this command only works in private rooms")]
+    OnlyPrivateRooms,
+    #[error("this command does not work in direct messages")]
+    NotInDirectMessages,
+    #[error("no user named {0}")]
+    UserNotFound(String),
+    #[error("{0}")]
+    Room(database::rooms::RoomError),
+    #[error("{0}")]
+    Block(database::blocks::BlockError),
+    #[error("/{0} did not respond")]
+    External(String),
+    #[error("reminders can be at most a week away")]
+    ReminderTooFar,
+    #[error("you already have {0} reminders waiting")]
+    TooManyReminders(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse("/Remind 5m tea"),
+            Some(("remind".to_string(), "5m tea".to_string()))
+        );
+        assert_eq!(parse("  /help"), Some(("help".to_string(), String::new())));
+        assert_eq!(parse("no command"), None);
+        assert_eq!(parse("/not.valid"), None);
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        assert_eq!(parse_duration("999999999999999999d"), None);
+        assert_eq!(parse_duration("99999999999999999999s"), None);
+    }
+}
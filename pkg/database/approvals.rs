@@ -0,0 +1,282 @@
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(Error, Debug)]
+pub enum ApprovalError {
+    #[error("this account is not waiting for approval")]
+    NotPending,
+}
+
+/// Where a registration stands, accounts without one never needed approving.
+#[derive(serde::Serialize, Debug)]
+pub struct Approval {
+    // pending, approved or rejected
+    pub status: String,
+    pub reason: Option<String>,
+    pub decided_at: Option<OffsetDateTime>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct PendingUser {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub created_at: OffsetDateTime,
+}
+
+/// Queues the account for review, returns false when it already had a decision or request.
+pub async fn request_approval(db: &Database, user_id: &str) -> Result<bool> {
+    let now = OffsetDateTime::now_utc();
+    let result = sqlx::query!(
+        r#"
+INSERT OR IGNORE INTO user_approvals (user_id, status, created_at)
+VALUES ($1, 'pending', $2)
+"#,
+        user_id,
+        now
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn get_approval(db: &Database, user_id: &str) -> Result<Option<Approval>> {
+    let approval = sqlx::query_as!(
+        Approval,
+        r#"
+SELECT status, reason, decided_at as "decided_at: OffsetDateTime"
+FROM user_approvals
+WHERE user_id = $1
+"#,
+        user_id
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(approval)
+}
+
+/// Accounts waiting for an admin, oldest first.
+pub async fn get_pending_users(db: &Database) -> Result<Vec<PendingUser>> {
+    let users = sqlx::query_as!(
+        PendingUser,
+        r#"
+SELECT u.id, p.username, u.email, a.created_at as "created_at: OffsetDateTime"
+FROM user_approvals a
+INNER JOIN users u ON u.id = a.user_id
+INNER JOIN user_profiles p ON p.user_id = a.user_id
+WHERE a.status = 'pending' AND u.is_enabled = FALSE
+ORDER BY a.created_at
+"#
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(users)
+}
+
+pub async fn count_pending(db: &Database) -> Result<i64> {
+    let row = sqlx::query!(
+        r#"
+SELECT COUNT(*) as "count!: i64"
+FROM user_approvals a
+INNER JOIN users u ON u.id = a.user_id
+WHERE a.status = 'pending' AND u.is_enabled = FALSE
+"#
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(row.count)
+}
+
+/// Enables an account that is waiting in the queue.
+pub async fn approve_user(db: &Database, user_id: &str, decided_by: &str) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    let now = OffsetDateTime::now_utc();
+    // only a pending request, approving must not bring back accounts disabled for other reasons
+    let result = sqlx::query!(
+        r#"
+UPDATE user_approvals
+SET status = 'approved', reason = NULL, decided_by = $2, decided_at = $3
+WHERE user_id = $1 AND status = 'pending'
+  AND user_id IN (SELECT id FROM users WHERE is_enabled = FALSE)
+"#,
+        user_id,
+        decided_by,
+        now
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApprovalError::NotPending.into());
+    }
+
+    enable(&mut trx, user_id, now).await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+/// Enables any account on an admin's say, settling the request it may have had.
+pub async fn enable_account(db: &Database, user_id: &str, decided_by: &str) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+UPDATE user_approvals
+SET status = 'approved', reason = NULL, decided_by = $2, decided_at = $3
+WHERE user_id = $1
+"#,
+        user_id,
+        decided_by,
+        now
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    enable(&mut trx, user_id, now).await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+async fn enable(
+    trx: &mut Transaction<'_, Sqlite>,
+    user_id: &str,
+    now: OffsetDateTime,
+) -> Result<()> {
+    // a guest whose access ran out would be deactivated again right away
+    sqlx::query!(
+        r#"
+UPDATE users
+SET is_enabled = TRUE,
+    guest_expires_at = CASE WHEN guest_expires_at <= $2 THEN NULL ELSE guest_expires_at END
+WHERE id = $1
+"#,
+        user_id,
+        now
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    Ok(())
+}
+
+/// Keeps a queued account disabled, the reason is shown to the user when they try to sign in.
+pub async fn reject_user(
+    db: &Database,
+    user_id: &str,
+    decided_by: &str,
+    reason: Option<&str>,
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    let now = OffsetDateTime::now_utc();
+    // disabling anyone else goes through deactivation, which has its own checks
+    let result = sqlx::query!(
+        r#"
+UPDATE user_approvals
+SET status = 'rejected', reason = $2, decided_by = $3, decided_at = $4
+WHERE user_id = $1 AND status = 'pending'
+"#,
+        user_id,
+        reason,
+        decided_by,
+        now
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApprovalError::NotPending.into());
+    }
+
+    sqlx::query!("UPDATE users SET is_enabled = FALSE WHERE id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{database, local_user};
+
+    fn not_pending(result: Result<()>) -> bool {
+        matches!(
+            result.map_err(|e| e.downcast::<ApprovalError>()),
+            Err(Ok(ApprovalError::NotPending))
+        )
+    }
+
+    async fn is_enabled(db: &Database, user_id: &str) -> bool {
+        crate::users::get_user_with_profile(db, user_id)
+            .await
+            .unwrap()
+            .is_enabled
+    }
+
+    #[tokio::test]
+    async fn only_settles_pending_requests() {
+        let db = database("approvals").await;
+        let boss = local_user(&db, "boss").await;
+        let ada = local_user(&db, "ada").await;
+        let eve = local_user(&db, "eve").await;
+
+        crate::users::enable_user(&db, &ada, false).await.unwrap();
+        assert!(request_approval(&db, &ada).await.unwrap());
+        assert!(!request_approval(&db, &ada).await.unwrap());
+        approve_user(&db, &ada, &boss).await.unwrap();
+        assert!(is_enabled(&db, &ada).await);
+        assert!(not_pending(approve_user(&db, &ada, &boss).await));
+        assert!(not_pending(reject_user(&db, &ada, &boss, None).await));
+        assert!(is_enabled(&db, &ada).await);
+
+        // disabled without ever being queued
+        crate::users::deactivate_user(&db, &eve).await.unwrap();
+        assert!(not_pending(approve_user(&db, &eve, &boss).await));
+        assert!(!is_enabled(&db, &eve).await);
+
+        // only an explicit enable brings such an account back
+        enable_account(&db, &eve, &boss).await.unwrap();
+        assert!(is_enabled(&db, &eve).await);
+    }
+
+    #[tokio::test]
+    async fn rejects_with_a_reason() {
+        let db = database("rejections").await;
+        let boss = local_user(&db, "boss").await;
+        let ada = local_user(&db, "ada").await;
+
+        crate::users::enable_user(&db, &ada, false).await.unwrap();
+        request_approval(&db, &ada).await.unwrap();
+        assert_eq!(count_pending(&db).await.unwrap(), 1);
+
+        reject_user(&db, &ada, &boss, Some("who are you?"))
+            .await
+            .unwrap();
+        let approval = get_approval(&db, &ada).await.unwrap().unwrap();
+        assert_eq!(approval.status, "rejected");
+        assert_eq!(approval.reason.as_deref(), Some("who are you?"));
+        assert_eq!(count_pending(&db).await.unwrap(), 0);
+        assert!(not_pending(approve_user(&db, &ada, &boss).await));
+    }
+}
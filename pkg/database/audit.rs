@@ -0,0 +1,243 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+/// Something worth knowing about later, who did it is kept alongside.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    UserApproved,
+    UserRejected,
+    UserEnabled,
+    UserDisabled,
+    UserDeleted,
+    AdminGranted,
+    AdminRevoked,
+    UserRolesChanged,
+    RoleCreated,
+    RoleChanged,
+    RoleDeleted,
+    InviteCreated,
+    InviteRevoked,
+    PasswordResetLinkCreated,
+    TwoFactorReset,
+    TwoFactorPolicyChanged,
+    RoomMemberAdded,
+    RoomMemberRemoved,
+    GuestAccessChanged,
+    GuestConverted,
+    GuestExpired,
+    BotCreated,
+    BotTokenRotated,
+    ReportDismissed,
+    MessageDeleted,
+    UserWarned,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 28] = [
+        AuditAction::LoginSucceeded,
+        AuditAction::LoginFailed,
+        AuditAction::UserApproved,
+        AuditAction::UserRejected,
+        AuditAction::UserEnabled,
+        AuditAction::UserDisabled,
+        AuditAction::UserDeleted,
+        AuditAction::AdminGranted,
+        AuditAction::AdminRevoked,
+        AuditAction::UserRolesChanged,
+        AuditAction::RoleCreated,
+        AuditAction::RoleChanged,
+        AuditAction::RoleDeleted,
+        AuditAction::InviteCreated,
+        AuditAction::InviteRevoked,
+        AuditAction::PasswordResetLinkCreated,
+        AuditAction::TwoFactorReset,
+        AuditAction::TwoFactorPolicyChanged,
+        AuditAction::RoomMemberAdded,
+        AuditAction::RoomMemberRemoved,
+        AuditAction::GuestAccessChanged,
+        AuditAction::GuestConverted,
+        AuditAction::GuestExpired,
+        AuditAction::BotCreated,
+        AuditAction::BotTokenRotated,
+        AuditAction::ReportDismissed,
+        AuditAction::MessageDeleted,
+        AuditAction::UserWarned,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login_succeeded",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::UserApproved => "user_approved",
+            AuditAction::UserRejected => "user_rejected",
+            AuditAction::UserEnabled => "user_enabled",
+            AuditAction::UserDisabled => "user_disabled",
+            AuditAction::UserDeleted => "user_deleted",
+            AuditAction::AdminGranted => "admin_granted",
+            AuditAction::AdminRevoked => "admin_revoked",
+            AuditAction::UserRolesChanged => "user_roles_changed",
+            AuditAction::RoleCreated => "role_created",
+            AuditAction::RoleChanged => "role_changed",
+            AuditAction::RoleDeleted => "role_deleted",
+            AuditAction::InviteCreated => "invite_created",
+            AuditAction::InviteRevoked => "invite_revoked",
+            AuditAction::PasswordResetLinkCreated => "password_reset_link_created",
+            AuditAction::TwoFactorReset => "two_factor_reset",
+            AuditAction::TwoFactorPolicyChanged => "two_factor_policy_changed",
+            AuditAction::RoomMemberAdded => "room_member_added",
+            AuditAction::RoomMemberRemoved => "room_member_removed",
+            AuditAction::GuestAccessChanged => "guest_access_changed",
+            AuditAction::GuestConverted => "guest_converted",
+            AuditAction::GuestExpired => "guest_expired",
+            AuditAction::BotCreated => "bot_created",
+            AuditAction::BotTokenRotated => "bot_token_rotated",
+            AuditAction::ReportDismissed => "report_dismissed",
+            AuditAction::MessageDeleted => "message_deleted",
+            AuditAction::UserWarned => "user_warned",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "Signed in",
+            AuditAction::LoginFailed => "Failed to sign in",
+            AuditAction::UserApproved => "Approved registration",
+            AuditAction::UserRejected => "Rejected registration",
+            AuditAction::UserEnabled => "Enabled account",
+            AuditAction::UserDisabled => "Disabled account",
+            AuditAction::UserDeleted => "Deleted account",
+            AuditAction::AdminGranted => "Made admin",
+            AuditAction::AdminRevoked => "Removed admin",
+            AuditAction::UserRolesChanged => "Changed roles of",
+            AuditAction::RoleCreated => "Created role",
+            AuditAction::RoleChanged => "Changed role",
+            AuditAction::RoleDeleted => "Deleted role",
+            AuditAction::InviteCreated => "Created invite",
+            AuditAction::InviteRevoked => "Revoked invite",
+            AuditAction::PasswordResetLinkCreated => "Created password reset link for",
+            AuditAction::TwoFactorReset => "Reset two-factor authentication of",
+            AuditAction::TwoFactorPolicyChanged => "Changed two-factor policy",
+            AuditAction::RoomMemberAdded => "Added to room",
+            AuditAction::RoomMemberRemoved => "Removed from room",
+            AuditAction::GuestAccessChanged => "Changed guest access of",
+            AuditAction::GuestConverted => "Made guest a member",
+            AuditAction::GuestExpired => "Guest access expired",
+            AuditAction::BotCreated => "Created bot",
+            AuditAction::BotTokenRotated => "Rotated bot token",
+            AuditAction::ReportDismissed => "Dismissed report about",
+            AuditAction::MessageDeleted => "Deleted reported message of",
+            AuditAction::UserWarned => "Warned",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<AuditAction> {
+        AuditAction::ALL.into_iter().find(|a| a.as_str() == value)
+    }
+}
+
+pub struct AuditEvent<'a> {
+    // empty for what the app did on its own, like expiring guests
+    pub actor_id: Option<&'a str>,
+    pub action: AuditAction,
+    // a user, role or room
+    pub target_id: Option<&'a str>,
+    pub details: Option<&'a str>,
+    pub ip: Option<&'a str>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct AuditEntry {
+    pub id: String,
+    pub actor_id: Option<String>,
+    pub actor_name: Option<String>,
+    pub action: String,
+    pub target_id: Option<String>,
+    pub target_name: Option<String>,
+    pub details: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+/// Narrows down the entries, everything matches when empty.
+#[derive(Default, Debug)]
+pub struct AuditFilter {
+    pub action: Option<AuditAction>,
+    // matched against names, details and addresses
+    pub search: Option<String>,
+    pub since: Option<OffsetDateTime>,
+    pub until: Option<OffsetDateTime>,
+}
+
+pub async fn record(db: &Database, event: &AuditEvent<'_>) -> Result<()> {
+    let id = xid::new().to_string();
+    let action = event.action.as_str();
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        r#"
+INSERT INTO audit_log (id, actor_id, actor_name, action, target_id, target_name, details, ip, created_at)
+VALUES (
+    $1, $2, (SELECT username FROM user_profiles WHERE user_id = $2), $3, $4,
+    COALESCE(
+        (SELECT username FROM user_profiles WHERE user_id = $4),
+        (SELECT name FROM roles WHERE id = $4),
+        (SELECT name FROM rooms WHERE id = $4)
+    ),
+    $5, $6, $7
+)
+"#,
+        id,
+        event.actor_id,
+        action,
+        event.target_id,
+        event.details,
+        event.ip,
+        now
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Matching entries, newest first. Everything from `offset` on when `limit` is empty.
+pub async fn get_entries(
+    db: &Database,
+    filter: &AuditFilter,
+    limit: Option<i64>,
+    offset: i64,
+) -> Result<Vec<AuditEntry>> {
+    let action = filter.action.map(|a| a.as_str());
+    let search = filter.search.as_ref().map(|s| format!("%{}%", s));
+    // a negative limit has no upper bound in sqlite
+    let limit = limit.unwrap_or(-1);
+
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+SELECT id, actor_id, actor_name, action, target_id, target_name, details, ip,
+       created_at as "created_at: OffsetDateTime"
+FROM audit_log
+WHERE ($1 IS NULL OR action = $1)
+  AND ($2 IS NULL OR actor_name LIKE $2 OR target_name LIKE $2 OR details LIKE $2 OR ip LIKE $2)
+  AND ($3 IS NULL OR created_at >= $3)
+  AND ($4 IS NULL OR created_at < $4)
+ORDER BY created_at DESC, id DESC
+LIMIT $5 OFFSET $6
+"#,
+        action,
+        search,
+        filter.since,
+        filter.until,
+        limit,
+        offset
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(entries)
+}
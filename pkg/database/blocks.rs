@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::Database;
+
+#[derive(Error, Debug)]
+pub enum BlockError {
+    #[error("you cannot block or mute yourself")]
+    Yourself,
+    #[error("{0} has blocked you")]
+    BlockedBy(String),
+}
+
+/// How the user feels about someone else, for the buttons on their card.
+#[derive(serde::Serialize, Debug)]
+pub struct Relation {
+    pub is_blocked: bool,
+    pub is_muted: bool,
+}
+
+/// Someone the user blocked or muted, for the list on their profile.
+#[derive(serde::Serialize, Debug)]
+pub struct BlockedUser {
+    pub id: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub is_blocked: bool,
+    pub is_muted: bool,
+}
+
+pub async fn set_blocked(
+    db: &Database,
+    user_id: &str,
+    other_id: &str,
+    blocked: bool,
+) -> Result<()> {
+    if user_id == other_id {
+        return Err(BlockError::Yourself.into());
+    }
+
+    if blocked {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO user_blocks (user_id, blocked_id) VALUES ($1, $2)",
+            user_id,
+            other_id
+        )
+        .execute(&db.pool)
+        .await?;
+    } else {
+        sqlx::query!(
+            "DELETE FROM user_blocks WHERE user_id = $1 AND blocked_id = $2",
+            user_id,
+            other_id
+        )
+        .execute(&db.pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn set_muted(db: &Database, user_id: &str, other_id: &str, muted: bool) -> Result<()> {
+    if user_id == other_id {
+        return Err(BlockError::Yourself.into());
+    }
+
+    if muted {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO user_mutes (user_id, muted_id) VALUES ($1, $2)",
+            user_id,
+            other_id
+        )
+        .execute(&db.pool)
+        .await?;
+    } else {
+        sqlx::query!(
+            "DELETE FROM user_mutes WHERE user_id = $1 AND muted_id = $2",
+            user_id,
+            other_id
+        )
+        .execute(&db.pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_relation(db: &Database, user_id: &str, other_id: &str) -> Result<Relation> {
+    let relation = sqlx::query_as!(
+        Relation,
+        r#"
+SELECT EXISTS (SELECT 1 FROM user_blocks WHERE user_id = $1 AND blocked_id = $2) as "is_blocked!: bool",
+       EXISTS (SELECT 1 FROM user_mutes WHERE user_id = $1 AND muted_id = $2) as "is_muted!: bool"
+"#,
+        user_id,
+        other_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(relation)
+}
+
+/// Fails with [`BlockError::BlockedBy`] when `other_id` has blocked `user_id`.
+pub async fn ensure_not_blocked_by(db: &Database, user_id: &str, other_id: &str) -> Result<()> {
+    let row = sqlx::query!(
+        r#"
+SELECT p.username
+FROM user_blocks b
+INNER JOIN user_profiles p ON p.user_id = b.user_id
+WHERE b.user_id = $1 AND b.blocked_id = $2
+"#,
+        other_id,
+        user_id
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    match row {
+        Some(row) => Err(BlockError::BlockedBy(row.username).into()),
+        None => Ok(()),
+    }
+}
+
+/// Fails when someone else in the direct message room has blocked `user_id`, other rooms always pass.
+pub async fn ensure_can_message(db: &Database, room_id: &str, user_id: &str) -> Result<()> {
+    let row = sqlx::query!(
+        r#"
+SELECT p.username
+FROM rooms r
+INNER JOIN user_rooms ur ON ur.room_id = r.id
+INNER JOIN user_blocks b ON b.user_id = ur.user_id AND b.blocked_id = $2
+INNER JOIN user_profiles p ON p.user_id = b.user_id
+WHERE r.id = $1 AND r.is_user = TRUE
+LIMIT 1
+"#,
+        room_id,
+        user_id
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    match row {
+        Some(row) => Err(BlockError::BlockedBy(row.username).into()),
+        None => Ok(()),
+    }
+}
+
+/// Ids of everyone the user muted.
+pub async fn get_muted_ids(db: &Database, user_id: &str) -> Result<HashSet<String>> {
+    let rows = sqlx::query!(
+        "SELECT muted_id FROM user_mutes WHERE user_id = $1",
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.muted_id).collect())
+}
+
+/// Everyone the user blocked or muted, by handle.
+pub async fn get_blocked_users(db: &Database, user_id: &str) -> Result<Vec<BlockedUser>> {
+    let users = sqlx::query_as!(
+        BlockedUser,
+        r#"
+SELECT p.user_id as "id!", p.username as "username!", p.display_name,
+       EXISTS (SELECT 1 FROM user_blocks b WHERE b.user_id = $1 AND b.blocked_id = p.user_id) as "is_blocked!: bool",
+       EXISTS (SELECT 1 FROM user_mutes m WHERE m.user_id = $1 AND m.muted_id = p.user_id) as "is_muted!: bool"
+FROM user_profiles p
+WHERE p.user_id IN (
+    SELECT blocked_id FROM user_blocks WHERE user_id = $1
+    UNION
+    SELECT muted_id FROM user_mutes WHERE user_id = $1
+)
+ORDER BY lower(p.username)
+"#,
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(users)
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::{users::UserCombined, Database};
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+pub struct Bot {
+    pub user_id: String,
+    pub username: String,
+    pub is_enabled: bool,
+    pub created_by: String,
+    pub created_at: OffsetDateTime,
+}
+
+/// Creates a bot user that can only authenticate with the given token hash.
+pub async fn create_bot(
+    db: &Database,
+    name: &str,
+    token_hash: &str,
+    created_by: &str,
+) -> Result<String> {
+    let mut trx = db.pool.begin().await?;
+
+    let user_id = xid::new().to_string();
+    // bots never log in with a password, the email only has to be unique
+    let email = format!("{}@bots", user_id);
+
+    sqlx::query!(
+        r#"
+INSERT INTO users (id, email, password, hash, is_admin, is_enabled, is_bot)
+VALUES ($1, $2, '', '', FALSE, TRUE, TRUE)
+"#,
+        user_id,
+        email,
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    crate::handles::claim_handle(&mut trx, &user_id, name).await?;
+
+    let profile_id = xid::new().to_string();
+    sqlx::query!(
+        r#"
+INSERT INTO user_profiles (id, user_id, username)
+VALUES ($1 ,$2, $3)
+"#,
+        profile_id,
+        user_id,
+        name,
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO bots (user_id, token_hash, created_by)
+VALUES ($1 ,$2, $3)
+"#,
+        user_id,
+        token_hash,
+        created_by,
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(user_id)
+}
+
+pub async fn set_bot_token(db: &Database, user_id: &str, token_hash: &str) -> Result<()> {
+    sqlx::query!(
+        r#"UPDATE bots SET token_hash = $1 WHERE user_id = $2"#,
+        token_hash,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_bots(db: &Database) -> Result<Vec<Bot>> {
+    sqlx::query_as!(
+        Bot,
+        r#"
+SELECT b.user_id, p.username, u.is_enabled as "is_enabled!", b.created_by, b.created_at as "created_at!"
+FROM bots AS b
+INNER JOIN users AS u ON u.id = b.user_id
+INNER JOIN user_profiles AS p ON p.user_id = b.user_id
+ORDER BY p.username
+"#
+    )
+    .fetch_all(&db.pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_bot_by_token(db: &Database, token_hash: &str) -> Result<UserCombined> {
+    sqlx::query_as!(
+        UserCombined,
+        r#"
+SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.is_bot as "is_bot!", u.is_guest as "is_guest!", u.guest_expires_at as "guest_expires_at: OffsetDateTime", u.created_at as "created_at!", p.username as username, p.display_name, p.bio, p.image, p.timezone
+FROM bots AS b
+INNER JOIN users AS u ON u.id = b.user_id
+INNER JOIN user_profiles AS p ON p.user_id = b.user_id
+WHERE b.token_hash = $1 AND u.is_enabled = TRUE
+"#,
+        token_hash
+    )
+    .fetch_one(&db.pool)
+    .await
+    .map_err(|e| e.into())
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug, Clone)]
+pub struct Command {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub url: String,
+    pub secret: String,
+    pub created_by: String,
+    pub created_at: OffsetDateTime,
+}
+
+pub async fn create_command(
+    db: &Database,
+    name: &str,
+    description: &str,
+    url: &str,
+    secret: &str,
+    created_by: &str,
+) -> Result<Command> {
+    let id = xid::new().to_string();
+
+    sqlx::query!(
+        r#"
+INSERT INTO commands (id, name, description, url, secret, created_by)
+VALUES ($1, $2, $3, $4, $5, $6)
+"#,
+        id,
+        name,
+        description,
+        url,
+        secret,
+        created_by
+    )
+    .execute(&db.pool)
+    .await?;
+
+    get_command(db, name).await
+}
+
+pub async fn get_command(db: &Database, name: &str) -> Result<Command> {
+    sqlx::query_as!(
+        Command,
+        r#"
+SELECT id, name, description, url, secret, created_by, created_at as "created_at!"
+FROM commands
+WHERE name = $1
+"#,
+        name
+    )
+    .fetch_one(&db.pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_commands(db: &Database) -> Result<Vec<Command>> {
+    sqlx::query_as!(
+        Command,
+        r#"
+SELECT id, name, description, url, secret, created_by, created_at as "created_at!"
+FROM commands
+ORDER BY name
+"#
+    )
+    .fetch_all(&db.pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn delete_command(db: &Database, name: &str) -> Result<()> {
+    sqlx::query!("DELETE FROM commands WHERE name = $1", name)
+        .execute(&db.pool)
+        .await?;
+
+    Ok(())
+}
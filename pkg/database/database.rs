@@ -4,8 +4,32 @@ use anyhow::Result;
 use rooms::init_rooms;
 use sqlx::{migrate::MigrateDatabase, sqlite::SqlitePoolOptions, Pool, Sqlite};
 
+pub mod approvals;
+pub mod audit;
+pub mod blocks;
+pub mod bots;
+pub mod commands;
+pub mod digests;
+pub mod directory;
+pub mod drafts;
+pub mod guests;
+pub mod handles;
+pub mod invites;
+pub mod login_attempts;
 pub mod messages;
+pub mod notifications;
+pub mod password_resets;
+pub mod polls;
+pub mod preferences;
+pub mod push;
+pub mod reports;
+pub mod roles;
 pub mod rooms;
+pub mod saved;
+pub mod sessions;
+pub mod settings;
+pub mod sso;
+pub mod two_factor;
 pub mod uploads;
 pub mod users;
 
@@ -32,3 +56,45 @@ impl Database {
         Ok(Database { pool })
     }
 }
+
+/// Fixtures for tests that need a real database, also used by other crates' tests
+/// through the `test-util` feature.
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing {
+    use super::*;
+
+    /// A freshly migrated database in the temp dir, one per test.
+    pub async fn database(name: &str) -> Database {
+        let path =
+            std::env::temp_dir().join(format!("speakwith-{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        Database::new(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap()
+    }
+
+    /// An enabled account with a password of its own.
+    pub async fn local_user(db: &Database, username: &str) -> String {
+        let trx = db.pool.begin().await.unwrap();
+        let (user_id, trx) = crate::users::create_user(
+            &xid::new().to_string(),
+            trx,
+            crate::users::User {
+                email: format!("{}@example.com", username),
+                password: "password".to_string(),
+                is_enabled: true,
+                ..Default::default()
+            },
+            crate::users::UserProfile {
+                username: username.to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        trx.commit().await.unwrap();
+
+        user_id
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+// a message only counts as missed once it sat unread for a while
+const GRACE_MINUTES: &str = "-30 minutes";
+// the first digest does not dig up everything that was ever sent
+const LOOKBACK: &str = "-7 days";
+
+/// How often someone wants mail about what they missed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DigestFrequency {
+    Never,
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl DigestFrequency {
+    pub const ALL: [DigestFrequency; 4] = [
+        DigestFrequency::Never,
+        DigestFrequency::Hourly,
+        DigestFrequency::Daily,
+        DigestFrequency::Weekly,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestFrequency::Never => "never",
+            DigestFrequency::Hourly => "hourly",
+            DigestFrequency::Daily => "daily",
+            DigestFrequency::Weekly => "weekly",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DigestFrequency::Never => "Never",
+            DigestFrequency::Hourly => "Every hour",
+            DigestFrequency::Daily => "Once a day",
+            DigestFrequency::Weekly => "Once a week",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<DigestFrequency> {
+        DigestFrequency::ALL
+            .into_iter()
+            .find(|f| f.as_str() == value)
+    }
+}
+
+/// Someone whose next digest is due.
+#[derive(Debug)]
+pub struct DigestRecipient {
+    pub user_id: String,
+    pub email: String,
+    pub username: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct MissedMessage {
+    pub id: String,
+    pub room_id: String,
+    pub room_name: String,
+    pub is_direct: bool,
+    pub is_mention: bool,
+    pub user_name: String,
+    pub message: String,
+    pub created_at: OffsetDateTime,
+}
+
+pub async fn get_frequency(db: &Database, user_id: &str) -> Result<DigestFrequency> {
+    let frequency = sqlx::query!(
+        "SELECT digest_frequency FROM user_profiles WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?
+    .digest_frequency;
+
+    Ok(DigestFrequency::parse(&frequency).unwrap_or(DigestFrequency::Never))
+}
+
+pub async fn set_frequency(db: &Database, user_id: &str, frequency: DigestFrequency) -> Result<()> {
+    let frequency = frequency.as_str();
+    sqlx::query!(
+        "UPDATE user_profiles SET digest_frequency = $1 WHERE user_id = $2",
+        frequency,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Enabled people with a digest frequency whose last digest is at least that long ago.
+pub async fn get_due_recipients(db: &Database) -> Result<Vec<DigestRecipient>> {
+    let recipients = sqlx::query_as!(
+        DigestRecipient,
+        r#"
+SELECT u.id as "user_id!", u.email, p.username, p.display_name
+FROM users u
+INNER JOIN user_profiles p ON p.user_id = u.id
+WHERE u.is_enabled = TRUE AND COALESCE(u.is_bot, FALSE) = FALSE
+  AND p.digest_frequency != 'never'
+  AND (p.digest_sent_at IS NULL OR p.digest_sent_at <= datetime('now',
+        CASE p.digest_frequency WHEN 'hourly' THEN '-1 hours' WHEN 'daily' THEN '-1 days' ELSE '-7 days' END))
+"#
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(recipients)
+}
+
+/// Direct messages and mentions the user has not read since their last digest, oldest first.
+/// Rooms set to nothing or muted and people the user muted or blocked are left out.
+pub async fn get_missed(db: &Database, user_id: &str, limit: i64) -> Result<Vec<MissedMessage>> {
+    let now = OffsetDateTime::now_utc();
+    let messages = sqlx::query_as!(
+        MissedMessage,
+        r#"
+SELECT m.id as "id!", m.room_id as "room_id!", r.name as "room_name!", r.is_user as "is_direct!: bool",
+       EXISTS (SELECT 1 FROM message_mentions mm WHERE mm.message_id = m.id AND mm.user_id = $1) as "is_mention!: bool",
+       p.username as "user_name!", m.message as "message!", m.created_at as "created_at!: OffsetDateTime"
+FROM messages m
+INNER JOIN rooms r ON r.id = m.room_id
+INNER JOIN user_profiles p ON p.user_id = m.user_id
+INNER JOIN user_profiles me ON me.user_id = $1
+LEFT JOIN room_reads rr ON rr.room_id = m.room_id AND rr.user_id = $1
+LEFT JOIN room_preferences rp ON rp.room_id = m.room_id AND rp.user_id = $1
+WHERE m.user_id != $1
+  AND m.created_at > COALESCE(rr.last_read_at, '')
+  -- the last digest covered up to its own grace period
+  AND m.created_at > COALESCE(datetime(me.digest_sent_at, $3), '')
+  AND m.created_at > datetime('now', $2)
+  AND m.created_at <= datetime('now', $3)
+  AND (
+      (r.is_user = TRUE AND EXISTS (SELECT 1 FROM user_rooms ur WHERE ur.room_id = r.id AND ur.user_id = $1))
+      OR EXISTS (SELECT 1 FROM message_mentions mm WHERE mm.message_id = m.id AND mm.user_id = $1)
+  )
+  AND COALESCE(rp.level, 'all') != 'nothing'
+  AND (rp.muted_until IS NULL OR rp.muted_until <= $4)
+  AND NOT EXISTS (SELECT 1 FROM user_mutes WHERE user_id = $1 AND muted_id = m.user_id)
+  AND NOT EXISTS (SELECT 1 FROM user_blocks WHERE user_id = $1 AND blocked_id = m.user_id)
+ORDER BY m.created_at, m.id
+LIMIT $5
+"#,
+        user_id,
+        LOOKBACK,
+        GRACE_MINUTES,
+        now,
+        limit
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(messages)
+}
+
+pub async fn mark_sent(db: &Database, user_id: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE user_profiles SET digest_sent_at = CURRENT_TIMESTAMP WHERE user_id = $1",
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+/// A local user whose account comes from an external directory.
+#[derive(Debug)]
+pub struct DirectoryUser {
+    pub user_id: String,
+    pub dn: String,
+}
+
+pub async fn link_directory_user(db: &Database, user_id: &str, dn: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+INSERT INTO directory_users (user_id, dn, synced_at)
+VALUES ($1, $2, $3)
+ON CONFLICT (user_id) DO UPDATE SET dn = excluded.dn, synced_at = excluded.synced_at
+"#,
+        user_id,
+        dn,
+        now
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether the account was provisioned from the directory, as opposed to a local one with the
+/// same address.
+pub async fn is_directory_user(db: &Database, user_id: &str) -> Result<bool> {
+    let row = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!: i64" FROM directory_users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(row.count > 0)
+}
+
+pub async fn get_directory_users(db: &Database) -> Result<Vec<DirectoryUser>> {
+    let users = sqlx::query_as!(
+        DirectoryUser,
+        "SELECT user_id, dn FROM directory_users ORDER BY synced_at"
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(users)
+}
+
+/// Copies what the directory knows about the user over the local profile.
+pub async fn sync_directory_user(
+    db: &Database,
+    user_id: &str,
+    username: &str,
+    bio: Option<&str>,
+    is_admin: Option<bool>,
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    // the directory name may not be a valid or free handle, it is kept for display then
+    let handle = crate::handles::available_handle(&mut trx, user_id, username).await?;
+    crate::handles::rename_handle(&mut trx, user_id, &handle).await?;
+
+    let display_name = (handle != username).then_some(username);
+    sqlx::query!(
+        "UPDATE user_profiles SET display_name = COALESCE($1, display_name), bio = COALESCE($2, bio) WHERE user_id = $3",
+        display_name,
+        bio,
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    // without an admin group the flag stays whatever the admins set in the app
+    if let Some(is_admin) = is_admin {
+        sqlx::query!(
+            "UPDATE users SET is_admin = $1 WHERE id = $2",
+            is_admin,
+            user_id
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "UPDATE directory_users SET synced_at = $1 WHERE user_id = $2",
+        now,
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+pub struct Draft {
+    pub room_id: String,
+    pub message: String,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+pub struct DraftRoom {
+    pub id: String,
+    pub name: String,
+    pub is_user: bool,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+pub struct DraftUpload {
+    pub id: String,
+    pub filename: String,
+    pub url: String,
+}
+
+/// Replaces the user's draft for a room, an empty draft without uploads is removed.
+pub async fn save_draft(
+    db: &Database,
+    user_id: &str,
+    room_id: &str,
+    message: &str,
+    uploads: &[String],
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    if message.trim().is_empty() && uploads.is_empty() {
+        sqlx::query!(
+            "DELETE FROM drafts WHERE user_id = $1 AND room_id = $2",
+            user_id,
+            room_id
+        )
+        .execute(&mut *trx)
+        .await?;
+
+        trx.commit().await?;
+        return Ok(());
+    }
+
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+INSERT INTO drafts (user_id, room_id, message, updated_at)
+VALUES ($1, $2, $3, $4)
+ON CONFLICT (user_id, room_id) DO UPDATE SET message = excluded.message, updated_at = excluded.updated_at
+"#,
+        user_id,
+        room_id,
+        message,
+        now
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM draft_uploads WHERE user_id = $1 AND room_id = $2",
+        user_id,
+        room_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    for upload in uploads {
+        insert_draft_upload(&mut trx, user_id, room_id, upload).await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+/// Remembers an upload made while composing, creating an empty draft if needed.
+pub async fn add_draft_upload(
+    db: &Database,
+    user_id: &str,
+    room_id: &str,
+    upload_id: &str,
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+INSERT OR IGNORE INTO drafts (user_id, room_id, message)
+VALUES ($1, $2, '')
+"#,
+        user_id,
+        room_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    insert_draft_upload(&mut trx, user_id, room_id, upload_id).await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+async fn insert_draft_upload<'a>(
+    trx: &mut Transaction<'a, Sqlite>,
+    user_id: &str,
+    room_id: &str,
+    upload_id: &str,
+) -> Result<()> {
+    // only uploads made by the same user can be attached to their draft
+    sqlx::query!(
+        r#"
+INSERT OR IGNORE INTO draft_uploads (user_id, room_id, upload_id)
+SELECT $1, $2, id FROM uploads WHERE id = $3 AND uploaded_by = $1
+"#,
+        user_id,
+        room_id,
+        upload_id
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_draft(db: &Database, user_id: &str, room_id: &str) -> Result<Option<Draft>> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+SELECT room_id, message, updated_at as "updated_at!"
+FROM drafts
+WHERE user_id = $1 AND room_id = $2
+"#,
+        user_id,
+        room_id
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(draft)
+}
+
+pub async fn get_draft_uploads(
+    db: &Database,
+    user_id: &str,
+    room_id: &str,
+) -> Result<Vec<DraftUpload>> {
+    let uploads = sqlx::query_as!(
+        DraftUpload,
+        r#"
+SELECT u.id, u.filename, u.url
+FROM draft_uploads d
+INNER JOIN uploads u ON u.id = d.upload_id
+WHERE d.user_id = $1 AND d.room_id = $2
+"#,
+        user_id,
+        room_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(uploads)
+}
+
+/// Rooms the user has an unsent draft in and can still access.
+pub async fn get_draft_rooms(db: &Database, user_id: &str) -> Result<Vec<DraftRoom>> {
+    let rooms = sqlx::query_as!(
+        DraftRoom,
+        r#"
+SELECT r.id, r.name, r.is_user as "is_user!"
+FROM drafts d
+INNER JOIN rooms r ON r.id = d.room_id
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $1
+WHERE d.user_id = $1
+ORDER BY d.updated_at DESC
+"#,
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(rooms)
+}
+
+pub async fn delete_draft(db: &Database, user_id: &str, room_id: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM drafts WHERE user_id = $1 AND room_id = $2",
+        user_id,
+        room_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+/// Sets when the guest account is deactivated, never when empty. Members are left alone.
+pub async fn set_guest_expiry(
+    db: &Database,
+    user_id: &str,
+    expires_at: Option<OffsetDateTime>,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE users SET guest_expires_at = $1 WHERE id = $2 AND is_guest = TRUE",
+        expires_at,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Turns a guest into a full member who sees public rooms again.
+pub async fn convert_to_member(db: &Database, user_id: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE users SET is_guest = FALSE, guest_expires_at = NULL WHERE id = $1",
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Deactivates the guests whose access ran out, returns their ids.
+pub async fn deactivate_expired_guests(db: &Database) -> Result<Vec<String>> {
+    let now = OffsetDateTime::now_utc();
+    let rows = sqlx::query!(
+        r#"
+SELECT id
+FROM users
+WHERE is_guest = TRUE AND is_enabled = TRUE AND guest_expires_at <= $1
+"#,
+        now
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let mut deactivated = Vec::with_capacity(rows.len());
+    for row in rows {
+        crate::users::deactivate_user(db, &row.id).await?;
+        deactivated.push(row.id);
+    }
+
+    Ok(deactivated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        roles::{get_capabilities, Capability},
+        rooms::{create_room, get_room, get_rooms},
+        testing::{database, local_user},
+    };
+
+    async fn room_ids(db: &Database, user_id: &str) -> Vec<String> {
+        let (_, rooms) = get_rooms(db, user_id).await.unwrap();
+        let mut ids: Vec<String> = rooms.into_iter().map(|r| r.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn guests_only_reach_their_rooms() {
+        let db = database("guests").await;
+        let ada = local_user(&db, "ada").await;
+        let eve = local_user(&db, "eve").await;
+        sqlx::query!("UPDATE users SET is_guest = TRUE WHERE id = $1", eve)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        create_room(&db, "lobby", "lobby", "", false, false, &[])
+            .await
+            .unwrap();
+        create_room(
+            &db,
+            "project",
+            "project",
+            "",
+            true,
+            false,
+            &[ada.clone(), eve.clone()],
+        )
+        .await
+        .unwrap();
+        create_room(
+            &db,
+            "secret",
+            "secret",
+            "",
+            true,
+            false,
+            std::slice::from_ref(&ada),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(room_ids(&db, &eve).await, ["project"]);
+        assert!(get_room(&db, "lobby", &eve).await.is_err());
+        assert!(get_room(&db, "secret", &eve).await.is_err());
+        assert!(get_capabilities(&db, &eve).await.unwrap().is_empty());
+
+        let recipients = crate::preferences::get_recipients(&db, "general", &ada)
+            .await
+            .unwrap();
+        assert!(recipients.iter().all(|r| r.user_id != eve));
+        let recipients = crate::preferences::get_recipients(&db, "project", &ada)
+            .await
+            .unwrap();
+        assert!(recipients.iter().any(|r| r.user_id == eve));
+
+        // members see every public room on top of their own
+        assert_eq!(
+            room_ids(&db, &ada).await,
+            ["general", "lobby", "project", "secret"]
+        );
+
+        convert_to_member(&db, &eve).await.unwrap();
+        assert_eq!(room_ids(&db, &eve).await, ["general", "lobby", "project"]);
+        assert_eq!(
+            get_capabilities(&db, &eve).await.unwrap(),
+            [Capability::CreatePublicRooms]
+        );
+    }
+}
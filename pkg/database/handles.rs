@@ -0,0 +1,224 @@
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+pub const MIN_HANDLE_LENGTH: usize = 2;
+pub const MAX_HANDLE_LENGTH: usize = 32;
+
+// would be confused with staff or with mentions that notify a whole room
+pub const RESERVED_HANDLES: &[&str] = &[
+    "admin",
+    "administrator",
+    "root",
+    "system",
+    "deleted",
+    "here",
+    "channel",
+    "everyone",
+    "all",
+];
+
+// mentions that reach everyone in the room rather than one person
+pub const ROOM_MENTIONS: &[&str] = &["here", "channel", "everyone", "all"];
+
+#[derive(Error, Debug)]
+pub enum HandleError {
+    #[error("handles are 2 to 32 letters, digits, dots, dashes or underscores and start with a letter or digit")]
+    Invalid,
+    #[error("{0} is reserved")]
+    Reserved(String),
+    #[error("{0} is already taken")]
+    Taken(String),
+}
+
+pub fn validate_handle(handle: &str) -> Result<(), HandleError> {
+    let valid = (MIN_HANDLE_LENGTH..=MAX_HANDLE_LENGTH).contains(&handle.len())
+        && handle.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && handle.chars().all(is_handle_char);
+
+    if !valid {
+        return Err(HandleError::Invalid);
+    }
+
+    if RESERVED_HANDLES.contains(&handle.to_lowercase().as_str()) {
+        return Err(HandleError::Reserved(handle.to_string()));
+    }
+
+    Ok(())
+}
+
+fn is_handle_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
+/// Handles written as @handle in a message, without the @.
+pub fn mentioned_handles(message: &str) -> Vec<&str> {
+    let mut handles = vec![];
+    for (i, _) in message.match_indices('@') {
+        // part of an email address
+        if message[..i].ends_with(|c: char| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+
+        let rest = &message[i + 1..];
+        let end = rest.find(|c| !is_handle_char(c)).unwrap_or(rest.len());
+        // punctuation after a handle ends the sentence
+        let handle = rest[..end].trim_end_matches(['.', '_', '-']);
+        if !handle.is_empty() {
+            handles.push(handle);
+        }
+    }
+
+    handles
+}
+
+async fn is_handle_free<'a>(
+    trx: &mut Transaction<'a, Sqlite>,
+    user_id: &str,
+    handle: &str,
+) -> Result<bool> {
+    // old handles stay with their owner so earlier mentions keep pointing at the same person
+    let row = sqlx::query!(
+        r#"
+SELECT NOT EXISTS (
+    SELECT 1 FROM user_profiles WHERE lower(username) = lower($1) AND user_id != $2
+    UNION ALL
+    SELECT 1 FROM user_handle_history WHERE lower(handle) = lower($1) AND user_id != $2
+) as "free!: bool"
+"#,
+        handle,
+        user_id
+    )
+    .fetch_one(&mut **trx)
+    .await?;
+
+    Ok(row.free)
+}
+
+/// Checks that `user_id` may use the handle, taking back one of their own old handles.
+pub async fn claim_handle<'a>(
+    trx: &mut Transaction<'a, Sqlite>,
+    user_id: &str,
+    handle: &str,
+) -> Result<()> {
+    validate_handle(handle)?;
+
+    if !is_handle_free(trx, user_id, handle).await? {
+        return Err(HandleError::Taken(handle.to_string()).into());
+    }
+
+    sqlx::query!(
+        "DELETE FROM user_handle_history WHERE lower(handle) = lower($1) AND user_id = $2",
+        handle,
+        user_id
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    Ok(())
+}
+
+/// Changes the handle, remembering the old one and renaming direct message rooms that showed it.
+pub async fn rename_handle<'a>(
+    trx: &mut Transaction<'a, Sqlite>,
+    user_id: &str,
+    handle: &str,
+) -> Result<()> {
+    let current = sqlx::query!(
+        "SELECT username FROM user_profiles WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(&mut **trx)
+    .await?
+    .username;
+
+    if current == handle {
+        return Ok(());
+    }
+
+    claim_handle(trx, user_id, handle).await?;
+
+    // only the casing changed, the old spelling still resolves to the same handle
+    if !current.eq_ignore_ascii_case(handle) {
+        let now = OffsetDateTime::now_utc();
+        sqlx::query!(
+            "INSERT INTO user_handle_history (handle, user_id, retired_at) VALUES ($1, $2, $3)",
+            current,
+            user_id,
+            now
+        )
+        .execute(&mut **trx)
+        .await?;
+    }
+
+    sqlx::query!(
+        "UPDATE user_profiles SET username = $1 WHERE user_id = $2",
+        handle,
+        user_id
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    // direct message rooms are named after their members when created
+    let rooms = sqlx::query!(
+        r#"
+SELECT r.id, r.name
+FROM rooms r
+INNER JOIN user_rooms ur ON ur.room_id = r.id
+WHERE r.is_user = TRUE AND ur.user_id = $1
+"#,
+        user_id
+    )
+    .fetch_all(&mut **trx)
+    .await?;
+
+    for room in rooms {
+        let name = room
+            .name
+            .split(", ")
+            .map(|name| if name == current { handle } else { name })
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        if name != room.name {
+            sqlx::query!("UPDATE rooms SET name = $1 WHERE id = $2", name, room.id)
+                .execute(&mut **trx)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A free handle as close to `name` as possible, for accounts named by an identity provider.
+pub async fn available_handle<'a>(
+    trx: &mut Transaction<'a, Sqlite>,
+    user_id: &str,
+    name: &str,
+) -> Result<String> {
+    let mut base = name
+        .trim()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') => Some(c),
+            c if c.is_whitespace() => Some('.'),
+            _ => None,
+        })
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(MAX_HANDLE_LENGTH - 4)
+        .collect::<String>();
+
+    if base.len() < MIN_HANDLE_LENGTH {
+        base = "user".to_string();
+    }
+
+    let mut handle = base.clone();
+    let mut n = 1;
+    while validate_handle(&handle).is_err() || !is_handle_free(trx, user_id, &handle).await? {
+        n += 1;
+        handle = format!("{}-{}", base, n);
+    }
+
+    Ok(handle)
+}
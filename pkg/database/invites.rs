@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(Error, Debug)]
+pub enum InviteError {
+    #[error("invite is invalid, used up or expired")]
+    InvalidInvite,
+}
+
+pub struct NewInvite {
+    // only this address may register with the invite
+    pub email: Option<String>,
+    // unlimited when empty
+    pub max_uses: Option<i64>,
+    pub expires_at: Option<OffsetDateTime>,
+    pub is_admin: bool,
+    // accounts start enabled instead of waiting for an admin
+    pub auto_approve: bool,
+    // guests only see the invite's rooms
+    pub is_guest: bool,
+    // guest accounts are deactivated this many days after registering, never when empty
+    pub guest_days: Option<i64>,
+    pub rooms: Vec<String>,
+}
+
+/// What registering with an invite grants.
+#[derive(serde::Serialize, Debug)]
+pub struct InviteGrant {
+    pub id: String,
+    pub email: Option<String>,
+    pub is_admin: bool,
+    pub auto_approve: bool,
+    pub is_guest: bool,
+    pub guest_days: Option<i64>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Invite {
+    pub id: String,
+    pub email: Option<String>,
+    pub max_uses: Option<i64>,
+    pub uses: i64,
+    pub expires_at: Option<OffsetDateTime>,
+    pub is_admin: bool,
+    pub auto_approve: bool,
+    pub is_guest: bool,
+    pub guest_days: Option<i64>,
+    pub created_by: Option<String>,
+    pub revoked_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+    pub is_usable: bool,
+    pub rooms: Vec<String>,
+    pub used_by: Vec<String>,
+}
+
+pub async fn create_invite(
+    db: &Database,
+    token_hash: &str,
+    invite: &NewInvite,
+    created_by: &str,
+) -> Result<String> {
+    let mut trx = db.pool.begin().await?;
+
+    let id = xid::new().to_string();
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        r#"
+INSERT INTO invites (id, token_hash, email, max_uses, expires_at, is_admin, auto_approve, is_guest, guest_days, created_by, created_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+"#,
+        id,
+        token_hash,
+        invite.email,
+        invite.max_uses,
+        invite.expires_at,
+        invite.is_admin,
+        invite.auto_approve,
+        invite.is_guest,
+        invite.guest_days,
+        created_by,
+        now
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    for room_id in &invite.rooms {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO invite_rooms (invite_id, room_id) VALUES ($1, $2)",
+            id,
+            room_id
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(id)
+}
+
+/// The invite behind a token, as long as it can still be used.
+pub async fn get_usable_invite(db: &Database, token_hash: &str) -> Result<Option<InviteGrant>> {
+    let now = OffsetDateTime::now_utc();
+    let invite = sqlx::query_as!(
+        InviteGrant,
+        r#"
+SELECT id, email, is_admin, auto_approve, is_guest, guest_days
+FROM invites
+WHERE token_hash = $1
+  AND revoked_at IS NULL
+  AND (expires_at IS NULL OR expires_at > $2)
+  AND (max_uses IS NULL OR uses < max_uses)
+"#,
+        token_hash,
+        now
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(invite)
+}
+
+/// Takes one use of the invite for `email`, rolled back along with the transaction.
+pub async fn redeem_invite<'a>(
+    trx: &mut Transaction<'a, Sqlite>,
+    token_hash: &str,
+    email: &str,
+) -> Result<InviteGrant> {
+    let now = OffsetDateTime::now_utc();
+
+    // checked and counted in one statement so two registrations cannot share the last use
+    let result = sqlx::query!(
+        r#"
+UPDATE invites
+SET uses = uses + 1
+WHERE token_hash = $1
+  AND revoked_at IS NULL
+  AND (expires_at IS NULL OR expires_at > $2)
+  AND (max_uses IS NULL OR uses < max_uses)
+  AND (email IS NULL OR lower(email) = lower($3))
+"#,
+        token_hash,
+        now,
+        email
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(InviteError::InvalidInvite.into());
+    }
+
+    let invite = sqlx::query_as!(
+        InviteGrant,
+        "SELECT id, email, is_admin, auto_approve, is_guest, guest_days FROM invites WHERE token_hash = $1",
+        token_hash
+    )
+    .fetch_one(&mut **trx)
+    .await?;
+
+    Ok(invite)
+}
+
+/// Records who used the invite and adds them to its rooms.
+pub async fn record_invite_use<'a>(
+    trx: &mut Transaction<'a, Sqlite>,
+    invite_id: &str,
+    user_id: &str,
+) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "INSERT INTO invite_uses (invite_id, user_id, used_at) VALUES ($1, $2, $3)",
+        invite_id,
+        user_id,
+        now
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT OR IGNORE INTO user_rooms (user_id, room_id)
+SELECT $2, ir.room_id
+FROM invite_rooms ir
+INNER JOIN rooms r ON r.id = ir.room_id
+WHERE ir.invite_id = $1 AND r.is_user = FALSE
+"#,
+        invite_id,
+        user_id
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn revoke_invite(db: &Database, id: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "UPDATE invites SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL",
+        now,
+        id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Every invite with its rooms and who registered with it, newest first.
+pub async fn get_invites(db: &Database) -> Result<Vec<Invite>> {
+    let now = OffsetDateTime::now_utc();
+    let rows = sqlx::query!(
+        r#"
+SELECT i.id as "id!", i.email, i.max_uses, i.uses, i.expires_at as "expires_at: OffsetDateTime",
+       i.is_admin, i.auto_approve, i.is_guest, i.guest_days, p.username as "created_by?", i.revoked_at as "revoked_at: OffsetDateTime",
+       i.created_at as "created_at: OffsetDateTime"
+FROM invites i
+LEFT JOIN user_profiles p ON p.user_id = i.created_by
+ORDER BY i.created_at DESC
+"#
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let mut rooms: HashMap<String, Vec<String>> = HashMap::new();
+    for row in sqlx::query!(
+        r#"
+SELECT ir.invite_id, r.name
+FROM invite_rooms ir
+INNER JOIN rooms r ON r.id = ir.room_id
+ORDER BY r.name
+"#
+    )
+    .fetch_all(&db.pool)
+    .await?
+    {
+        rooms.entry(row.invite_id).or_default().push(row.name);
+    }
+
+    let mut used_by: HashMap<String, Vec<String>> = HashMap::new();
+    for row in sqlx::query!(
+        r#"
+SELECT iu.invite_id, p.username
+FROM invite_uses iu
+INNER JOIN user_profiles p ON p.user_id = iu.user_id
+ORDER BY iu.used_at
+"#
+    )
+    .fetch_all(&db.pool)
+    .await?
+    {
+        used_by.entry(row.invite_id).or_default().push(row.username);
+    }
+
+    let invites = rows
+        .into_iter()
+        .map(|row| Invite {
+            is_usable: row.revoked_at.is_none()
+                && row.expires_at.is_none_or(|at| at > now)
+                && row.max_uses.is_none_or(|max| row.uses < max),
+            rooms: rooms.remove(&row.id).unwrap_or_default(),
+            used_by: used_by.remove(&row.id).unwrap_or_default(),
+            id: row.id,
+            email: row.email,
+            max_uses: row.max_uses,
+            uses: row.uses,
+            expires_at: row.expires_at,
+            is_admin: row.is_admin,
+            auto_approve: row.auto_approve,
+            is_guest: row.is_guest,
+            guest_days: row.guest_days,
+            created_by: row.created_by,
+            revoked_at: row.revoked_at,
+            created_at: row.created_at,
+        })
+        .collect();
+
+    Ok(invites)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+    use crate::testing::{database, local_user};
+
+    fn invite(max_uses: Option<i64>) -> NewInvite {
+        NewInvite {
+            email: None,
+            max_uses,
+            expires_at: None,
+            is_admin: false,
+            auto_approve: false,
+            is_guest: false,
+            guest_days: None,
+            rooms: vec![],
+        }
+    }
+
+    async fn redeem(db: &Database, token_hash: &str, email: &str) -> Result<InviteGrant> {
+        let mut trx = db.pool.begin().await?;
+        let grant = redeem_invite(&mut trx, token_hash, email).await?;
+        trx.commit().await?;
+
+        Ok(grant)
+    }
+
+    fn invalid(result: Result<InviteGrant>) -> bool {
+        matches!(
+            result.map_err(|e| e.downcast::<InviteError>()),
+            Err(Ok(InviteError::InvalidInvite))
+        )
+    }
+
+    #[tokio::test]
+    async fn enforces_invite_limits() {
+        let db = database("invites").await;
+        let boss = local_user(&db, "boss").await;
+
+        create_invite(&db, "twice", &invite(Some(2)), &boss)
+            .await
+            .unwrap();
+        redeem(&db, "twice", "a@example.com").await.unwrap();
+        redeem(&db, "twice", "b@example.com").await.unwrap();
+        assert!(invalid(redeem(&db, "twice", "c@example.com").await));
+        assert!(get_usable_invite(&db, "twice").await.unwrap().is_none());
+
+        // a failed registration gives its use back
+        let mut trx = db.pool.begin().await.unwrap();
+        create_invite(&db, "once", &invite(Some(1)), &boss)
+            .await
+            .unwrap();
+        redeem_invite(&mut trx, "once", "a@example.com")
+            .await
+            .unwrap();
+        trx.rollback().await.unwrap();
+        redeem(&db, "once", "b@example.com").await.unwrap();
+
+        let bound = NewInvite {
+            email: Some("Ada@Example.com".to_string()),
+            ..invite(None)
+        };
+        create_invite(&db, "ada", &bound, &boss).await.unwrap();
+        assert!(invalid(redeem(&db, "ada", "eve@example.com").await));
+        redeem(&db, "ada", "ada@example.com").await.unwrap();
+
+        let expired = NewInvite {
+            expires_at: Some(OffsetDateTime::now_utc() - Duration::minutes(1)),
+            ..invite(None)
+        };
+        create_invite(&db, "expired", &expired, &boss)
+            .await
+            .unwrap();
+        assert!(invalid(redeem(&db, "expired", "a@example.com").await));
+
+        let id = create_invite(&db, "revoked", &invite(None), &boss)
+            .await
+            .unwrap();
+        revoke_invite(&db, &id).await.unwrap();
+        assert!(invalid(redeem(&db, "revoked", "a@example.com").await));
+        assert!(get_usable_invite(&db, "revoked").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn never_adds_to_conversations() {
+        let db = database("invite-rooms").await;
+        let boss = local_user(&db, "boss").await;
+        let bob = local_user(&db, "bob").await;
+        let eve = local_user(&db, "eve").await;
+
+        crate::rooms::create_room(
+            &db,
+            "team",
+            "team",
+            "",
+            true,
+            false,
+            std::slice::from_ref(&boss),
+        )
+        .await
+        .unwrap();
+        crate::rooms::create_room(&db, "dm", "boss, bob", "", true, true, &[boss.clone(), bob])
+            .await
+            .unwrap();
+
+        let with_rooms = NewInvite {
+            rooms: vec!["team".to_string(), "dm".to_string()],
+            ..invite(None)
+        };
+        let id = create_invite(&db, "rooms", &with_rooms, &boss)
+            .await
+            .unwrap();
+
+        let mut trx = db.pool.begin().await.unwrap();
+        record_invite_use(&mut trx, &id, &eve).await.unwrap();
+        trx.commit().await.unwrap();
+
+        assert!(crate::rooms::is_member_of_room(&db, "team", &eve).await);
+        assert!(!crate::rooms::is_member_of_room(&db, "dm", &eve).await);
+    }
+}
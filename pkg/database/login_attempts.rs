@@ -0,0 +1,143 @@
+use anyhow::Result;
+use time::{Duration, OffsetDateTime};
+
+use crate::Database;
+
+// how long the attempts are kept around for auditing
+const RETENTION: Duration = Duration::days(90);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginOutcome {
+    Success,
+    InvalidCredentials,
+    InvalidSecondFactor,
+    NotEnabled,
+    // refused without looking at the password
+    Locked,
+}
+
+impl LoginOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginOutcome::Success => "success",
+            LoginOutcome::InvalidCredentials => "invalid_credentials",
+            LoginOutcome::InvalidSecondFactor => "invalid_second_factor",
+            LoginOutcome::NotEnabled => "not_enabled",
+            LoginOutcome::Locked => "locked",
+        }
+    }
+}
+
+/// Failed attempts that count towards a lockout.
+#[derive(Debug, Default)]
+pub struct Failures {
+    pub count: i64,
+    pub last_at: Option<OffsetDateTime>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct LoginAttempt {
+    pub email: String,
+    pub ip: String,
+    pub outcome: String,
+    pub created_at: OffsetDateTime,
+}
+
+pub async fn record_login_attempt(
+    db: &Database,
+    email: &str,
+    ip: &str,
+    outcome: LoginOutcome,
+) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let outcome = outcome.as_str();
+    sqlx::query!(
+        r#"
+INSERT INTO login_attempts (email, ip, outcome, created_at)
+VALUES ($1, $2, $3, $4)
+"#,
+        email,
+        ip,
+        outcome,
+        now
+    )
+    .execute(&db.pool)
+    .await?;
+
+    let expired = now - RETENTION;
+    sqlx::query!("DELETE FROM login_attempts WHERE created_at < $1", expired)
+        .execute(&db.pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Failures for the account since `since` or its last successful sign in, whichever is later.
+pub async fn account_failures(
+    db: &Database,
+    email: &str,
+    since: OffsetDateTime,
+) -> Result<Failures> {
+    let row = sqlx::query!(
+        r#"
+SELECT COUNT(*) as "count!: i64", MAX(created_at) as "last_at: OffsetDateTime"
+FROM login_attempts
+WHERE email = $1
+  AND outcome IN ('invalid_credentials', 'invalid_second_factor')
+  AND created_at > $2
+  AND created_at > COALESCE(
+      (SELECT MAX(created_at) FROM login_attempts WHERE email = $1 AND outcome = 'success'),
+      $2
+  )
+"#,
+        email,
+        since
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(Failures {
+        count: row.count,
+        last_at: row.last_at,
+    })
+}
+
+/// Failures from the address since `since`, a success does not reset these.
+pub async fn ip_failures(db: &Database, ip: &str, since: OffsetDateTime) -> Result<Failures> {
+    let row = sqlx::query!(
+        r#"
+SELECT COUNT(*) as "count!: i64", MAX(created_at) as "last_at: OffsetDateTime"
+FROM login_attempts
+WHERE ip = $1
+  AND outcome IN ('invalid_credentials', 'invalid_second_factor')
+  AND created_at > $2
+"#,
+        ip,
+        since
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(Failures {
+        count: row.count,
+        last_at: row.last_at,
+    })
+}
+
+pub async fn get_failed_attempts(db: &Database, limit: i64) -> Result<Vec<LoginAttempt>> {
+    let attempts = sqlx::query_as!(
+        LoginAttempt,
+        r#"
+SELECT email, ip, outcome, created_at as "created_at: OffsetDateTime"
+FROM login_attempts
+WHERE outcome != 'success'
+ORDER BY created_at DESC
+LIMIT $1
+"#,
+        limit
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(attempts)
+}
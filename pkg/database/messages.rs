@@ -5,18 +5,29 @@ use crate::Database;
 
 pub const MAX_FETCH: i32 = 5;
 
+pub const TEXT_MESSAGE: &str = "text";
+pub const POLL_MESSAGE: &str = "poll";
+
 #[derive(Clone, Debug, serde::Serialize, sqlx::FromRow)]
 pub struct ChatMessage {
     pub id: String,
     pub room_id: String,
     pub user_id: String,
     pub user_name: String,
+    pub user_display_name: Option<String>,
     pub user_image: Option<String>,
+    pub user_is_bot: bool,
+    pub user_is_guest: bool,
     pub created_at: OffsetDateTime,
 
+    pub kind: String,
     pub message: String,
 
     pub uploads: Option<String>,
+
+    pub is_saved: bool,
+    // sent by someone the reader muted
+    pub is_muted: bool,
 }
 
 pub async fn get_messages_for_room(
@@ -29,15 +40,16 @@ pub async fn get_messages_for_room(
     let messages = sqlx::query_as!(
         ChatMessage,
         r#"
-SELECT m.id as "id!", m.room_id as "room_id!", m.user_id as "user_id!", m.created_at as "created_at!", m.message as "message!", user_profiles.username as "user_name!", user_profiles.image as "user_image!", GROUP_CONCAT(up.upload_path, '||') as "uploads"
+SELECT m.id as "id!", m.room_id as "room_id!", m.user_id as "user_id!", m.created_at as "created_at!", m.kind as "kind!", m.message as "message!", user_profiles.username as "user_name!", user_profiles.display_name as user_display_name, user_profiles.image as "user_image!", u.is_bot as "user_is_bot!", u.is_guest as "user_is_guest!", GROUP_CONCAT(up.upload_path, '||') as "uploads: String", EXISTS(SELECT 1 FROM saved_messages s WHERE s.message_id = m.id AND s.user_id = $4) as "is_saved!: bool", EXISTS(SELECT 1 FROM user_mutes mu WHERE mu.user_id = $4 AND mu.muted_id = m.user_id) as "is_muted!: bool"
 FROM messages m
 INNER JOIN user_profiles ON user_profiles.user_id = m.user_id
+INNER JOIN users u ON u.id = m.user_id
 LEFT JOIN message_uploads up ON up.message_id = m.id
 JOIN (
     SELECT r.id
     FROM rooms r
-    LEFT JOIN user_rooms ur ON r.id = ur.room_id AND ur.user_id = $4
-    WHERE r.id = $1 AND (r.is_private = FALSE OR ur.user_id IS NOT NULL)
+    INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $4
+    WHERE r.id = $1
 ) AS accessible_rooms ON m.room_id = accessible_rooms.id
 WHERE m.room_id = $1
 GROUP BY m.id
@@ -68,8 +80,8 @@ pub async fn send_message(
         r#"
 SELECT r.id
 FROM rooms r
-LEFT JOIN user_rooms ur ON r.id = ur.room_id AND ur.user_id = $2
-WHERE r.id = $1 AND (r.is_private = FALSE OR ur.user_id IS NOT NULL)
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $2
+WHERE r.id = $1
 "#,
         room_id,
         user_id
@@ -123,3 +135,23 @@ VALUES ($1, $2, $3, $4)
 
     Ok(id)
 }
+
+/// Removes a message with its attachments, saved copies and poll go with it.
+pub async fn delete_message(db: &Database, message_id: &str) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!(
+        "DELETE FROM message_uploads WHERE message_id = $1",
+        message_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!("DELETE FROM messages WHERE id = $1", message_id)
+        .execute(&mut *trx)
+        .await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS command_name_index;
+DROP TABLE IF EXISTS commands;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS commands (
+       id TEXT NOT NULL PRIMARY KEY,
+       name TEXT NOT NULL UNIQUE,
+       description TEXT NOT NULL,
+       url TEXT NOT NULL,
+       secret TEXT NOT NULL,
+       created_by TEXT NOT NULL REFERENCES users(id),
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS command_name_index ON commands(name);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS bot_token_index;
+DROP TABLE IF EXISTS bots;
+ALTER TABLE users DROP COLUMN is_bot;
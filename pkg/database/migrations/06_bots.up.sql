@@ -0,0 +1,10 @@
+ALTER TABLE users ADD COLUMN is_bot BOOLEAN DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS bots (
+       user_id TEXT NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+       token_hash TEXT NOT NULL UNIQUE,
+       created_by TEXT NOT NULL REFERENCES users(id),
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS bot_token_index ON bots(token_hash);
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS poll_vote_index;
+DROP TABLE IF EXISTS poll_votes;
+DROP INDEX IF EXISTS poll_option_index;
+DROP TABLE IF EXISTS poll_options;
+DROP TABLE IF EXISTS polls;
+ALTER TABLE messages DROP COLUMN kind;
@@ -0,0 +1,28 @@
+ALTER TABLE messages ADD COLUMN kind TEXT NOT NULL DEFAULT 'text';
+
+CREATE TABLE IF NOT EXISTS polls (
+       message_id TEXT NOT NULL PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+       is_multi BOOLEAN DEFAULT FALSE,
+       is_anonymous BOOLEAN DEFAULT FALSE,
+       closes_at DATETIME,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS poll_options (
+       id TEXT NOT NULL PRIMARY KEY,
+       poll_id TEXT NOT NULL REFERENCES polls(message_id) ON DELETE CASCADE,
+       label TEXT NOT NULL,
+       position INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS poll_option_index ON poll_options(poll_id);
+
+CREATE TABLE IF NOT EXISTS poll_votes (
+       poll_id TEXT NOT NULL REFERENCES polls(message_id) ON DELETE CASCADE,
+       option_id TEXT NOT NULL REFERENCES poll_options(id) ON DELETE CASCADE,
+       user_id TEXT NOT NULL REFERENCES users(id),
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+       PRIMARY KEY (option_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS poll_vote_index ON poll_votes(poll_id);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS draft_uploads;
+DROP TABLE IF EXISTS drafts;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS drafts (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       room_id TEXT NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+       message TEXT NOT NULL,
+       updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+       PRIMARY KEY (user_id, room_id)
+);
+
+CREATE TABLE IF NOT EXISTS draft_uploads (
+       user_id TEXT NOT NULL,
+       room_id TEXT NOT NULL,
+       upload_id TEXT NOT NULL REFERENCES uploads(id) ON DELETE CASCADE,
+       PRIMARY KEY (user_id, room_id, upload_id),
+       FOREIGN KEY (user_id, room_id) REFERENCES drafts(user_id, room_id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS saved_message_remind_index;
+DROP TABLE IF EXISTS saved_messages;
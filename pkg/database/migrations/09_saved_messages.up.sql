@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS saved_messages (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       message_id TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+       remind_at DATETIME,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+       PRIMARY KEY (user_id, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS saved_message_remind_index ON saved_messages(user_id, remind_at);
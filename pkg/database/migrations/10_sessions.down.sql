@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS session_user_index;
+DROP TABLE IF EXISTS sessions;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS sessions (
+       id TEXT NOT NULL PRIMARY KEY,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       expires_at DATETIME NOT NULL,
+       last_seen_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS session_user_index ON sessions(user_id);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS password_reset_user_index;
+DROP TABLE IF EXISTS password_resets;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS password_resets (
+       token_hash TEXT NOT NULL PRIMARY KEY,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       expires_at DATETIME NOT NULL,
+       created_by TEXT REFERENCES users(id) ON DELETE SET NULL,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS password_reset_user_index ON password_resets(user_id);
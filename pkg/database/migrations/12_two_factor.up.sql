@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS settings (
+       key TEXT NOT NULL PRIMARY KEY,
+       value TEXT NOT NULL,
+       updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS user_totp (
+       user_id TEXT NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+       secret TEXT NOT NULL,
+       is_enabled BOOLEAN DEFAULT FALSE,
+       last_step INTEGER,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS user_recovery_codes (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       code_hash TEXT NOT NULL,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+       PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS directory_users (
+       user_id TEXT NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+       dn TEXT NOT NULL,
+       synced_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
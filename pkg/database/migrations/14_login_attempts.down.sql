@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS login_attempt_ip_index;
+DROP INDEX IF EXISTS login_attempt_email_index;
+DROP TABLE IF EXISTS login_attempts;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS login_attempts (
+       id INTEGER PRIMARY KEY AUTOINCREMENT,
+       email TEXT NOT NULL,
+       ip TEXT NOT NULL,
+       outcome TEXT NOT NULL,
+       created_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS login_attempt_email_index ON login_attempts(email, created_at);
+CREATE INDEX IF NOT EXISTS login_attempt_ip_index ON login_attempts(ip, created_at);
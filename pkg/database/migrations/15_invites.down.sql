@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS invite_uses;
+DROP TABLE IF EXISTS invite_rooms;
+DROP TABLE IF EXISTS invites;
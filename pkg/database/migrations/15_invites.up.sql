@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS invites (
+       id TEXT NOT NULL PRIMARY KEY,
+       token_hash TEXT NOT NULL UNIQUE,
+       email TEXT,
+       max_uses INTEGER,
+       uses INTEGER NOT NULL DEFAULT 0,
+       expires_at DATETIME,
+       is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+       auto_approve BOOLEAN NOT NULL DEFAULT FALSE,
+       created_by TEXT REFERENCES users(id) ON DELETE SET NULL,
+       revoked_at DATETIME,
+       created_at DATETIME NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS invite_rooms (
+       invite_id TEXT NOT NULL REFERENCES invites(id) ON DELETE CASCADE,
+       room_id TEXT NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+       PRIMARY KEY (invite_id, room_id)
+);
+
+CREATE TABLE IF NOT EXISTS invite_uses (
+       invite_id TEXT NOT NULL REFERENCES invites(id) ON DELETE CASCADE,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       used_at DATETIME NOT NULL,
+       PRIMARY KEY (invite_id, user_id)
+);
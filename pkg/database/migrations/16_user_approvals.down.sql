@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS user_approval_status_index;
+DROP TABLE IF EXISTS user_approvals;
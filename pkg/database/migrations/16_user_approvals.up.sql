@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS user_approvals (
+       user_id TEXT NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+       status TEXT NOT NULL,
+       reason TEXT,
+       decided_by TEXT REFERENCES users(id) ON DELETE SET NULL,
+       decided_at DATETIME,
+       created_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS user_approval_status_index ON user_approvals(status);
+
+-- before the queue existed a disabled account was one still waiting for an admin
+INSERT INTO user_approvals (user_id, status, created_at)
+SELECT id, CASE WHEN is_enabled THEN 'approved' ELSE 'pending' END, COALESCE(created_at, CURRENT_TIMESTAMP)
+FROM users
+WHERE COALESCE(is_bot, FALSE) = FALSE;
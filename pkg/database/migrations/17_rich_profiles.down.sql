@@ -0,0 +1,7 @@
+ALTER TABLE user_profiles DROP COLUMN status_expires_at;
+ALTER TABLE user_profiles DROP COLUMN status_text;
+ALTER TABLE user_profiles DROP COLUMN status_emoji;
+ALTER TABLE user_profiles DROP COLUMN pronouns;
+ALTER TABLE user_profiles DROP COLUMN timezone;
+ALTER TABLE user_profiles DROP COLUMN title;
+ALTER TABLE user_profiles DROP COLUMN display_name;
@@ -0,0 +1,7 @@
+ALTER TABLE user_profiles ADD COLUMN display_name TEXT;
+ALTER TABLE user_profiles ADD COLUMN title TEXT;
+ALTER TABLE user_profiles ADD COLUMN timezone TEXT;
+ALTER TABLE user_profiles ADD COLUMN pronouns TEXT;
+ALTER TABLE user_profiles ADD COLUMN status_emoji TEXT;
+ALTER TABLE user_profiles ADD COLUMN status_text TEXT;
+ALTER TABLE user_profiles ADD COLUMN status_expires_at DATETIME;
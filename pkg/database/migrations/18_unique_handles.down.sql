@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS user_handle_history_index;
+DROP TABLE IF EXISTS user_handle_history;
+DROP INDEX IF EXISTS user_profile_username_index;
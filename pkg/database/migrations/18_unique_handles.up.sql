@@ -0,0 +1,24 @@
+-- handles that were not valid under the new rules keep showing as the display name
+UPDATE user_profiles
+SET display_name = username
+WHERE display_name IS NULL AND username GLOB '*[^A-Za-z0-9._-]*';
+
+-- the oldest account keeps a shared handle, everyone else gets a numbered one
+UPDATE user_profiles
+SET username = user_profiles.username || '-' || (d.n + 1)
+FROM (
+    SELECT p.user_id, ROW_NUMBER() OVER (PARTITION BY lower(p.username) ORDER BY u.created_at, p.user_id) - 1 AS n
+    FROM user_profiles p
+    INNER JOIN users u ON u.id = p.user_id
+) AS d
+WHERE d.user_id = user_profiles.user_id AND d.n > 0;
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_profile_username_index ON user_profiles(lower(username));
+
+CREATE TABLE IF NOT EXISTS user_handle_history (
+       handle TEXT NOT NULL,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       retired_at DATETIME NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_handle_history_index ON user_handle_history(lower(handle));
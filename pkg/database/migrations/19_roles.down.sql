@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS role_capabilities;
+DROP INDEX IF EXISTS role_name_index;
+DROP TABLE IF EXISTS roles;
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS roles (
+       id TEXT NOT NULL PRIMARY KEY,
+       name TEXT NOT NULL,
+       -- every account holds the capabilities of the default role
+       is_default BOOLEAN NOT NULL DEFAULT FALSE,
+       created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS role_name_index ON roles(lower(name));
+
+CREATE TABLE IF NOT EXISTS role_capabilities (
+       role_id TEXT NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+       capability TEXT NOT NULL,
+       PRIMARY KEY (role_id, capability)
+);
+
+CREATE TABLE IF NOT EXISTS user_roles (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       role_id TEXT NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+       PRIMARY KEY (user_id, role_id)
+);
+
+-- anyone could create rooms before roles existed
+INSERT INTO roles (id, name, is_default) VALUES ('everyone', 'Everyone', TRUE);
+INSERT INTO role_capabilities (role_id, capability) VALUES ('everyone', 'create_public_rooms');
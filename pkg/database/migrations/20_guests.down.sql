@@ -0,0 +1,5 @@
+ALTER TABLE invites DROP COLUMN guest_days;
+ALTER TABLE invites DROP COLUMN is_guest;
+
+ALTER TABLE users DROP COLUMN guest_expires_at;
+ALTER TABLE users DROP COLUMN is_guest;
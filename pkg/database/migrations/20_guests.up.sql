@@ -0,0 +1,7 @@
+-- guests only ever see the rooms they were added to
+ALTER TABLE users ADD COLUMN is_guest BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN guest_expires_at DATETIME;
+
+ALTER TABLE invites ADD COLUMN is_guest BOOLEAN NOT NULL DEFAULT FALSE;
+-- guest accounts made with the invite are deactivated this many days after registering
+ALTER TABLE invites ADD COLUMN guest_days INTEGER;
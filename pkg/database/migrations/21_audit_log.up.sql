@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+       id TEXT NOT NULL PRIMARY KEY,
+       -- names are copied in so entries stay readable after renames and deletions
+       actor_id TEXT,
+       actor_name TEXT,
+       action TEXT NOT NULL,
+       target_id TEXT,
+       target_name TEXT,
+       details TEXT,
+       ip TEXT,
+       created_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_created_at_index ON audit_log (created_at);
+
+-- entries are only ever added
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+       SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+       SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS user_blocks (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       blocked_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+       PRIMARY KEY (user_id, blocked_id)
+);
+
+CREATE TABLE IF NOT EXISTS user_mutes (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       muted_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+       PRIMARY KEY (user_id, muted_id)
+);
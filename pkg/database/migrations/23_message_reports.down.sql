@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS message_reports_reporter_index;
+DROP INDEX IF EXISTS message_reports_pending_index;
+DROP TABLE IF EXISTS message_reports;
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS message_reports (
+       id TEXT NOT NULL PRIMARY KEY,
+       -- the message may be deleted while the report is kept, so its text is copied
+       message_id TEXT NOT NULL,
+       room_id TEXT NOT NULL,
+       author_id TEXT REFERENCES users(id) ON DELETE SET NULL,
+       message TEXT NOT NULL,
+       message_created_at DATETIME NOT NULL,
+       reporter_id TEXT REFERENCES users(id) ON DELETE SET NULL,
+       reason TEXT NOT NULL,
+       created_at DATETIME NOT NULL,
+       resolution TEXT,
+       resolved_by TEXT REFERENCES users(id) ON DELETE SET NULL,
+       resolved_at DATETIME,
+       note TEXT
+);
+
+CREATE INDEX IF NOT EXISTS message_reports_pending_index ON message_reports(resolution, created_at);
+CREATE UNIQUE INDEX IF NOT EXISTS message_reports_reporter_index ON message_reports(message_id, reporter_id);
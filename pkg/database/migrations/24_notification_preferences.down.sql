@@ -0,0 +1,7 @@
+ALTER TABLE user_profiles DROP COLUMN quiet_end;
+ALTER TABLE user_profiles DROP COLUMN quiet_start;
+
+DROP INDEX IF EXISTS message_mention_user_index;
+DROP TABLE IF EXISTS message_mentions;
+DROP TABLE IF EXISTS room_reads;
+DROP TABLE IF EXISTS room_preferences;
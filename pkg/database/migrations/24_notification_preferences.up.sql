@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS room_preferences (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       room_id TEXT NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+       -- all, mentions or nothing
+       level TEXT NOT NULL DEFAULT 'all',
+       muted_until DATETIME,
+       PRIMARY KEY (user_id, room_id)
+);
+
+CREATE TABLE IF NOT EXISTS room_reads (
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       room_id TEXT NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+       last_read_at DATETIME NOT NULL,
+       PRIMARY KEY (user_id, room_id)
+);
+
+CREATE TABLE IF NOT EXISTS message_mentions (
+       message_id TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       PRIMARY KEY (message_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS message_mention_user_index ON message_mentions(user_id);
+
+-- minutes after local midnight, alerts are held back from start until end which may be the next day
+ALTER TABLE user_profiles ADD COLUMN quiet_start INTEGER;
+ALTER TABLE user_profiles ADD COLUMN quiet_end INTEGER;
@@ -0,0 +1,13 @@
+-- one row per browser that allowed pushes, an endpoint belongs to whoever subscribed with it last
+CREATE TABLE IF NOT EXISTS push_subscriptions (
+       id TEXT PRIMARY KEY NOT NULL,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       endpoint TEXT NOT NULL UNIQUE,
+       p256dh TEXT NOT NULL,
+       auth TEXT NOT NULL,
+       user_agent TEXT,
+       created_at DATETIME NOT NULL,
+       last_used_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS push_subscriptions_user_index ON push_subscriptions(user_id);
@@ -0,0 +1,2 @@
+ALTER TABLE user_profiles DROP COLUMN digest_sent_at;
+ALTER TABLE user_profiles DROP COLUMN digest_frequency;
@@ -0,0 +1,4 @@
+-- never, hourly, daily or weekly
+ALTER TABLE user_profiles ADD COLUMN digest_frequency TEXT NOT NULL DEFAULT 'never';
+-- stored like message timestamps so the two compare
+ALTER TABLE user_profiles ADD COLUMN digest_sent_at DATETIME;
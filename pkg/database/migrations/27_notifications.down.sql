@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS notifications_user_index;
+DROP TABLE IF EXISTS notifications;
@@ -0,0 +1,15 @@
+-- what someone should hear about in the app, kept until they read it
+CREATE TABLE IF NOT EXISTS notifications (
+       id TEXT PRIMARY KEY NOT NULL,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       -- mention, direct_message, room_invite or approved
+       kind TEXT NOT NULL,
+       actor_id TEXT REFERENCES users(id) ON DELETE SET NULL,
+       room_id TEXT REFERENCES rooms(id) ON DELETE CASCADE,
+       message_id TEXT REFERENCES messages(id) ON DELETE CASCADE,
+       body TEXT,
+       created_at DATETIME NOT NULL,
+       read_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS notifications_user_index ON notifications(user_id, created_at);
@@ -0,0 +1,9 @@
+-- which identity provider account signs in as which user, matched by subject after the first sign in
+CREATE TABLE IF NOT EXISTS sso_identities (
+       issuer TEXT NOT NULL,
+       subject TEXT NOT NULL,
+       user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+       created_at DATETIME NOT NULL,
+       PRIMARY KEY (issuer, subject),
+       UNIQUE (user_id, issuer)
+);
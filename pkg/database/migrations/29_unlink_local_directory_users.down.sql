@@ -0,0 +1,2 @@
+-- the removed links are not restored
+SELECT 1;
@@ -0,0 +1,2 @@
+-- local accounts that were linked to a directory entry only because the addresses matched
+DELETE FROM directory_users WHERE user_id IN (SELECT id FROM users WHERE hash != '');
@@ -0,0 +1 @@
+DROP VIEW IF EXISTS external_accounts;
@@ -0,0 +1,5 @@
+-- accounts whose sign in an identity provider or a directory is in charge of
+CREATE VIEW IF NOT EXISTS external_accounts AS
+SELECT user_id FROM sso_identities
+UNION
+SELECT user_id FROM directory_users;
@@ -0,0 +1,2 @@
+DROP VIEW IF EXISTS role_grants;
+DROP VIEW IF EXISTS room_access;
@@ -0,0 +1,15 @@
+-- rooms each user may read: the ones they were added to, and every public one unless they are a guest
+CREATE VIEW IF NOT EXISTS room_access AS
+SELECT user_id, room_id FROM user_rooms
+UNION
+SELECT u.id AS user_id, r.id AS room_id
+FROM users u, rooms r
+WHERE r.is_private = FALSE AND u.is_guest = FALSE;
+
+-- roles each user holds: their own, and the default ones unless they are a guest
+CREATE VIEW IF NOT EXISTS role_grants AS
+SELECT user_id, role_id FROM user_roles
+UNION
+SELECT u.id AS user_id, r.id AS role_id
+FROM users u, roles r
+WHERE r.is_default = TRUE AND u.is_guest = FALSE;
@@ -0,0 +1,266 @@
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+use time::OffsetDateTime;
+
+use crate::Database;
+
+// reactions and thread replies get their kinds once messages can have either
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NotificationKind {
+    Mention,
+    DirectMessage,
+    // added to a private room by someone else
+    RoomInvite,
+    // an admin let the account in
+    Approved,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Mention => "mention",
+            NotificationKind::DirectMessage => "direct_message",
+            NotificationKind::RoomInvite => "room_invite",
+            NotificationKind::Approved => "approved",
+        }
+    }
+}
+
+pub struct NewNotification<'a> {
+    pub user_id: &'a str,
+    pub kind: NotificationKind,
+    pub actor_id: Option<&'a str>,
+    pub room_id: Option<&'a str>,
+    pub message_id: Option<&'a str>,
+    pub body: Option<&'a str>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Notification {
+    pub id: String,
+    pub kind: String,
+    pub actor_name: Option<String>,
+    pub room_id: Option<String>,
+    pub room_name: Option<String>,
+    pub room_is_user: Option<bool>,
+    pub message_id: Option<String>,
+    pub body: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub is_read: bool,
+}
+
+/// Stores a notification and returns its id. Direct messages pile up in the unread
+/// notification of their room instead of adding one per message.
+pub async fn create_notification(db: &Database, new: &NewNotification<'_>) -> Result<String> {
+    let mut trx = db.pool.begin().await?;
+    let id = store(&mut trx, new, OffsetDateTime::now_utc()).await?;
+    trx.commit().await?;
+
+    Ok(id)
+}
+
+/// Stores the notifications of one message in a single transaction, returning their ids in order.
+pub async fn create_notifications(
+    db: &Database,
+    new: &[NewNotification<'_>],
+) -> Result<Vec<String>> {
+    let now = OffsetDateTime::now_utc();
+    let mut trx = db.pool.begin().await?;
+
+    let mut ids = Vec::with_capacity(new.len());
+    for notification in new {
+        ids.push(store(&mut trx, notification, now).await?);
+    }
+
+    trx.commit().await?;
+
+    Ok(ids)
+}
+
+async fn store(
+    trx: &mut Transaction<'_, Sqlite>,
+    new: &NewNotification<'_>,
+    now: OffsetDateTime,
+) -> Result<String> {
+    let kind = new.kind.as_str();
+
+    if new.kind == NotificationKind::DirectMessage {
+        let existing = sqlx::query!(
+            r#"
+UPDATE notifications SET actor_id = $1, message_id = $2, body = $3, created_at = $4
+WHERE user_id = $5 AND kind = $6 AND room_id = $7 AND read_at IS NULL
+RETURNING id as "id!"
+"#,
+            new.actor_id,
+            new.message_id,
+            new.body,
+            now,
+            new.user_id,
+            kind,
+            new.room_id
+        )
+        .fetch_optional(&mut **trx)
+        .await?;
+
+        if let Some(existing) = existing {
+            return Ok(existing.id);
+        }
+    }
+
+    let id = xid::new().to_string();
+    sqlx::query!(
+        r#"
+INSERT INTO notifications (id, user_id, kind, actor_id, room_id, message_id, body, created_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+"#,
+        id,
+        new.user_id,
+        kind,
+        new.actor_id,
+        new.room_id,
+        new.message_id,
+        new.body,
+        now
+    )
+    .execute(&mut **trx)
+    .await?;
+
+    Ok(id)
+}
+
+/// The newest notifications of the user, read or not.
+pub async fn get_notifications(
+    db: &Database,
+    user_id: &str,
+    limit: i64,
+) -> Result<Vec<Notification>> {
+    let notifications = sqlx::query_as!(
+        Notification,
+        r#"
+SELECT n.id as "id!", n.kind, COALESCE(p.display_name, p.username) as "actor_name?: String",
+       n.room_id, r.name as "room_name?", r.is_user as "room_is_user?: bool", n.message_id, n.body,
+       n.created_at as "created_at: OffsetDateTime", n.read_at IS NOT NULL as "is_read!: bool"
+FROM notifications n
+LEFT JOIN user_profiles p ON p.user_id = n.actor_id
+LEFT JOIN rooms r ON r.id = n.room_id
+WHERE n.user_id = $1
+ORDER BY n.created_at DESC, n.id DESC
+LIMIT $2
+"#,
+        user_id,
+        limit
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(notifications)
+}
+
+pub async fn get_unread_count(db: &Database, user_id: &str) -> Result<i64> {
+    let row = sqlx::query!(
+        r#"
+SELECT COUNT(*) as "count!: i64"
+FROM notifications
+WHERE user_id = $1 AND read_at IS NULL
+"#,
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(row.count)
+}
+
+pub async fn mark_read(db: &Database, user_id: &str, id: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "UPDATE notifications SET read_at = $1 WHERE id = $2 AND user_id = $3 AND read_at IS NULL",
+        now,
+        id,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn mark_all_read(db: &Database, user_id: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "UPDATE notifications SET read_at = $1 WHERE user_id = $2 AND read_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Opening a room covers whatever the user was notified about in it.
+pub async fn mark_room_read(db: &Database, user_id: &str, room_id: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "UPDATE notifications SET read_at = $1 WHERE user_id = $2 AND room_id = $3 AND read_at IS NULL",
+        now,
+        user_id,
+        room_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rooms::create_room,
+        testing::{database, local_user},
+    };
+
+    fn direct<'a>(user_id: &'a str, actor_id: &'a str, body: &'a str) -> NewNotification<'a> {
+        NewNotification {
+            user_id,
+            kind: NotificationKind::DirectMessage,
+            actor_id: Some(actor_id),
+            room_id: Some("dm"),
+            message_id: None,
+            body: Some(body),
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_collapse_direct_messages() {
+        let db = database("notifications").await;
+        let ada = local_user(&db, "ada").await;
+        let bob = local_user(&db, "bob").await;
+        create_room(&db, "dm", "dm", "", true, true, &[ada.clone(), bob.clone()])
+            .await
+            .unwrap();
+
+        let mention = NewNotification {
+            kind: NotificationKind::Mention,
+            ..direct(&ada, &bob, "hi @ada")
+        };
+        let ids = create_notifications(&db, &[mention, direct(&ada, &bob, "first")])
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+
+        // a later message lands in the same unread notification
+        let again = create_notifications(&db, &[direct(&ada, &bob, "second")])
+            .await
+            .unwrap();
+        assert_eq!(again, ids[1..]);
+        assert_eq!(get_unread_count(&db, &ada).await.unwrap(), 2);
+        assert_eq!(get_unread_count(&db, &bob).await.unwrap(), 0);
+
+        let latest = get_notifications(&db, &ada, 10).await.unwrap();
+        assert!(latest.iter().any(|n| n.body.as_deref() == Some("second")));
+
+        assert!(create_notifications(&db, &[]).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::Result;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::{users::hash_password, Database};
+
+#[derive(Error, Debug)]
+pub enum PasswordResetError {
+    #[error("reset link is invalid or expired")]
+    InvalidToken,
+    #[error("this account signs in through single sign-on or the directory")]
+    ExternallyManaged,
+}
+
+pub async fn create_password_reset(
+    db: &Database,
+    user_id: &str,
+    token_hash: &str,
+    expires_at: OffsetDateTime,
+    created_by: Option<&str>,
+) -> Result<()> {
+    // a password of their own would let these accounts outlive the provider's say
+    if crate::sso::is_externally_managed(db, user_id).await? {
+        return Err(PasswordResetError::ExternallyManaged.into());
+    }
+
+    sqlx::query!(
+        r#"
+INSERT INTO password_resets (token_hash, user_id, expires_at, created_by)
+VALUES ($1, $2, $3, $4)
+"#,
+        token_hash,
+        user_id,
+        expires_at,
+        created_by
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The username a still valid reset token belongs to.
+pub async fn get_password_reset_user(db: &Database, token_hash: &str) -> Result<String> {
+    let now = OffsetDateTime::now_utc();
+    let row = sqlx::query!(
+        r#"
+SELECT p.username
+FROM password_resets r
+INNER JOIN users u ON u.id = r.user_id
+INNER JOIN user_profiles p ON p.user_id = r.user_id
+WHERE r.token_hash = $1 AND r.expires_at > $2 AND u.is_enabled = TRUE
+  AND r.user_id NOT IN (SELECT user_id FROM external_accounts)
+"#,
+        token_hash,
+        now
+    )
+    .fetch_optional(&db.pool)
+    .await?
+    .ok_or(PasswordResetError::InvalidToken)?;
+
+    Ok(row.username)
+}
+
+/// Sets a new password with a reset token, the token and every session of the user are dropped.
+pub async fn reset_password(db: &Database, token_hash: &str, password: &str) -> Result<String> {
+    let (password, salt) = hash_password(password)?;
+
+    let mut trx = db.pool.begin().await?;
+
+    let now = OffsetDateTime::now_utc();
+    let row = sqlx::query!(
+        r#"
+SELECT r.user_id
+FROM password_resets r
+INNER JOIN users u ON u.id = r.user_id
+WHERE r.token_hash = $1 AND r.expires_at > $2 AND u.is_enabled = TRUE
+  AND r.user_id NOT IN (SELECT user_id FROM external_accounts)
+"#,
+        token_hash,
+        now
+    )
+    .fetch_optional(&mut *trx)
+    .await?
+    .ok_or(PasswordResetError::InvalidToken)?;
+
+    sqlx::query!(
+        "UPDATE users SET password = $1, hash = $2 WHERE id = $3",
+        password,
+        salt,
+        row.user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", row.user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    // any other outstanding link is as stale as the old password
+    sqlx::query!(
+        "DELETE FROM password_resets WHERE user_id = $1",
+        row.user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(row.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+    use crate::{
+        sso,
+        testing::{database, local_user},
+        users::{create_external_user, ExternalLink},
+    };
+
+    fn expired(e: anyhow::Error) -> bool {
+        matches!(
+            e.downcast::<PasswordResetError>(),
+            Ok(PasswordResetError::InvalidToken)
+        )
+    }
+
+    #[tokio::test]
+    async fn tokens_are_single_use_and_expire() {
+        let db = database("resets").await;
+        let user_id = local_user(&db, "ada").await;
+        let in_an_hour = OffsetDateTime::now_utc() + Duration::hours(1);
+
+        create_password_reset(&db, &user_id, "token", in_an_hour, None)
+            .await
+            .unwrap();
+        create_password_reset(&db, &user_id, "other", in_an_hour, None)
+            .await
+            .unwrap();
+        assert_eq!(get_password_reset_user(&db, "token").await.unwrap(), "ada");
+
+        assert_eq!(
+            reset_password(&db, "token", "new password").await.unwrap(),
+            user_id
+        );
+        assert!(expired(
+            reset_password(&db, "token", "again").await.unwrap_err()
+        ));
+        // redeeming one link voids the others
+        assert!(expired(
+            reset_password(&db, "other", "again").await.unwrap_err()
+        ));
+
+        let an_hour_ago = OffsetDateTime::now_utc() - Duration::hours(1);
+        create_password_reset(&db, &user_id, "stale", an_hour_ago, None)
+            .await
+            .unwrap();
+        assert!(expired(
+            get_password_reset_user(&db, "stale").await.unwrap_err()
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_externally_managed_accounts() {
+        let db = database("resets-external").await;
+        let in_an_hour = OffsetDateTime::now_utc() + Duration::hours(1);
+
+        let sso_id = create_external_user(
+            &db,
+            "sso@example.com",
+            "sso",
+            false,
+            true,
+            ExternalLink::Sso {
+                issuer: "https://idp.example.com",
+                subject: "1",
+            },
+        )
+        .await
+        .unwrap();
+        let directory_id = create_external_user(
+            &db,
+            "ldap@example.com",
+            "ldap",
+            false,
+            true,
+            ExternalLink::Directory {
+                dn: "uid=ldap,dc=example,dc=org",
+            },
+        )
+        .await
+        .unwrap();
+
+        for user_id in [&sso_id, &directory_id] {
+            let e = create_password_reset(&db, user_id, user_id, in_an_hour, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                e.downcast::<PasswordResetError>(),
+                Ok(PasswordResetError::ExternallyManaged)
+            ));
+        }
+
+        // a link handed out before the account was tied to a provider
+        let user_id = local_user(&db, "ada").await;
+        create_password_reset(&db, &user_id, "token", in_an_hour, None)
+            .await
+            .unwrap();
+        sso::link_identity(&db, "https://idp.example.com", "2", &user_id)
+            .await
+            .unwrap();
+
+        assert!(expired(
+            get_password_reset_user(&db, "token").await.unwrap_err()
+        ));
+        assert!(expired(
+            reset_password(&db, "token", "new password")
+                .await
+                .unwrap_err()
+        ));
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::Result;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::{messages::POLL_MESSAGE, Database};
+
+pub const MAX_OPTIONS: usize = 10;
+
+pub struct NewPoll {
+    pub question: String,
+    pub options: Vec<String>,
+    pub is_multi: bool,
+    pub is_anonymous: bool,
+    pub closes_at: Option<OffsetDateTime>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Poll {
+    pub id: String,
+    pub room_id: String,
+    pub created_by: String,
+    pub question: String,
+    pub is_multi: bool,
+    pub is_anonymous: bool,
+    pub is_closed: bool,
+    pub closes_at: Option<OffsetDateTime>,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+pub struct PollOption {
+    pub id: String,
+    pub label: String,
+    pub votes: i64,
+    pub voted: bool,
+    pub voters: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum PollError {
+    #[error("poll needs a question")]
+    MissingQuestion,
+    #[error("poll needs between 2 and {MAX_OPTIONS} options")]
+    InvalidOptions,
+    #[error("poll is closed")]
+    Closed,
+    #[error("only the creator can close a poll")]
+    NotCreator,
+}
+
+/// Creates a poll message in the room and returns its id, which doubles as the poll id.
+pub async fn create_poll(
+    db: &Database,
+    room_id: &str,
+    user_id: &str,
+    poll: &NewPoll,
+) -> Result<String> {
+    if poll.question.trim().is_empty() {
+        return Err(PollError::MissingQuestion.into());
+    }
+
+    if poll.options.len() < 2 || poll.options.len() > MAX_OPTIONS {
+        return Err(PollError::InvalidOptions.into());
+    }
+
+    let mut trx = db.pool.begin().await?;
+    sqlx::query!(
+        r#"
+SELECT r.id
+FROM rooms r
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $2
+WHERE r.id = $1
+"#,
+        room_id,
+        user_id
+    )
+    .fetch_one(&mut *trx)
+    .await?;
+
+    let id = xid::new().to_string();
+
+    sqlx::query!(
+        r#"
+INSERT INTO messages (id, room_id, user_id, message, kind)
+VALUES ($1, $2, $3, $4, $5)
+"#,
+        id,
+        room_id,
+        user_id,
+        poll.question,
+        POLL_MESSAGE
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO polls (message_id, is_multi, is_anonymous, closes_at)
+VALUES ($1, $2, $3, $4)
+"#,
+        id,
+        poll.is_multi,
+        poll.is_anonymous,
+        poll.closes_at
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    for (position, label) in poll.options.iter().enumerate() {
+        let option_id = xid::new().to_string();
+        let position = position as i64;
+        sqlx::query!(
+            r#"
+INSERT INTO poll_options (id, poll_id, label, position)
+VALUES ($1, $2, $3, $4)
+"#,
+            option_id,
+            id,
+            label,
+            position
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(id)
+}
+
+/// Fetches a poll, as long as the user can see the room it was posted in.
+pub async fn get_poll(db: &Database, poll_id: &str, user_id: &str) -> Result<Poll> {
+    let row = sqlx::query!(
+        r#"
+SELECT p.message_id as "id!", m.room_id, m.user_id as created_by, m.message as "question!", p.is_multi as "is_multi!", p.is_anonymous as "is_anonymous!", p.closes_at as "closes_at: OffsetDateTime"
+FROM polls p
+INNER JOIN messages m ON m.id = p.message_id
+INNER JOIN rooms r ON r.id = m.room_id
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $2
+WHERE p.message_id = $1
+"#,
+        poll_id,
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    let is_closed = row
+        .closes_at
+        .is_some_and(|closes_at| closes_at <= OffsetDateTime::now_utc());
+
+    Ok(Poll {
+        id: row.id,
+        room_id: row.room_id,
+        created_by: row.created_by,
+        question: row.question,
+        is_multi: row.is_multi,
+        is_anonymous: row.is_anonymous,
+        is_closed,
+        closes_at: row.closes_at,
+    })
+}
+
+/// Options with their tally, whether `user_id` picked them and, unless anonymous, who did.
+pub async fn get_poll_options(
+    db: &Database,
+    poll: &Poll,
+    user_id: &str,
+) -> Result<Vec<PollOption>> {
+    let mut options = sqlx::query_as!(
+        PollOption,
+        r#"
+SELECT o.id, o.label, COUNT(v.user_id) as "votes!: i64", COALESCE(SUM(v.user_id = $2), 0) > 0 as "voted!: bool", GROUP_CONCAT(p.username, ', ') as "voters: String"
+FROM poll_options o
+LEFT JOIN poll_votes v ON v.option_id = o.id
+LEFT JOIN user_profiles p ON p.user_id = v.user_id
+WHERE o.poll_id = $1
+GROUP BY o.id
+ORDER BY o.position
+"#,
+        poll.id,
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    if poll.is_anonymous {
+        options.iter_mut().for_each(|o| o.voters = None);
+    }
+
+    Ok(options)
+}
+
+/// Toggles the user's vote on an option, single choice polls drop any other vote.
+pub async fn toggle_vote(db: &Database, poll: &Poll, option_id: &str, user_id: &str) -> Result<()> {
+    if poll.is_closed {
+        return Err(PollError::Closed.into());
+    }
+
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!(
+        "SELECT id FROM poll_options WHERE id = $1 AND poll_id = $2",
+        option_id,
+        poll.id
+    )
+    .fetch_one(&mut *trx)
+    .await?;
+
+    let removed = sqlx::query!(
+        "DELETE FROM poll_votes WHERE option_id = $1 AND user_id = $2",
+        option_id,
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        if !poll.is_multi {
+            sqlx::query!(
+                "DELETE FROM poll_votes WHERE poll_id = $1 AND user_id = $2",
+                poll.id,
+                user_id
+            )
+            .execute(&mut *trx)
+            .await?;
+        }
+
+        sqlx::query!(
+            "INSERT INTO poll_votes (poll_id, option_id, user_id) VALUES ($1, $2, $3)",
+            poll.id,
+            option_id,
+            user_id
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn close_poll(db: &Database, poll: &Poll, user_id: &str) -> Result<()> {
+    if poll.created_by != user_id {
+        return Err(PollError::NotCreator.into());
+    }
+
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "UPDATE polls SET closes_at = $1 WHERE message_id = $2",
+        now,
+        poll.id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
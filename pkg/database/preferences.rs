@@ -0,0 +1,304 @@
+use anyhow::Result;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// How much of a room someone wants to hear about.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NotifyLevel {
+    All,
+    Mentions,
+    Nothing,
+}
+
+impl NotifyLevel {
+    pub const ALL: [NotifyLevel; 3] = [
+        NotifyLevel::All,
+        NotifyLevel::Mentions,
+        NotifyLevel::Nothing,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotifyLevel::All => "all",
+            NotifyLevel::Mentions => "mentions",
+            NotifyLevel::Nothing => "nothing",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NotifyLevel::All => "All messages",
+            NotifyLevel::Mentions => "Mentions only",
+            NotifyLevel::Nothing => "Nothing",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<NotifyLevel> {
+        NotifyLevel::ALL.into_iter().find(|l| l.as_str() == value)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PreferenceError {
+    #[error("quiet hours need both a start and an end")]
+    IncompleteQuietHours,
+    #[error("quiet hours start and end on different times of day")]
+    InvalidQuietHours,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct RoomPreference {
+    pub level: String,
+    pub muted_until: Option<OffsetDateTime>,
+}
+
+impl RoomPreference {
+    pub fn is_muted(&self, now: OffsetDateTime) -> bool {
+        self.muted_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Minutes after local midnight, both set or both empty.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct QuietHours {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+/// Someone who can read the room a message was sent to.
+#[derive(Debug)]
+pub struct Recipient {
+    pub user_id: String,
+    pub username: String,
+    pub timezone: Option<String>,
+    pub quiet_start: Option<i64>,
+    pub quiet_end: Option<i64>,
+    pub level: String,
+    pub muted_until: Option<OffsetDateTime>,
+    // muted or blocked the sender
+    pub ignores_sender: bool,
+    // the room is a direct message
+    pub is_direct: bool,
+}
+
+/// What the sidebar shows next to a room.
+#[derive(serde::Serialize, Debug)]
+pub struct RoomActivity {
+    pub room_id: String,
+    pub unread: bool,
+    pub mentions: i64,
+}
+
+pub async fn get_room_preference(
+    db: &Database,
+    user_id: &str,
+    room_id: &str,
+) -> Result<RoomPreference> {
+    let preference = sqlx::query_as!(
+        RoomPreference,
+        r#"
+SELECT COALESCE((SELECT level FROM room_preferences WHERE user_id = $1 AND room_id = $2), 'all') as "level!: String",
+       (SELECT muted_until FROM room_preferences WHERE user_id = $1 AND room_id = $2) as "muted_until: OffsetDateTime"
+"#,
+        user_id,
+        room_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(preference)
+}
+
+pub async fn set_room_level(
+    db: &Database,
+    user_id: &str,
+    room_id: &str,
+    level: NotifyLevel,
+) -> Result<()> {
+    let level = level.as_str();
+    sqlx::query!(
+        r#"
+INSERT INTO room_preferences (user_id, room_id, level)
+VALUES ($1, $2, $3)
+ON CONFLICT (user_id, room_id) DO UPDATE SET level = excluded.level
+"#,
+        user_id,
+        room_id,
+        level
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Silences the room until the given time, or unmutes it when empty.
+pub async fn mute_room(
+    db: &Database,
+    user_id: &str,
+    room_id: &str,
+    until: Option<OffsetDateTime>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO room_preferences (user_id, room_id, muted_until)
+VALUES ($1, $2, $3)
+ON CONFLICT (user_id, room_id) DO UPDATE SET muted_until = excluded.muted_until
+"#,
+        user_id,
+        room_id,
+        until
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_quiet_hours(db: &Database, user_id: &str) -> Result<QuietHours> {
+    let hours = sqlx::query_as!(
+        QuietHours,
+        r#"SELECT quiet_start as start, quiet_end as end FROM user_profiles WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(hours)
+}
+
+pub async fn set_quiet_hours(db: &Database, user_id: &str, hours: &QuietHours) -> Result<()> {
+    match (hours.start, hours.end) {
+        (Some(start), Some(end)) => {
+            let in_day = |m: i64| (0..MINUTES_PER_DAY).contains(&m);
+            if !in_day(start) || !in_day(end) || start == end {
+                return Err(PreferenceError::InvalidQuietHours.into());
+            }
+        }
+        (None, None) => {}
+        _ => return Err(PreferenceError::IncompleteQuietHours.into()),
+    }
+
+    sqlx::query!(
+        "UPDATE user_profiles SET quiet_start = $1, quiet_end = $2 WHERE user_id = $3",
+        hours.start,
+        hours.end,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Everyone besides the sender who can read the room, with their preferences for it.
+pub async fn get_recipients(
+    db: &Database,
+    room_id: &str,
+    sender_id: &str,
+) -> Result<Vec<Recipient>> {
+    let recipients = sqlx::query_as!(
+        Recipient,
+        r#"
+SELECT u.id as "user_id!", p.username as "username!", p.timezone, p.quiet_start, p.quiet_end,
+       COALESCE(rp.level, 'all') as "level!: String", rp.muted_until as "muted_until: OffsetDateTime",
+       EXISTS (SELECT 1 FROM user_mutes WHERE user_id = u.id AND muted_id = $2)
+           OR EXISTS (SELECT 1 FROM user_blocks WHERE user_id = u.id AND blocked_id = $2) as "ignores_sender!: bool",
+       r.is_user as "is_direct!: bool"
+FROM users u
+INNER JOIN user_profiles p ON p.user_id = u.id
+INNER JOIN rooms r ON r.id = $1
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = u.id
+LEFT JOIN room_preferences rp ON rp.room_id = r.id AND rp.user_id = u.id
+WHERE u.id != $2 AND u.is_enabled = TRUE AND u.is_bot = FALSE
+"#,
+        room_id,
+        sender_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(recipients)
+}
+
+pub async fn record_mentions(db: &Database, message_id: &str, user_ids: &[String]) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    for user_id in user_ids {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO message_mentions (message_id, user_id) VALUES ($1, $2)",
+            message_id,
+            user_id
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn mark_read(db: &Database, user_id: &str, room_id: &str) -> Result<()> {
+    // stored like message timestamps so the two compare
+    sqlx::query!(
+        r#"
+INSERT INTO room_reads (user_id, room_id, last_read_at)
+VALUES ($1, $2, CURRENT_TIMESTAMP)
+ON CONFLICT (user_id, room_id) DO UPDATE SET last_read_at = excluded.last_read_at
+"#,
+        user_id,
+        room_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Unread messages and mentions in every room the user can read, as their preferences allow.
+pub async fn get_activity(db: &Database, user_id: &str) -> Result<Vec<RoomActivity>> {
+    let rows = sqlx::query!(
+        r#"
+SELECT r.id as "room_id!", COALESCE(rp.level, 'all') as "level!: String", rp.muted_until as "muted_until: OffsetDateTime",
+       EXISTS (
+           SELECT 1 FROM messages m
+           WHERE m.room_id = r.id AND m.user_id != $1 AND m.created_at > COALESCE(rr.last_read_at, '')
+       ) as "unread!: bool",
+       (
+           SELECT COUNT(*) FROM message_mentions mm
+           INNER JOIN messages m ON m.id = mm.message_id
+           WHERE mm.user_id = $1 AND m.room_id = r.id AND m.created_at > COALESCE(rr.last_read_at, '')
+       ) as "mentions!: i64"
+FROM rooms r
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $1
+LEFT JOIN room_preferences rp ON rp.room_id = r.id AND rp.user_id = $1
+LEFT JOIN room_reads rr ON rr.room_id = r.id AND rr.user_id = $1
+"#,
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let now = OffsetDateTime::now_utc();
+    let activity = rows
+        .into_iter()
+        .map(|row| {
+            let level = NotifyLevel::parse(&row.level).unwrap_or(NotifyLevel::All);
+            let muted = row.muted_until.is_some_and(|until| until > now);
+            let quiet = muted || level == NotifyLevel::Nothing;
+
+            RoomActivity {
+                room_id: row.room_id,
+                unread: !quiet && level == NotifyLevel::All && row.unread,
+                mentions: if quiet { 0 } else { row.mentions },
+            }
+        })
+        .collect();
+
+    Ok(activity)
+}
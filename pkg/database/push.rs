@@ -0,0 +1,101 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(serde::Serialize, Debug)]
+pub struct PushSubscription {
+    pub id: String,
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+    pub user_agent: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+/// Keeps the browser's subscription, moving it over when someone else subscribed from it before.
+pub async fn save_subscription(
+    db: &Database,
+    user_id: &str,
+    endpoint: &str,
+    p256dh: &str,
+    auth: &str,
+    user_agent: Option<&str>,
+) -> Result<()> {
+    let id = xid::new().to_string();
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        r#"
+INSERT INTO push_subscriptions (id, user_id, endpoint, p256dh, auth, user_agent, created_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+ON CONFLICT (endpoint) DO UPDATE
+SET user_id = excluded.user_id, p256dh = excluded.p256dh, auth = excluded.auth,
+    user_agent = excluded.user_agent, created_at = excluded.created_at, last_used_at = NULL
+"#,
+        id,
+        user_id,
+        endpoint,
+        p256dh,
+        auth,
+        user_agent,
+        now
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_subscriptions(db: &Database, user_id: &str) -> Result<Vec<PushSubscription>> {
+    let subscriptions = sqlx::query_as!(
+        PushSubscription,
+        r#"
+SELECT id, endpoint, p256dh, auth, user_agent,
+       created_at as "created_at: OffsetDateTime", last_used_at as "last_used_at: OffsetDateTime"
+FROM push_subscriptions
+WHERE user_id = $1
+ORDER BY created_at DESC
+"#,
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(subscriptions)
+}
+
+pub async fn delete_subscription(db: &Database, user_id: &str, id: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM push_subscriptions WHERE id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Drops a subscription the push service no longer knows.
+pub async fn remove_expired(db: &Database, id: &str) -> Result<()> {
+    sqlx::query!("DELETE FROM push_subscriptions WHERE id = $1", id)
+        .execute(&db.pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn mark_used(db: &Database, id: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "UPDATE push_subscriptions SET last_used_at = $1 WHERE id = $2",
+        now,
+        id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use anyhow::Result;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+// messages shown on either side of a reported one
+const CONTEXT_SIZE: i64 = 3;
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("say what is wrong with the message")]
+    MissingReason,
+    #[error("you cannot report your own message")]
+    OwnMessage,
+    #[error("you already reported this message")]
+    AlreadyReported,
+    #[error("this report was already handled")]
+    AlreadyResolved,
+}
+
+/// What a moderator did about a report.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resolution {
+    Dismissed,
+    MessageDeleted,
+    UserWarned,
+    UserDisabled,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Dismissed => "dismissed",
+            Resolution::MessageDeleted => "message_deleted",
+            Resolution::UserWarned => "user_warned",
+            Resolution::UserDisabled => "user_disabled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Resolution> {
+        [
+            Resolution::Dismissed,
+            Resolution::MessageDeleted,
+            Resolution::UserWarned,
+            Resolution::UserDisabled,
+        ]
+        .into_iter()
+        .find(|r| r.as_str() == value)
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Report {
+    pub id: String,
+    pub message_id: String,
+    pub room_id: String,
+    pub room_name: Option<String>,
+    pub author_id: Option<String>,
+    pub author_name: Option<String>,
+    pub message: String,
+    pub message_created_at: OffsetDateTime,
+    // false once the message was deleted
+    pub message_exists: bool,
+    pub reporter_name: Option<String>,
+    pub reason: String,
+    pub created_at: OffsetDateTime,
+    pub resolution: Option<String>,
+    pub resolved_by_name: Option<String>,
+    pub resolved_at: Option<OffsetDateTime>,
+    pub note: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ContextMessage {
+    pub id: String,
+    pub user_name: String,
+    pub message: String,
+    pub created_at: OffsetDateTime,
+    // sent before the reported message rather than after
+    pub is_before: bool,
+}
+
+/// Reports a message the user can read, one report per person and message.
+pub async fn report_message(
+    db: &Database,
+    message_id: &str,
+    reporter_id: &str,
+    reason: &str,
+) -> Result<String> {
+    if reason.is_empty() {
+        return Err(ReportError::MissingReason.into());
+    }
+
+    let mut trx = db.pool.begin().await?;
+
+    let author = sqlx::query!(
+        r#"
+SELECT m.user_id
+FROM messages m
+INNER JOIN rooms r ON r.id = m.room_id
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $2
+WHERE m.id = $1
+"#,
+        message_id,
+        reporter_id
+    )
+    .fetch_one(&mut *trx)
+    .await?;
+
+    if author.user_id == reporter_id {
+        return Err(ReportError::OwnMessage.into());
+    }
+
+    let reported = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM message_reports WHERE message_id = $1 AND reporter_id = $2) as "reported!: bool""#,
+        message_id,
+        reporter_id
+    )
+    .fetch_one(&mut *trx)
+    .await?
+    .reported;
+
+    if reported {
+        return Err(ReportError::AlreadyReported.into());
+    }
+
+    let id = xid::new().to_string();
+    let now = OffsetDateTime::now_utc();
+    // created_at is copied as stored so the context lookup compares like with like
+    sqlx::query!(
+        r#"
+INSERT INTO message_reports (id, message_id, room_id, author_id, message, message_created_at, reporter_id, reason, created_at)
+SELECT $1, m.id, m.room_id, m.user_id, m.message, m.created_at, $2, $3, $4
+FROM messages m
+WHERE m.id = $5
+"#,
+        id,
+        reporter_id,
+        reason,
+        now,
+        message_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(id)
+}
+
+pub async fn count_pending(db: &Database) -> Result<i64> {
+    let count = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!: i64" FROM message_reports WHERE resolution IS NULL"#
+    )
+    .fetch_one(&db.pool)
+    .await?
+    .count;
+
+    Ok(count)
+}
+
+/// Open reports oldest first, or the latest handled ones newest first.
+pub async fn get_reports(db: &Database, resolved: bool, limit: i64) -> Result<Vec<Report>> {
+    let reports = sqlx::query_as!(
+        Report,
+        r#"
+SELECT mr.id, mr.message_id, mr.room_id, r.name as "room_name?", mr.author_id, a.username as "author_name?",
+       mr.message, mr.message_created_at as "message_created_at: OffsetDateTime",
+       EXISTS (SELECT 1 FROM messages m WHERE m.id = mr.message_id) as "message_exists!: bool",
+       rp.username as "reporter_name?", mr.reason, mr.created_at as "created_at: OffsetDateTime",
+       mr.resolution, rb.username as "resolved_by_name?", mr.resolved_at as "resolved_at: OffsetDateTime", mr.note
+FROM message_reports mr
+LEFT JOIN rooms r ON r.id = mr.room_id
+LEFT JOIN user_profiles a ON a.user_id = mr.author_id
+LEFT JOIN user_profiles rp ON rp.user_id = mr.reporter_id
+LEFT JOIN user_profiles rb ON rb.user_id = mr.resolved_by
+WHERE (mr.resolution IS NOT NULL) = $1
+ORDER BY CASE WHEN $1 THEN mr.resolved_at END DESC, mr.created_at
+LIMIT $2
+"#,
+        resolved,
+        limit
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(reports)
+}
+
+pub async fn get_report(db: &Database, report_id: &str) -> Result<Report> {
+    let report = sqlx::query_as!(
+        Report,
+        r#"
+SELECT mr.id, mr.message_id, mr.room_id, r.name as "room_name?", mr.author_id, a.username as "author_name?",
+       mr.message, mr.message_created_at as "message_created_at: OffsetDateTime",
+       EXISTS (SELECT 1 FROM messages m WHERE m.id = mr.message_id) as "message_exists!: bool",
+       rp.username as "reporter_name?", mr.reason, mr.created_at as "created_at: OffsetDateTime",
+       mr.resolution, rb.username as "resolved_by_name?", mr.resolved_at as "resolved_at: OffsetDateTime", mr.note
+FROM message_reports mr
+LEFT JOIN rooms r ON r.id = mr.room_id
+LEFT JOIN user_profiles a ON a.user_id = mr.author_id
+LEFT JOIN user_profiles rp ON rp.user_id = mr.reporter_id
+LEFT JOIN user_profiles rb ON rb.user_id = mr.resolved_by
+WHERE mr.id = $1
+"#,
+        report_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(report)
+}
+
+/// The messages sent just before and after the reported one, oldest first.
+pub async fn get_context(db: &Database, report_id: &str) -> Result<Vec<ContextMessage>> {
+    let messages = sqlx::query_as!(
+        ContextMessage,
+        r#"
+SELECT id as "id!", user_name as "user_name!", message as "message!", created_at as "created_at!: OffsetDateTime", is_before as "is_before!: bool"
+FROM (
+    SELECT * FROM (
+        SELECT m.id, p.username as user_name, m.message, m.created_at, TRUE as is_before
+        FROM messages m
+        INNER JOIN user_profiles p ON p.user_id = m.user_id
+        INNER JOIN message_reports mr ON mr.id = $1 AND m.room_id = mr.room_id
+        WHERE m.created_at < mr.message_created_at
+           OR (m.created_at = mr.message_created_at AND m.id < mr.message_id)
+        ORDER BY m.created_at DESC, m.id DESC
+        LIMIT $2
+    )
+    UNION ALL
+    SELECT * FROM (
+        SELECT m.id, p.username as user_name, m.message, m.created_at, FALSE as is_before
+        FROM messages m
+        INNER JOIN user_profiles p ON p.user_id = m.user_id
+        INNER JOIN message_reports mr ON mr.id = $1 AND m.room_id = mr.room_id
+        WHERE m.created_at > mr.message_created_at
+           OR (m.created_at = mr.message_created_at AND m.id > mr.message_id)
+        ORDER BY m.created_at, m.id
+        LIMIT $2
+    )
+)
+ORDER BY created_at, id
+"#,
+        report_id,
+        CONTEXT_SIZE
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(messages)
+}
+
+/// Closes every open report of the same message, the moderator and time are kept with it.
+pub async fn resolve_report(
+    db: &Database,
+    report_id: &str,
+    resolution: Resolution,
+    moderator_id: &str,
+    note: Option<&str>,
+) -> Result<()> {
+    let resolution = resolution.as_str();
+    let now = OffsetDateTime::now_utc();
+
+    let result = sqlx::query!(
+        r#"
+UPDATE message_reports
+SET resolution = $1, resolved_by = $2, resolved_at = $3, note = $4
+WHERE resolution IS NULL
+  AND message_id = (SELECT message_id FROM message_reports WHERE id = $5 AND resolution IS NULL)
+"#,
+        resolution,
+        moderator_id,
+        now,
+        note,
+        report_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ReportError::AlreadyResolved.into());
+    }
+
+    Ok(())
+}
+
+/// Opens the reports closed together with this one again, for when the action failed after the claim.
+pub async fn reopen_report(db: &Database, report_id: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+UPDATE message_reports
+SET resolution = NULL, resolved_by = NULL, resolved_at = NULL, note = NULL
+WHERE (message_id, resolved_at) = (SELECT message_id, resolved_at FROM message_reports WHERE id = $1)
+"#,
+        report_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::send_message,
+        rooms::create_room,
+        testing::{database, local_user},
+    };
+
+    fn already_resolved(result: Result<()>) -> bool {
+        matches!(
+            result.map_err(|e| e.downcast::<ReportError>()),
+            Err(Ok(ReportError::AlreadyResolved))
+        )
+    }
+
+    #[tokio::test]
+    async fn reports_are_claimed_once() {
+        let db = database("reports").await;
+        let ada = local_user(&db, "ada").await;
+        let bob = local_user(&db, "bob").await;
+        let eve = local_user(&db, "eve").await;
+        let boss = local_user(&db, "boss").await;
+
+        create_room(&db, "lobby", "lobby", "", false, false, &[])
+            .await
+            .unwrap();
+        let message = send_message(&db, "lobby", &eve, "spam", &[]).await.unwrap();
+        let first = report_message(&db, &message, &ada, "spam").await.unwrap();
+        let second = report_message(&db, &message, &bob, "spam").await.unwrap();
+
+        resolve_report(&db, &first, Resolution::MessageDeleted, &boss, None)
+            .await
+            .unwrap();
+
+        // the other report of the same message went with it
+        assert!(already_resolved(
+            resolve_report(&db, &second, Resolution::UserDisabled, &ada, None).await
+        ));
+        assert!(already_resolved(
+            resolve_report(&db, &first, Resolution::Dismissed, &ada, None).await
+        ));
+        assert_eq!(count_pending(&db).await.unwrap(), 0);
+
+        // an action that failed after the claim hands both back
+        reopen_report(&db, &second).await.unwrap();
+        assert_eq!(count_pending(&db).await.unwrap(), 2);
+        resolve_report(&db, &second, Resolution::Dismissed, &ada, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_report(&db, &first).await.unwrap().resolution.as_deref(),
+            Some(Resolution::Dismissed.as_str())
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::Database;
+
+/// Something a role lets its members do, admins can do all of them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Capability {
+    ApproveUsers,
+    ManageInvites,
+    GrantAdmin,
+    CreatePublicRooms,
+    ManageRoomMembers,
+    ViewUsers,
+    ViewAuditLog,
+    ModerateMessages,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 8] = [
+        Capability::ApproveUsers,
+        Capability::ManageInvites,
+        Capability::GrantAdmin,
+        Capability::CreatePublicRooms,
+        Capability::ManageRoomMembers,
+        Capability::ViewUsers,
+        Capability::ViewAuditLog,
+        Capability::ModerateMessages,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::ApproveUsers => "approve_users",
+            Capability::ManageInvites => "manage_invites",
+            Capability::GrantAdmin => "grant_admin",
+            Capability::CreatePublicRooms => "create_public_rooms",
+            Capability::ManageRoomMembers => "manage_room_members",
+            Capability::ViewUsers => "view_users",
+            Capability::ViewAuditLog => "view_audit_log",
+            Capability::ModerateMessages => "moderate_messages",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Capability::ApproveUsers => "Approve users",
+            Capability::ManageInvites => "Manage invites",
+            Capability::GrantAdmin => "Grant admin and roles",
+            Capability::CreatePublicRooms => "Create public rooms",
+            Capability::ManageRoomMembers => "Manage private room members",
+            Capability::ViewUsers => "View all users",
+            Capability::ViewAuditLog => "View the audit log",
+            Capability::ModerateMessages => "Moderate reported messages",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Capability> {
+        Capability::ALL.into_iter().find(|c| c.as_str() == value)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RoleError {
+    #[error("a role called {0} already exists")]
+    NameTaken(String),
+    #[error("roles need a name")]
+    MissingName,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Role {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+    pub capabilities: Vec<String>,
+    pub members: i64,
+}
+
+/// What `user_id` may do through its roles and the default role, not counting admin rights.
+///
+/// Guests only get what their own roles grant.
+pub async fn get_capabilities(db: &Database, user_id: &str) -> Result<Vec<Capability>> {
+    let rows = sqlx::query!(
+        r#"
+SELECT DISTINCT rc.capability
+FROM role_capabilities rc
+INNER JOIN role_grants g ON g.role_id = rc.role_id
+WHERE g.user_id = $1
+"#,
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| Capability::parse(&row.capability))
+        .collect())
+}
+
+/// Enabled people who are admins or hold the capability.
+pub async fn get_users_with_capability(
+    db: &Database,
+    capability: Capability,
+) -> Result<Vec<String>> {
+    let capability = capability.as_str();
+    let rows = sqlx::query!(
+        r#"
+SELECT u.id
+FROM users u
+WHERE u.is_enabled = TRUE AND u.is_bot = FALSE AND (
+    u.is_admin = TRUE OR EXISTS (
+        SELECT 1
+        FROM role_capabilities rc
+        INNER JOIN role_grants g ON g.role_id = rc.role_id
+        WHERE rc.capability = $1 AND g.user_id = u.id
+    )
+)
+"#,
+        capability
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.id).collect())
+}
+
+/// Every role with its capabilities, the default role first.
+pub async fn get_roles(db: &Database) -> Result<Vec<Role>> {
+    let rows = sqlx::query!(
+        r#"
+SELECT r.id, r.name, r.is_default, (SELECT COUNT(*) FROM user_roles ur WHERE ur.role_id = r.id) as "members!: i64"
+FROM roles r
+ORDER BY r.is_default DESC, r.name
+"#
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let mut capabilities: HashMap<String, Vec<String>> = HashMap::new();
+    for row in sqlx::query!("SELECT role_id, capability FROM role_capabilities")
+        .fetch_all(&db.pool)
+        .await?
+    {
+        capabilities
+            .entry(row.role_id)
+            .or_default()
+            .push(row.capability);
+    }
+
+    let roles = rows
+        .into_iter()
+        .map(|row| Role {
+            capabilities: capabilities.remove(&row.id).unwrap_or_default(),
+            id: row.id,
+            name: row.name,
+            is_default: row.is_default,
+            members: row.members,
+        })
+        .collect();
+
+    Ok(roles)
+}
+
+pub async fn create_role(db: &Database, name: &str, capabilities: &[Capability]) -> Result<String> {
+    if name.is_empty() {
+        return Err(RoleError::MissingName.into());
+    }
+
+    let mut trx = db.pool.begin().await?;
+
+    let taken = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM roles WHERE lower(name) = lower($1)) as "taken!: bool""#,
+        name
+    )
+    .fetch_one(&mut *trx)
+    .await?
+    .taken;
+
+    if taken {
+        return Err(RoleError::NameTaken(name.to_string()).into());
+    }
+
+    let id = xid::new().to_string();
+    sqlx::query!("INSERT INTO roles (id, name) VALUES ($1, $2)", id, name)
+        .execute(&mut *trx)
+        .await?;
+
+    for capability in capabilities {
+        let capability = capability.as_str();
+        sqlx::query!(
+            "INSERT OR IGNORE INTO role_capabilities (role_id, capability) VALUES ($1, $2)",
+            id,
+            capability
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(id)
+}
+
+pub async fn set_role_capabilities(
+    db: &Database,
+    role_id: &str,
+    capabilities: &[Capability],
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!("DELETE FROM role_capabilities WHERE role_id = $1", role_id)
+        .execute(&mut *trx)
+        .await?;
+
+    for capability in capabilities {
+        let capability = capability.as_str();
+        sqlx::query!(
+            "INSERT OR IGNORE INTO role_capabilities (role_id, capability) VALUES ($1, $2)",
+            role_id,
+            capability
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+/// Removes a role from everyone holding it, the default role cannot be deleted.
+pub async fn delete_role(db: &Database, role_id: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM roles WHERE id = $1 AND is_default = FALSE",
+        role_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Role ids held by each user, the default role is left out as everyone holds it.
+pub async fn get_member_roles(db: &Database) -> Result<HashMap<String, Vec<String>>> {
+    let mut members: HashMap<String, Vec<String>> = HashMap::new();
+    for row in sqlx::query!("SELECT user_id, role_id FROM user_roles")
+        .fetch_all(&db.pool)
+        .await?
+    {
+        members.entry(row.user_id).or_default().push(row.role_id);
+    }
+
+    Ok(members)
+}
+
+pub async fn set_user_roles(db: &Database, user_id: &str, role_ids: &[String]) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!("DELETE FROM user_roles WHERE user_id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    for role_id in role_ids {
+        sqlx::query!(
+            r#"
+INSERT OR IGNORE INTO user_roles (user_id, role_id)
+SELECT $1, id FROM roles WHERE id = $2 AND is_default = FALSE
+"#,
+            user_id,
+            role_id
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(())
+}
@@ -115,6 +115,18 @@ pub async fn is_member_of_room(db: &Database, roomid: &str, userid: &str) -> boo
     output.is_ok()
 }
 
+pub async fn set_room_description(db: &Database, roomid: &str, description: &str) -> Result<()> {
+    sqlx::query!(
+        r#"UPDATE rooms SET description = $1 WHERE id = $2"#,
+        description,
+        roomid
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
 pub async fn get_room_users(db: &Database, roomid: &str) -> Result<Vec<RoomUser>> {
     let users = sqlx::query_as!(
         RoomUser,
@@ -132,14 +144,15 @@ WHERE ur.room_id = $1
     Ok(users)
 }
 
+/// The room as long as `user_id` may see it, guests only see rooms they were added to.
 pub async fn get_room(db: &Database, roomid: &str, user_id: &str) -> Result<Room> {
     let room = sqlx::query_as!(
         Room,
         r#"
 SELECT id, description, name, is_user as "is_user!", is_private as "is_private!", created_at as "created_at!"
 FROM rooms r
-LEFT JOIN user_rooms ur ON r.id = ur.room_id AND ur.user_id = $1
-WHERE r.id = $2 AND (r.is_private = FALSE OR ur.user_id IS NOT NULL);
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $1
+WHERE r.id = $2;
 "#,
         user_id,
         roomid
@@ -212,14 +225,31 @@ VALUES ($1, $2)
     Ok(id.to_string())
 }
 
+/// Rooms the user was explicitly added to, public or private.
+pub async fn get_member_rooms(db: &Database, user_id: &str) -> Result<Vec<Room>> {
+    let rooms = sqlx::query_as!(
+        Room,
+        r#"
+SELECT id, description, name, is_user as "is_user!", is_private as "is_private!", created_at as "created_at!"
+FROM rooms r
+INNER JOIN user_rooms ur ON r.id = ur.room_id
+WHERE ur.user_id = $1
+"#,
+        user_id
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(rooms)
+}
+
 pub async fn get_rooms(db: &Database, user_id: &str) -> Result<(Vec<Room>, Vec<Room>)> {
     let rooms = sqlx::query_as!(
         Room,
         r#"
 SELECT id, description, name, is_user as "is_user!", is_private as "is_private!", created_at as "created_at!"
 FROM rooms r
-LEFT JOIN user_rooms ur ON r.id = ur.room_id AND ur.user_id = $1
-WHERE r.is_private = FALSE OR ur.user_id IS NOT NULL
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $1
 "#,
         user_id
     )
@@ -0,0 +1,113 @@
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+pub struct SavedMessage {
+    pub message_id: String,
+    pub room_id: String,
+    pub room_name: String,
+    pub room_is_user: bool,
+    pub user_name: String,
+    pub kind: String,
+    pub message: String,
+    pub created_at: OffsetDateTime,
+    pub saved_at: OffsetDateTime,
+    pub remind_at: Option<OffsetDateTime>,
+    pub is_due: bool,
+}
+
+/// Saves a message the user can read, saving it again only updates the reminder.
+pub async fn save_message(
+    db: &Database,
+    user_id: &str,
+    message_id: &str,
+    remind_at: Option<OffsetDateTime>,
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+SELECT m.id
+FROM messages m
+INNER JOIN rooms r ON r.id = m.room_id
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $2
+WHERE m.id = $1
+"#,
+        message_id,
+        user_id
+    )
+    .fetch_one(&mut *trx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO saved_messages (user_id, message_id, remind_at)
+VALUES ($1, $2, $3)
+ON CONFLICT (user_id, message_id) DO UPDATE SET remind_at = excluded.remind_at
+"#,
+        user_id,
+        message_id,
+        remind_at
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn unsave_message(db: &Database, user_id: &str, message_id: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM saved_messages WHERE user_id = $1 AND message_id = $2",
+        user_id,
+        message_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Saved messages from rooms the user can still read, due reminders first.
+pub async fn get_saved_messages(db: &Database, user_id: &str) -> Result<Vec<SavedMessage>> {
+    let now = OffsetDateTime::now_utc();
+    let saved = sqlx::query_as!(
+        SavedMessage,
+        r#"
+SELECT s.message_id, m.room_id, r.name as room_name, r.is_user as "room_is_user!", p.username as user_name, m.kind as "kind!", m.message as "message!", m.created_at as "created_at!", s.created_at as "saved_at!", s.remind_at as "remind_at: OffsetDateTime", COALESCE(s.remind_at <= $2, FALSE) as "is_due!: bool"
+FROM saved_messages s
+INNER JOIN messages m ON m.id = s.message_id
+INNER JOIN rooms r ON r.id = m.room_id
+INNER JOIN user_profiles p ON p.user_id = m.user_id
+INNER JOIN room_access a ON a.room_id = r.id AND a.user_id = $1
+WHERE s.user_id = $1
+ORDER BY COALESCE(s.remind_at <= $2, FALSE) DESC, s.created_at DESC
+"#,
+        user_id,
+        now
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(saved)
+}
+
+pub async fn count_due_reminders(db: &Database, user_id: &str) -> Result<i64> {
+    let now = OffsetDateTime::now_utc();
+    let row = sqlx::query!(
+        r#"
+SELECT COUNT(*) as "count!: i64"
+FROM saved_messages
+WHERE user_id = $1 AND remind_at <= $2
+"#,
+        user_id,
+        now
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(row.count)
+}
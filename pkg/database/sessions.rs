@@ -0,0 +1,141 @@
+use anyhow::Result;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub expires_at: OffsetDateTime,
+    pub last_seen_at: OffsetDateTime,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("session expired or revoked")]
+    Inactive,
+}
+
+pub async fn create_session(
+    db: &Database,
+    user_id: &str,
+    expires_at: OffsetDateTime,
+) -> Result<String> {
+    let mut trx = db.pool.begin().await?;
+
+    // good moment to forget about the sessions this user let expire
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "DELETE FROM sessions WHERE user_id = $1 AND expires_at <= $2",
+        user_id,
+        now
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    let id = xid::new().to_string();
+    sqlx::query!(
+        r#"
+INSERT INTO sessions (id, user_id, expires_at, last_seen_at)
+VALUES ($1, $2, $3, $4)
+"#,
+        id,
+        user_id,
+        expires_at,
+        now
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(id)
+}
+
+/// Checks the session is still active and belongs to the user.
+pub async fn verify_session(db: &Database, session_id: &str, user_id: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "SELECT id FROM sessions WHERE id = $1 AND user_id = $2 AND expires_at > $3",
+        session_id,
+        user_id,
+        now
+    )
+    .fetch_optional(&db.pool)
+    .await?
+    .ok_or(SessionError::Inactive)?;
+
+    Ok(())
+}
+
+/// Pushes the expiry of an active session forward.
+pub async fn extend_session(
+    db: &Database,
+    session_id: &str,
+    user_id: &str,
+    expires_at: OffsetDateTime,
+) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let updated = sqlx::query!(
+        r#"
+UPDATE sessions
+SET expires_at = $1, last_seen_at = $2
+WHERE id = $3 AND user_id = $4 AND expires_at > $2
+"#,
+        expires_at,
+        now,
+        session_id,
+        user_id
+    )
+    .execute(&db.pool)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        return Err(SessionError::Inactive.into());
+    }
+
+    Ok(())
+}
+
+pub async fn get_user_sessions(db: &Database, user_id: &str) -> Result<Vec<Session>> {
+    let now = OffsetDateTime::now_utc();
+    let sessions = sqlx::query_as!(
+        Session,
+        r#"
+SELECT id, user_id, expires_at, last_seen_at as "last_seen_at!", created_at as "created_at!"
+FROM sessions
+WHERE user_id = $1 AND expires_at > $2
+ORDER BY last_seen_at DESC
+"#,
+        user_id,
+        now
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(sessions)
+}
+
+pub async fn delete_session(db: &Database, session_id: &str) -> Result<()> {
+    sqlx::query!("DELETE FROM sessions WHERE id = $1", session_id)
+        .execute(&db.pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_other_sessions(db: &Database, user_id: &str, session_id: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM sessions WHERE user_id = $1 AND id != $2",
+        user_id,
+        session_id
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
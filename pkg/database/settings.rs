@@ -0,0 +1,27 @@
+use anyhow::Result;
+
+use crate::Database;
+
+pub async fn get_setting(db: &Database, key: &str) -> Result<Option<String>> {
+    let row = sqlx::query!("SELECT value FROM settings WHERE key = $1", key)
+        .fetch_optional(&db.pool)
+        .await?;
+
+    Ok(row.map(|r| r.value))
+}
+
+pub async fn set_setting(db: &Database, key: &str, value: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO settings (key, value)
+VALUES ($1, $2)
+ON CONFLICT (key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP
+"#,
+        key,
+        value
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
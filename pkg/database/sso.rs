@@ -0,0 +1,70 @@
+use anyhow::Result;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::Database;
+
+#[derive(Error, Debug)]
+pub enum SsoError {
+    #[error("this account is already linked to another single sign-on identity")]
+    AlreadyLinked,
+}
+
+/// The user an identity provider account signed in as before.
+pub async fn find_identity_user(
+    db: &Database,
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<String>> {
+    let row = sqlx::query!(
+        "SELECT user_id FROM sso_identities WHERE issuer = $1 AND subject = $2",
+        issuer,
+        subject
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Ties an identity to an existing account, which may only have one identity per provider.
+pub async fn link_identity(
+    db: &Database,
+    issuer: &str,
+    subject: &str,
+    user_id: &str,
+) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let result = sqlx::query!(
+        r#"
+INSERT INTO sso_identities (issuer, subject, user_id, created_at)
+VALUES ($1, $2, $3, $4)
+ON CONFLICT DO NOTHING
+"#,
+        issuer,
+        subject,
+        user_id,
+        now
+    )
+    .execute(&db.pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(SsoError::AlreadyLinked.into());
+    }
+
+    Ok(())
+}
+
+/// Whether the account signs in through an identity provider or a directory, which then
+/// decide who may use it.
+pub async fn is_externally_managed(db: &Database, user_id: &str) -> Result<bool> {
+    let row = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!: i64" FROM external_accounts WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(row.count > 0)
+}
@@ -0,0 +1,277 @@
+use anyhow::Result;
+
+use crate::{settings, Database};
+
+const POLICY_KEY: &str = "two_factor_policy";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TwoFactorPolicy {
+    #[default]
+    Optional,
+    Admins,
+    All,
+}
+
+impl TwoFactorPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TwoFactorPolicy::Optional => "optional",
+            TwoFactorPolicy::Admins => "admins",
+            TwoFactorPolicy::All => "all",
+        }
+    }
+
+    pub fn requires(&self, is_admin: bool) -> bool {
+        match self {
+            TwoFactorPolicy::Optional => false,
+            TwoFactorPolicy::Admins => is_admin,
+            TwoFactorPolicy::All => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Totp {
+    pub secret: String,
+    pub is_enabled: bool,
+    pub last_step: Option<i64>,
+}
+
+pub async fn get_policy(db: &Database) -> Result<TwoFactorPolicy> {
+    let policy = match settings::get_setting(db, POLICY_KEY).await?.as_deref() {
+        Some("admins") => TwoFactorPolicy::Admins,
+        Some("all") => TwoFactorPolicy::All,
+        _ => TwoFactorPolicy::Optional,
+    };
+
+    Ok(policy)
+}
+
+/// Stores the policy and signs out everyone it now applies to who has no second factor yet.
+pub async fn set_policy(db: &Database, policy: TwoFactorPolicy) -> Result<()> {
+    settings::set_setting(db, POLICY_KEY, policy.as_str()).await?;
+
+    let all = policy == TwoFactorPolicy::All;
+    let admins = policy == TwoFactorPolicy::Admins;
+    sqlx::query!(
+        r#"
+DELETE FROM sessions
+WHERE user_id IN (
+    SELECT u.id
+    FROM users u
+    LEFT JOIN user_totp t ON t.user_id = u.id AND t.is_enabled = TRUE
+    WHERE t.user_id IS NULL AND u.is_bot = FALSE AND ($1 OR ($2 AND u.is_admin = TRUE))
+)
+"#,
+        all,
+        admins
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_totp(db: &Database, user_id: &str) -> Result<Option<Totp>> {
+    let totp = sqlx::query_as!(
+        Totp,
+        r#"
+SELECT secret, is_enabled as "is_enabled!", last_step
+FROM user_totp
+WHERE user_id = $1
+"#,
+        user_id
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(totp)
+}
+
+pub async fn is_enabled(db: &Database, user_id: &str) -> Result<bool> {
+    Ok(get_totp(db, user_id).await?.is_some_and(|t| t.is_enabled))
+}
+
+/// Keeps a secret around until the user proves they can generate codes for it.
+pub async fn set_pending_secret(db: &Database, user_id: &str, secret: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO user_totp (user_id, secret, is_enabled)
+VALUES ($1, $2, FALSE)
+ON CONFLICT (user_id) DO UPDATE SET secret = excluded.secret, last_step = NULL
+WHERE user_totp.is_enabled = FALSE
+"#,
+        user_id,
+        secret
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn enable_totp(
+    db: &Database,
+    user_id: &str,
+    step: i64,
+    recovery_code_hashes: &[String],
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE user_totp SET is_enabled = TRUE, last_step = $2 WHERE user_id = $1",
+        user_id,
+        step
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    for code_hash in recovery_code_hashes {
+        sqlx::query!(
+            "INSERT INTO user_recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            user_id,
+            code_hash
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+/// Records a used time step, false if it (or a later one) was already used.
+pub async fn use_step(db: &Database, user_id: &str, step: i64) -> Result<bool> {
+    let updated = sqlx::query!(
+        r#"
+UPDATE user_totp
+SET last_step = $2
+WHERE user_id = $1 AND (last_step IS NULL OR last_step < $2)
+"#,
+        user_id,
+        step
+    )
+    .execute(&db.pool)
+    .await?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+/// Spends a recovery code, false if it does not exist or was already used.
+pub async fn use_recovery_code(db: &Database, user_id: &str, code_hash: &str) -> Result<bool> {
+    let deleted = sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1 AND code_hash = $2",
+        user_id,
+        code_hash
+    )
+    .execute(&db.pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+pub async fn replace_recovery_codes(
+    db: &Database,
+    user_id: &str,
+    recovery_code_hashes: &[String],
+) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    for code_hash in recovery_code_hashes {
+        sqlx::query!(
+            "INSERT INTO user_recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            user_id,
+            code_hash
+        )
+        .execute(&mut *trx)
+        .await?;
+    }
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn count_recovery_codes(db: &Database, user_id: &str) -> Result<i64> {
+    let row = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!: i64" FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(row.count)
+}
+
+/// Removes the second factor entirely, used when disabling it or by an admin.
+pub async fn reset_two_factor(db: &Database, user_id: &str) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{database, local_user};
+
+    #[tokio::test]
+    async fn codes_cannot_be_replayed() {
+        let db = database("two-factor").await;
+        let ada = local_user(&db, "ada").await;
+
+        set_pending_secret(&db, &ada, "SECRET").await.unwrap();
+        // the code that enabled it is spent already
+        enable_totp(&db, &ada, 100, &["one".to_string(), "two".to_string()])
+            .await
+            .unwrap();
+        assert!(!use_step(&db, &ada, 100).await.unwrap());
+
+        assert!(use_step(&db, &ada, 101).await.unwrap());
+        assert!(!use_step(&db, &ada, 101).await.unwrap());
+        // an older step that is still inside the window is refused as well
+        assert!(!use_step(&db, &ada, 100).await.unwrap());
+        assert!(use_step(&db, &ada, 103).await.unwrap());
+
+        assert!(use_recovery_code(&db, &ada, "one").await.unwrap());
+        assert!(!use_recovery_code(&db, &ada, "one").await.unwrap());
+        assert_eq!(count_recovery_codes(&db, &ada).await.unwrap(), 1);
+
+        // an enabled secret is not swapped by a new enrollment
+        set_pending_secret(&db, &ada, "OTHER").await.unwrap();
+        let totp = get_totp(&db, &ada).await.unwrap().unwrap();
+        assert_eq!(totp.secret, "SECRET");
+        assert_eq!(totp.last_step, Some(103));
+    }
+}
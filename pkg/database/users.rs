@@ -16,10 +16,46 @@ pub struct UserCombined {
     pub email: String,
     pub is_admin: bool,
     pub is_enabled: bool,
+    pub is_bot: bool,
+    pub is_guest: bool,
+    pub guest_expires_at: Option<OffsetDateTime>,
     pub created_at: OffsetDateTime,
     pub username: String,
+    pub display_name: Option<String>,
     pub bio: Option<String>,
     pub image: Option<String>,
+    pub timezone: Option<String>,
+}
+
+/// Everything shown on a profile card, an expired status is already cleared.
+#[derive(serde::Serialize, Debug)]
+pub struct Profile {
+    pub user_id: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub image: Option<String>,
+    pub title: Option<String>,
+    pub timezone: Option<String>,
+    pub pronouns: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_text: Option<String>,
+    pub status_expires_at: Option<OffsetDateTime>,
+    pub is_bot: bool,
+    pub is_guest: bool,
+}
+
+pub struct ProfileUpdate<'a> {
+    pub username: &'a str,
+    pub display_name: Option<&'a str>,
+    pub bio: &'a str,
+    pub title: Option<&'a str>,
+    pub timezone: Option<&'a str>,
+    pub pronouns: Option<&'a str>,
+    pub status_emoji: Option<&'a str>,
+    pub status_text: Option<&'a str>,
+    // the status stays until changed when empty
+    pub status_expires_at: Option<OffsetDateTime>,
 }
 
 pub struct User {
@@ -29,6 +65,8 @@ pub struct User {
     pub hash: String,
     pub is_admin: bool,
     pub is_enabled: bool,
+    pub is_guest: bool,
+    pub guest_expires_at: Option<OffsetDateTime>,
     pub created_at: OffsetDateTime,
 }
 
@@ -41,6 +79,8 @@ impl Default for User {
             hash: Default::default(),
             is_admin: false,
             is_enabled: false,
+            is_guest: false,
+            guest_expires_at: None,
             created_at: OffsetDateTime::now_utc(),
         }
     }
@@ -70,19 +110,82 @@ pub async fn search_users(db: &Database, search: &str, user_id: &str) -> Result<
     let output = sqlx::query_as!(
         UserCombined,
         r#"
-SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.created_at as "created_at!", p.username as username, p.bio, p.image
+SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.is_bot as "is_bot!", u.is_guest as "is_guest!", u.guest_expires_at as "guest_expires_at: OffsetDateTime", u.created_at as "created_at!", p.username as username, p.display_name, p.bio, p.image, p.timezone
 FROM users AS u 
 INNER JOIN user_profiles AS p ON u.id = p.user_id
-WHERE (p.username LIKE $1) AND u.id != $2
+INNER JOIN users AS me ON me.id = $2
+WHERE (p.username LIKE $1) AND u.id != $2 AND u.id != $3
+  -- guests only find the people they share a room with
+  AND (
+      me.is_guest = FALSE
+      OR EXISTS (
+          SELECT 1 FROM user_rooms mine
+          INNER JOIN user_rooms theirs ON theirs.room_id = mine.room_id
+          WHERE mine.user_id = $2 AND theirs.user_id = u.id
+      )
+  )
 LIMIT 5
 "#,
         search,
-        user_id
+        user_id,
+        DELETED_USER_ID
     ).fetch_all(&db.pool).await?;
 
     Ok(output)
 }
 
+pub async fn get_user_by_username(db: &Database, username: &str) -> Result<UserCombined> {
+    sqlx::query_as!(
+        UserCombined,
+        r#"
+SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.is_bot as "is_bot!", u.is_guest as "is_guest!", u.guest_expires_at as "guest_expires_at: OffsetDateTime", u.created_at as "created_at!", p.username as username, p.display_name, p.bio, p.image, p.timezone
+FROM users AS u
+INNER JOIN user_profiles AS p ON u.id = p.user_id
+WHERE p.user_id = COALESCE(
+    (SELECT user_id FROM user_profiles WHERE lower(username) = lower($1)),
+    (SELECT user_id FROM user_handle_history WHERE lower(handle) = lower($1))
+)
+"#,
+        username
+    )
+    .fetch_one(&db.pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_user_by_email(db: &Database, email: &str) -> Result<UserCombined> {
+    sqlx::query_as!(
+        UserCombined,
+        r#"
+SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.is_bot as "is_bot!", u.is_guest as "is_guest!", u.guest_expires_at as "guest_expires_at: OffsetDateTime", u.created_at as "created_at!", p.username as username, p.display_name, p.bio, p.image, p.timezone
+FROM users AS u
+INNER JOIN user_profiles AS p ON u.id = p.user_id
+WHERE u.email = $1
+"#,
+        email
+    )
+    .fetch_one(&db.pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn find_user_by_email(db: &Database, email: &str) -> Result<Option<UserCombined>> {
+    let user = sqlx::query_as!(
+        UserCombined,
+        r#"
+SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.is_bot as "is_bot!", u.is_guest as "is_guest!", u.guest_expires_at as "guest_expires_at: OffsetDateTime", u.created_at as "created_at!", p.username as username, p.display_name, p.bio, p.image, p.timezone
+FROM users AS u
+INNER JOIN user_profiles AS p ON u.id = p.user_id
+WHERE u.email = $1
+"#,
+        email
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(user)
+}
+
 pub async fn update_user_password(
     db: &Database,
     email: &str,
@@ -93,6 +196,7 @@ pub async fn update_user_password(
 
     let (password, salt) = hash_password(update)?;
 
+    let mut trx = db.pool.begin().await?;
     sqlx::query!(
         r#"
 UPDATE users
@@ -103,32 +207,78 @@ WHERE id = $3
         salt,
         user_id
     )
-    .execute(&db.pool)
+    .execute(&mut *trx)
     .await?;
 
+    // whoever knew the old password should not stay signed in
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    trx.commit().await?;
+
     Ok(())
 }
 
 pub async fn update_user_profile(
     db: &Database,
     user_id: &str,
-    name: &str,
-    bio: &str,
-) -> Result<UserCombined> {
+    update: &ProfileUpdate<'_>,
+) -> Result<Profile> {
+    let mut trx = db.pool.begin().await?;
+
+    crate::handles::rename_handle(&mut trx, user_id, update.username).await?;
+
     sqlx::query!(
         r#"
 UPDATE user_profiles
-SET username = $1, bio = $2
-WHERE user_id = $3
+SET display_name = $1, bio = $2, title = $3, timezone = $4, pronouns = $5,
+    status_emoji = $6, status_text = $7, status_expires_at = $8
+WHERE user_id = $9
 "#,
-        name,
-        bio,
+        update.display_name,
+        update.bio,
+        update.title,
+        update.timezone,
+        update.pronouns,
+        update.status_emoji,
+        update.status_text,
+        update.status_expires_at,
         user_id
     )
-    .execute(&db.pool)
+    .execute(&mut *trx)
     .await?;
 
-    get_user_with_profile(db, user_id).await
+    trx.commit().await?;
+
+    get_profile(db, user_id).await
+}
+
+pub async fn get_profile(db: &Database, user_id: &str) -> Result<Profile> {
+    let mut profile = sqlx::query_as!(
+        Profile,
+        r#"
+SELECT p.user_id, p.username, p.display_name, p.bio, p.image, p.title, p.timezone, p.pronouns,
+       p.status_emoji, p.status_text, p.status_expires_at as "status_expires_at: OffsetDateTime", u.is_bot as "is_bot!", u.is_guest as "is_guest!"
+FROM user_profiles AS p
+INNER JOIN users AS u ON u.id = p.user_id
+WHERE p.user_id = $1
+"#,
+        user_id
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    if profile
+        .status_expires_at
+        .is_some_and(|at| at <= OffsetDateTime::now_utc())
+    {
+        profile.status_emoji = None;
+        profile.status_text = None;
+        profile.status_expires_at = None;
+    }
+
+    Ok(profile)
 }
 
 pub async fn set_user_image<'a>(
@@ -151,7 +301,7 @@ WHERE user_id = $2
     Ok(())
 }
 
-pub async fn unset_user_image<'a>(db: &Database, user_id: &str) -> Result<()> {
+pub async fn unset_user_image(db: &Database, user_id: &str) -> Result<()> {
     sqlx::query!(
         r#"
 UPDATE user_profiles
@@ -168,6 +318,7 @@ WHERE user_id = $2
 }
 
 pub async fn enable_user(db: &Database, user_id: &str, enable: bool) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
     sqlx::query!(
         r#"
 UPDATE users
@@ -177,9 +328,17 @@ WHERE id = $2;
         enable,
         user_id
     )
-    .execute(&db.pool)
+    .execute(&mut *trx)
     .await?;
 
+    if !enable {
+        sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+            .execute(&mut *trx)
+            .await?;
+    }
+
+    trx.commit().await?;
+
     Ok(())
 }
 
@@ -228,8 +387,8 @@ pub async fn create_user<'a>(
     sqlx::query_as!(
         User,
         r#"
-INSERT INTO users (id, email, password, hash, is_admin, is_enabled)
-VALUES ($1, $2, $3, $4, $5, $6)
+INSERT INTO users (id, email, password, hash, is_admin, is_enabled, is_guest, guest_expires_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
 "#,
         user_id,
         user.email,
@@ -237,10 +396,14 @@ VALUES ($1, $2, $3, $4, $5, $6)
         salt,
         user.is_admin,
         user.is_enabled,
+        user.is_guest,
+        user.guest_expires_at,
     )
     .execute(&mut *trx)
     .await?;
 
+    crate::handles::claim_handle(&mut trx, user_id, &profile.username).await?;
+
     let profile_id = xid::new().to_string();
     sqlx::query_as!(
         UserProfile,
@@ -260,14 +423,97 @@ VALUES ($1 ,$2, $3, $4, $5)
     Ok((user_id.to_string(), trx))
 }
 
+/// Where an account without a password of its own signs in.
+pub enum ExternalLink<'a> {
+    Sso { issuer: &'a str, subject: &'a str },
+    Directory { dn: &'a str },
+}
+
+/// Provisions a user who signs in through single sign-on or a directory and so has no
+/// password, linked to the identity or entry that created it.
+pub async fn create_external_user(
+    db: &Database,
+    email: &str,
+    username: &str,
+    is_admin: bool,
+    is_enabled: bool,
+    link: ExternalLink<'_>,
+) -> Result<String> {
+    let mut trx = db.pool.begin().await?;
+
+    let user_id = xid::new().to_string();
+    // an empty hash never verifies and reset links are refused for these accounts
+    sqlx::query!(
+        r#"
+INSERT INTO users (id, email, password, hash, is_admin, is_enabled)
+VALUES ($1, $2, '', '', $3, $4)
+"#,
+        user_id,
+        email,
+        is_admin,
+        is_enabled,
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    // identity providers hand out full names, those are kept for display
+    let handle = crate::handles::available_handle(&mut trx, &user_id, username).await?;
+    let display_name = (handle != username).then_some(username);
+
+    let profile_id = xid::new().to_string();
+    sqlx::query!(
+        r#"
+INSERT INTO user_profiles (id, user_id, username, display_name)
+VALUES ($1 ,$2, $3, $4)
+"#,
+        profile_id,
+        user_id,
+        handle,
+        display_name,
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    let now = OffsetDateTime::now_utc();
+    match link {
+        ExternalLink::Sso { issuer, subject } => {
+            sqlx::query!(
+                "INSERT INTO sso_identities (issuer, subject, user_id, created_at) VALUES ($1, $2, $3, $4)",
+                issuer,
+                subject,
+                user_id,
+                now
+            )
+            .execute(&mut *trx)
+            .await?;
+        }
+        ExternalLink::Directory { dn } => {
+            sqlx::query!(
+                "INSERT INTO directory_users (user_id, dn, synced_at) VALUES ($1, $2, $3)",
+                user_id,
+                dn,
+                now
+            )
+            .execute(&mut *trx)
+            .await?;
+        }
+    }
+
+    trx.commit().await?;
+
+    Ok(user_id)
+}
+
 pub async fn get_user_list(db: &Database) -> Result<Vec<UserCombined>> {
     sqlx::query_as!(
         UserCombined,
         r#"
-SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.created_at as "created_at!", p.username as username, p.bio, p.image
+SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.is_bot as "is_bot!", u.is_guest as "is_guest!", u.guest_expires_at as "guest_expires_at: OffsetDateTime", u.created_at as "created_at!", p.username as username, p.display_name, p.bio, p.image, p.timezone
 FROM users AS u 
 INNER JOIN user_profiles AS p ON u.id = p.user_id
-"#,        
+WHERE u.id != $1
+"#,
+        DELETED_USER_ID
     )
     .fetch_all(&db.pool)
     .await.map_err(|e| e.into())
@@ -277,7 +523,7 @@ pub async fn get_user_with_profile(db: &Database, userid: &str) -> Result<UserCo
     sqlx::query_as!(
         UserCombined,
         r#"
-SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.created_at as "created_at!", p.username as username, p.bio, p.image
+SELECT u.id, u.email, u.is_admin as "is_admin!", u.is_enabled as "is_enabled!", u.is_bot as "is_bot!", u.is_guest as "is_guest!", u.guest_expires_at as "guest_expires_at: OffsetDateTime", u.created_at as "created_at!", p.username as username, p.display_name, p.bio, p.image, p.timezone
 FROM users AS u 
 INNER JOIN user_profiles AS p ON u.id = p.user_id
 WHERE u.id = $1
@@ -294,27 +540,34 @@ pub async fn verify_userpassword(
     password: &str,
 ) -> Result<String, DBUserErrors> {
     let user = sqlx::query!(
-        r#"SELECT id, is_enabled as "is_enabled!", password FROM users WHERE email = $1"#,
+        r#"SELECT id, is_enabled as "is_enabled!", is_bot as "is_bot!", password FROM users WHERE email = $1"#,
         email
     )
-    .fetch_one(&db.pool)
+    .fetch_optional(&db.pool)
     .await
-    .map_err(|e| DBUserErrors::InternalError(e.into()))?;
+    .map_err(|e| DBUserErrors::InternalError(e.into()))?
+    .ok_or(DBUserErrors::UnknownUser)?;
 
-    if !user.is_enabled {
-        return Err(DBUserErrors::UserNotEnabled);
+    if user.is_bot {
+        // bots authenticate with their api token only
+        return Err(DBUserErrors::PasswordMismatch);
     }
 
     let existing =
         String::from_utf8(user.password).map_err(|e| DBUserErrors::InternalError(e.into()))?;
-    if compare_password(&existing, password) {
-        return Ok(user.id);
+    if !compare_password(&existing, password) {
+        return Err(DBUserErrors::PasswordMismatch);
+    }
+
+    // only tell whether the account is enabled to someone who knows the password
+    if !user.is_enabled {
+        return Err(DBUserErrors::UserNotEnabled(user.id));
     }
 
-    Err(DBUserErrors::PasswordMismatch(password.to_string()))
+    Ok(user.id)
 }
 
-fn hash_password(password: &str) -> Result<(String, String), DBUserErrors> {
+pub(crate) fn hash_password(password: &str) -> Result<(String, String), DBUserErrors> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
 
@@ -340,10 +593,672 @@ fn compare_password(hashed: &str, password: &str) -> bool {
 pub enum DBUserErrors {
     #[error("failed to hash password: {0}")]
     PasswordHashFailed(argon2::password_hash::Error),
-    #[error("password mismatch: {0}")]
-    PasswordMismatch(String),
+    #[error("password mismatch")]
+    PasswordMismatch,
+    #[error("unknown user")]
+    UnknownUser,
     #[error("user not enabled")]
-    UserNotEnabled,
+    UserNotEnabled(String),
     #[error("internal error: {0}")]
     InternalError(anyhow::Error),
 }
+
+/// Messages and uploads of deleted accounts that were kept are credited to this user.
+pub const DELETED_USER_ID: &str = "deleted";
+
+/// What happens to the messages and uploads of a deleted account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletedContent {
+    Anonymize,
+    Delete,
+}
+
+#[derive(Debug, Error)]
+pub enum DeleteUserError {
+    #[error("{0} would be left without members, add someone else first")]
+    LastMemberOf(String),
+}
+
+/// Disables the account, signs it out everywhere and takes it out of the private rooms
+/// that have other members left.
+pub async fn deactivate_user(db: &Database, user_id: &str) -> Result<()> {
+    let mut trx = db.pool.begin().await?;
+
+    sqlx::query!("UPDATE users SET is_enabled = FALSE WHERE id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    // conversations between users are kept so the other side still has them
+    sqlx::query!(
+        r#"
+DELETE FROM user_rooms
+WHERE user_id = $1
+  AND room_id IN (
+      SELECT r.id FROM rooms r
+      WHERE r.is_private = TRUE AND r.is_user = FALSE
+        AND (SELECT COUNT(*) FROM user_rooms o WHERE o.room_id = r.id) > 1
+  )
+"#,
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    trx.commit().await?;
+
+    Ok(())
+}
+
+/// Removes the account for good, returns the uploaded files that should go from disk.
+///
+/// Commands and bots the user created are handed over to `deleted_by`.
+pub async fn delete_user(
+    db: &Database,
+    user_id: &str,
+    deleted_by: &str,
+    content: DeletedContent,
+) -> Result<Vec<String>> {
+    let mut trx = db.pool.begin().await?;
+
+    // nobody could reach a private room again once its last member is gone
+    let rooms = sqlx::query!(
+        r#"
+SELECT r.name
+FROM rooms r
+INNER JOIN user_rooms ur ON ur.room_id = r.id AND ur.user_id = $1
+WHERE r.is_private = TRUE AND r.is_user = FALSE
+  AND (SELECT COUNT(*) FROM user_rooms o WHERE o.room_id = r.id) = 1
+"#,
+        user_id
+    )
+    .fetch_all(&mut *trx)
+    .await?;
+
+    if !rooms.is_empty() {
+        let names = rooms
+            .into_iter()
+            .map(|r| format!("#{}", r.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(DeleteUserError::LastMemberOf(names).into());
+    }
+
+    let email = sqlx::query!("SELECT email FROM users WHERE id = $1", user_id)
+        .fetch_one(&mut *trx)
+        .await?
+        .email;
+
+    // profile pictures go either way, they are not part of any conversation
+    let delete_all = content == DeletedContent::Delete;
+    let files = sqlx::query!(
+        "SELECT url FROM uploads WHERE uploaded_by = $1 AND (room_id IS NULL OR $2)",
+        user_id,
+        delete_all
+    )
+    .fetch_all(&mut *trx)
+    .await?
+    .into_iter()
+    .map(|r| r.url)
+    .collect();
+
+    match content {
+        DeletedContent::Anonymize => {
+            // a disabled bot without a token can never sign in
+            sqlx::query!(
+                r#"
+INSERT OR IGNORE INTO users (id, email, password, hash, is_admin, is_enabled, is_bot)
+VALUES ($1, 'deleted@users', '', '', FALSE, FALSE, TRUE)
+"#,
+                DELETED_USER_ID
+            )
+            .execute(&mut *trx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+INSERT INTO user_profiles (id, user_id, username)
+SELECT $1, $1, 'Deleted user'
+WHERE NOT EXISTS (SELECT 1 FROM user_profiles WHERE user_id = $1)
+"#,
+                DELETED_USER_ID
+            )
+            .execute(&mut *trx)
+            .await?;
+
+            sqlx::query!(
+                "DELETE FROM uploads WHERE uploaded_by = $1 AND room_id IS NULL",
+                user_id
+            )
+            .execute(&mut *trx)
+            .await?;
+
+            sqlx::query!(
+                "UPDATE messages SET user_id = $1 WHERE user_id = $2",
+                DELETED_USER_ID,
+                user_id
+            )
+            .execute(&mut *trx)
+            .await?;
+
+            sqlx::query!(
+                "UPDATE uploads SET uploaded_by = $1 WHERE uploaded_by = $2",
+                DELETED_USER_ID,
+                user_id
+            )
+            .execute(&mut *trx)
+            .await?;
+
+            // votes stay counted, unless another deleted account already voted the same
+            sqlx::query!(
+                "UPDATE OR IGNORE poll_votes SET user_id = $1 WHERE user_id = $2",
+                DELETED_USER_ID,
+                user_id
+            )
+            .execute(&mut *trx)
+            .await?;
+        }
+        DeletedContent::Delete => {
+            sqlx::query!(
+                r#"
+DELETE FROM message_uploads
+WHERE message_id IN (SELECT id FROM messages WHERE user_id = $1)
+   OR upload_id IN (SELECT id FROM uploads WHERE uploaded_by = $1)
+"#,
+                user_id
+            )
+            .execute(&mut *trx)
+            .await?;
+
+            sqlx::query!("DELETE FROM messages WHERE user_id = $1", user_id)
+                .execute(&mut *trx)
+                .await?;
+
+            sqlx::query!("DELETE FROM uploads WHERE uploaded_by = $1", user_id)
+                .execute(&mut *trx)
+                .await?;
+        }
+    }
+
+    sqlx::query!("DELETE FROM poll_votes WHERE user_id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    sqlx::query!("DELETE FROM user_rooms WHERE user_id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    sqlx::query!(
+        "UPDATE commands SET created_by = $1 WHERE created_by = $2",
+        deleted_by,
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE bots SET created_by = $1 WHERE created_by = $2",
+        deleted_by,
+        user_id
+    )
+    .execute(&mut *trx)
+    .await?;
+
+    sqlx::query!("DELETE FROM login_attempts WHERE email = $1", email)
+        .execute(&mut *trx)
+        .await?;
+
+    // profile, sessions, drafts and the rest cascade
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id)
+        .execute(&mut *trx)
+        .await?;
+
+    trx.commit().await?;
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+    use crate::{
+        drafts::save_draft,
+        messages::send_message,
+        notifications::{create_notification, NewNotification, NotificationKind},
+        polls::{create_poll, get_poll, get_poll_options, toggle_vote, NewPoll},
+        reports::report_message,
+        rooms::{add_user_to_room, create_room},
+        saved::save_message,
+        sessions::create_session,
+        testing::{database, local_user},
+        uploads::add_upload_and_continue,
+    };
+
+    async fn count(db: &Database, sql: &str, id: &str) -> i64 {
+        sqlx::query_scalar(sql)
+            .bind(id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn upload(db: &Database, user_id: &str, room_id: Option<&str>, url: &str) -> String {
+        let (id, trx) = add_upload_and_continue(
+            db,
+            user_id,
+            room_id.map(str::to_string),
+            Some("file.png".to_string()),
+            Some(url.to_string()),
+        )
+        .await
+        .unwrap();
+        trx.commit().await.unwrap();
+
+        id
+    }
+
+    struct Conversation {
+        ada: String,
+        bob: String,
+        boss: String,
+        message: String,
+        poll: String,
+    }
+
+    /// Ada leaves traces everywhere an account can: messages, uploads, a poll,
+    /// votes, a draft, reports and notifications, with bob reacting to them.
+    async fn conversation(db: &Database) -> Conversation {
+        let ada = local_user(db, "ada").await;
+        let bob = local_user(db, "bob").await;
+        let boss = local_user(db, "boss").await;
+        create_room(db, "lobby", "lobby", "", false, false, &[])
+            .await
+            .unwrap();
+
+        upload(db, &ada, None, "/uploads/ada.png").await;
+        let file = upload(db, &ada, Some("lobby"), "/uploads/chart.png").await;
+        let message = send_message(db, "lobby", &ada, "the chart", &[file])
+            .await
+            .unwrap();
+        let theirs = send_message(db, "lobby", &bob, "nice", &[]).await.unwrap();
+
+        let poll = create_poll(
+            db,
+            "lobby",
+            &ada,
+            &NewPoll {
+                question: "lunch?".to_string(),
+                options: vec!["yes".to_string(), "no".to_string()],
+                is_multi: false,
+                is_anonymous: false,
+                closes_at: None,
+            },
+        )
+        .await
+        .unwrap();
+        let open = get_poll(db, &poll, &ada).await.unwrap();
+        let yes = get_poll_options(db, &open, &ada).await.unwrap()[0]
+            .id
+            .clone();
+        toggle_vote(db, &open, &yes, &ada).await.unwrap();
+        toggle_vote(db, &open, &yes, &bob).await.unwrap();
+
+        save_draft(db, &ada, "lobby", "half a thought", &[])
+            .await
+            .unwrap();
+        save_message(db, &bob, &message, None).await.unwrap();
+        report_message(db, &message, &bob, "off topic")
+            .await
+            .unwrap();
+        report_message(db, &theirs, &ada, "rude").await.unwrap();
+        create_notification(
+            db,
+            &NewNotification {
+                user_id: &bob,
+                kind: NotificationKind::Mention,
+                actor_id: Some(&ada),
+                room_id: Some("lobby"),
+                message_id: Some(&message),
+                body: Some("the chart"),
+            },
+        )
+        .await
+        .unwrap();
+        create_notification(
+            db,
+            &NewNotification {
+                user_id: &ada,
+                kind: NotificationKind::Mention,
+                actor_id: Some(&bob),
+                room_id: Some("lobby"),
+                message_id: Some(&theirs),
+                body: Some("nice"),
+            },
+        )
+        .await
+        .unwrap();
+        create_session(db, &ada, OffsetDateTime::now_utc() + Duration::days(1))
+            .await
+            .unwrap();
+
+        Conversation {
+            ada,
+            bob,
+            boss,
+            message,
+            poll,
+        }
+    }
+
+    #[tokio::test]
+    async fn anonymizing_keeps_the_conversation() {
+        let db = database("delete-anonymize").await;
+        let c = conversation(&db).await;
+
+        let files = delete_user(&db, &c.ada, &c.boss, DeletedContent::Anonymize)
+            .await
+            .unwrap();
+        // only the profile picture leaves the disk
+        assert_eq!(files, vec!["/uploads/ada.png".to_string()]);
+
+        assert_eq!(
+            count(&db, "SELECT COUNT(*) FROM users WHERE id = $1", &c.ada).await,
+            0
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM messages WHERE user_id = $1",
+                DELETED_USER_ID
+            )
+            .await,
+            2
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM uploads WHERE uploaded_by = $1",
+                DELETED_USER_ID
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM poll_votes WHERE poll_id = $1",
+                &c.poll
+            )
+            .await,
+            2
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM saved_messages WHERE message_id = $1",
+                &c.message
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM drafts WHERE user_id = $1",
+                &c.ada
+            )
+            .await,
+            0
+        );
+        // reports and the other side's notifications stay, without the account
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM message_reports WHERE author_id IS NULL AND reporter_id = $1",
+                &c.bob
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM message_reports WHERE reporter_id IS NULL AND author_id = $1",
+                &c.bob
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM notifications WHERE user_id = $1 AND actor_id IS NULL",
+                &c.bob
+            )
+            .await,
+            1
+        );
+
+        // a second anonymized account folds its identical vote into the first
+        delete_user(&db, &c.bob, &c.boss, DeletedContent::Anonymize)
+            .await
+            .unwrap();
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM poll_votes WHERE poll_id = $1",
+                &c.poll
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM messages WHERE user_id = $1",
+                DELETED_USER_ID
+            )
+            .await,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn deleting_removes_the_content() {
+        let db = database("delete-content").await;
+        let c = conversation(&db).await;
+
+        let mut files = delete_user(&db, &c.ada, &c.boss, DeletedContent::Delete)
+            .await
+            .unwrap();
+        files.sort();
+        assert_eq!(files, vec!["/uploads/ada.png", "/uploads/chart.png"]);
+
+        assert_eq!(
+            count(&db, "SELECT COUNT(*) FROM users WHERE id = $1", &c.ada).await,
+            0
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM messages WHERE user_id <> $1",
+                &c.bob
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM message_uploads WHERE message_id = $1",
+                &c.message
+            )
+            .await,
+            0
+        );
+        // the poll goes with its message, bob's vote and bookmark with it
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM poll_votes WHERE poll_id = $1",
+                &c.poll
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM saved_messages WHERE user_id = $1",
+                &c.bob
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM notifications WHERE user_id = $1",
+                &c.bob
+            )
+            .await,
+            0
+        );
+        // reports keep their copy of the message
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM message_reports WHERE message_id = $1",
+                &c.message
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM users WHERE id = $1",
+                DELETED_USER_ID
+            )
+            .await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn private_rooms_keep_a_member() {
+        let db = database("delete-last-member").await;
+        let ada = local_user(&db, "ada").await;
+        let bob = local_user(&db, "bob").await;
+        create_room(
+            &db,
+            "secret",
+            "secret",
+            "",
+            true,
+            false,
+            std::slice::from_ref(&ada),
+        )
+        .await
+        .unwrap();
+        // a conversation with someone gone is fine to leave behind
+        create_room(
+            &db,
+            "dm",
+            "bob",
+            "",
+            true,
+            true,
+            &[ada.clone(), bob.clone()],
+        )
+        .await
+        .unwrap();
+
+        let refused = delete_user(&db, &ada, &bob, DeletedContent::Delete).await;
+        assert!(matches!(
+            refused.map_err(|e| e.downcast::<DeleteUserError>()),
+            Err(Ok(DeleteUserError::LastMemberOf(names))) if names == "#secret"
+        ));
+        assert_eq!(
+            count(&db, "SELECT COUNT(*) FROM users WHERE id = $1", &ada).await,
+            1
+        );
+
+        add_user_to_room(&db, "secret", &bob).await.unwrap();
+        delete_user(&db, &ada, &bob, DeletedContent::Delete)
+            .await
+            .unwrap();
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM user_rooms WHERE room_id = 'secret' AND user_id = $1",
+                &bob
+            )
+            .await,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn deactivating_signs_out_and_leaves_shared_rooms() {
+        let db = database("deactivate").await;
+        let ada = local_user(&db, "ada").await;
+        let bob = local_user(&db, "bob").await;
+        let both = [ada.clone(), bob.clone()];
+        create_room(&db, "team", "team", "", true, false, &both)
+            .await
+            .unwrap();
+        create_room(
+            &db,
+            "notes",
+            "notes",
+            "",
+            true,
+            false,
+            std::slice::from_ref(&ada),
+        )
+        .await
+        .unwrap();
+        create_room(&db, "dm", "bob", "", true, true, &both)
+            .await
+            .unwrap();
+        create_session(&db, &ada, OffsetDateTime::now_utc() + Duration::days(1))
+            .await
+            .unwrap();
+
+        deactivate_user(&db, &ada).await.unwrap();
+
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM users WHERE id = $1 AND is_enabled = FALSE",
+                &ada
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &db,
+                "SELECT COUNT(*) FROM sessions WHERE user_id = $1",
+                &ada
+            )
+            .await,
+            0
+        );
+        let mut rooms: Vec<String> =
+            sqlx::query_scalar("SELECT room_id FROM user_rooms WHERE user_id = $1")
+                .bind(&ada)
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        rooms.sort();
+        // the room only ada was in and the direct message stay reachable
+        assert_eq!(rooms, vec!["dm", "notes"]);
+    }
+}
@@ -1,54 +1,151 @@
 use std::{
     convert::Infallible,
-    sync::{atomic::Ordering, Arc},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
 use anyhow::Result;
 use axum::{
     debug_handler,
-    extract::{Multipart, Path, Query, State},
+    extract::{ConnectInfo, Multipart, Path, Query, State},
+    http::HeaderMap,
     response::{
         sse::{Event, KeepAlive},
         Html, IntoResponse, Sse,
     },
     routing::{get, post},
-    Form, Router,
+    Form, Json, Router,
 };
 use axum_extra::extract::{
     cookie::{Cookie, SameSite},
     CookieJar,
 };
-use axum_htmx::HxRedirect;
+use axum_htmx::{HxRedirect, HxResponseTrigger};
+use commands::Reply;
 use convert_case::{Case, Casing};
-use database::{users::UserCombined, Database};
+use database::{
+    audit::AuditAction,
+    login_attempts::LoginOutcome,
+    notifications::{NewNotification, NotificationKind},
+    reports::{ReportError, Resolution},
+    roles::Capability,
+    users::UserCombined,
+    Database,
+};
 use futures::TryStreamExt;
 use minijinja::context;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use rooms::{RoomEvent, UserEvent};
 use time::{Duration, OffsetDateTime};
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt as _;
-use users::LoginForm;
+use users::{auth::AuthError, LoginForm};
 
-use crate::{FrontendError, FrontendState};
+use crate::{audit, moderation, notifications, permissions, FrontendError, FrontendState};
 
 pub fn setup_api(state: Arc<FrontendState>) -> Router {
     Router::new()
         .route("/register", post(handle_registration))
         .route("/login", post(handle_login))
+        .route("/login/2fa", post(handle_login_two_factor))
+        .route("/logout", post(handle_logout))
+        .route("/2fa/setup", post(handle_two_factor_setup))
+        .route("/2fa/enable", post(handle_two_factor_enable))
+        .route(
+            "/2fa/recovery-codes",
+            post(handle_two_factor_recovery_codes),
+        )
+        .route("/2fa/disable", post(handle_two_factor_disable))
+        .route(
+            "/settings/two-factor-policy",
+            post(handle_two_factor_policy),
+        )
+        .route("/password/forgot", post(handle_forgot_password))
+        .route("/password/reset/:token", post(handle_reset_password))
+        .route(
+            "/sessions/revoke-others",
+            post(handle_revoke_other_sessions),
+        )
         .route("/create-room", post(handle_create_room))
-        .route("/reset-register-link", post(handle_reset_register_link))
+        .route("/invites", post(handle_create_invite))
+        .route("/invites/:inviteid/revoke", post(handle_revoke_invite))
+        .route("/roles", post(handle_create_role))
+        .route("/roles/:roleid", post(handle_update_role))
+        .route("/roles/:roleid/delete", post(handle_delete_role))
+        .route("/workspace-links", get(handle_get_workspace_links))
+        .route("/audit-log", get(handle_get_audit_log))
         .route("/search-user", get(handle_search_users))
+        .route("/commands", post(handle_create_command))
+        .route("/commands/:name/delete", post(handle_delete_command))
+        .route("/bots", post(handle_create_bot))
+        .route("/bots/:userid/token", post(handle_rotate_bot_token))
         .route("/create-user-room", post(handle_create_user_room))
         .route("/user/update/password", post(handle_update_user_password))
         .route("/user/update/profile", post(handle_update_user_profile))
         .route("/user/update/image", post(handle_update_user_image))
         .route("/user/update/image-none", post(handle_delete_user_image))
+        .route("/user/update/quiet-hours", post(handle_update_quiet_hours))
+        .route("/user/update/digest", post(handle_update_digest))
         .route("/users/:userid/enabled", post(handle_enable_user))
+        .route("/users/:userid/delete", post(handle_delete_user))
+        .route("/users/:userid/card", get(handle_get_profile_card))
+        .route("/users/:userid/block", post(handle_block_user))
+        .route("/users/:userid/mute", post(handle_mute_user))
+        .route("/approvals/pending", get(handle_get_pending_approvals))
+        .route("/approvals/:userid/approve", post(handle_approve_user))
+        .route("/approvals/:userid/reject", post(handle_reject_user))
         .route("/users/:userid/admin", post(handle_user_admin))
+        .route("/users/:userid/roles", post(handle_set_user_roles))
+        .route("/users/:userid/guest", post(handle_set_guest_expiry))
+        .route("/users/:userid/member", post(handle_convert_guest))
+        .route("/users/:userid/reset-link", post(handle_admin_reset_link))
+        .route(
+            "/users/:userid/2fa/reset",
+            post(handle_admin_two_factor_reset),
+        )
         .route("/room/:roomid", get(handle_join_room))
         .route("/room/:roomid/upload", post(handle_upload_to_room))
         .route("/room/:roomid/send", post(handle_send_message))
+        .route("/room/:roomid/draft", post(handle_save_draft))
+        .route(
+            "/room/:roomid/notifications",
+            post(handle_set_room_notifications),
+        )
+        .route("/activity", get(handle_get_activity))
+        .route("/activity/stream", get(handle_activity_stream))
+        .route("/notifications", get(handle_get_notifications))
+        .route("/notifications/count", get(handle_get_notification_count))
+        .route(
+            "/notifications/read-all",
+            post(handle_mark_all_notifications_read),
+        )
+        .route(
+            "/notifications/:notificationid/read",
+            post(handle_mark_notification_read),
+        )
+        .route("/push/devices", get(handle_get_push_devices))
+        .route("/push/subscribe", post(handle_push_subscribe))
+        .route(
+            "/push/:subscriptionid/delete",
+            post(handle_delete_push_device),
+        )
+        .route("/drafts", get(handle_get_drafts))
+        .route("/message/:messageid/save", post(handle_save_message))
+        .route("/message/:messageid/unsave", post(handle_unsave_message))
+        .route("/message/:messageid/report", post(handle_report_message))
+        .route("/reports/pending", get(handle_get_pending_reports))
+        .route("/reports/:reportid/resolve", post(handle_resolve_report))
+        .route("/saved", get(handle_get_saved))
+        .route("/saved/due", get(handle_get_saved_due))
         .route("/room/:roomid/more", get(handle_pagination))
+        .route("/room/:roomid/poll", post(handle_create_poll))
+        .route("/poll/:pollid", get(handle_get_poll))
+        .route("/poll/:pollid/vote/:optionid", post(handle_poll_vote))
+        .route("/poll/:pollid/close", post(handle_close_poll))
         .route("/room/:roomid/add/:userid", post(handle_add_user_to_room))
         .route(
             "/room/:roomid/remove/:userid",
@@ -57,427 +154,3327 @@ pub fn setup_api(state: Arc<FrontendState>) -> Router {
         .with_state(state)
 }
 
+#[derive(serde::Deserialize)]
+struct NewInviteForm {
+    email: Option<String>,
+    max_uses: Option<i64>,
+    expires_in_days: Option<i64>,
+    is_admin: Option<bool>,
+    auto_approve: Option<bool>,
+    is_guest: Option<bool>,
+    // no end date when empty
+    guest_days: Option<String>,
+    #[serde(default)]
+    rooms: Vec<String>,
+}
+
 #[debug_handler]
-async fn handle_reset_register_link(
+async fn handle_create_invite(
     jar: CookieJar,
     State(state): State<Arc<FrontendState>>,
+    axum_extra::extract::Form(form): axum_extra::extract::Form<NewInviteForm>, // extra::form to read array values
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    if !user.is_admin {
-        return Err(FrontendError::NoPermission);
+    permissions::require(&state, &user, Capability::ManageInvites).await?;
+
+    // an invite can only hand out what its creator could grant
+    if form.is_admin.unwrap_or(false) {
+        permissions::require(&state, &user, Capability::GrantAdmin).await?;
+    }
+    if form.auto_approve.unwrap_or(false) {
+        permissions::require(&state, &user, Capability::ApproveUsers).await?;
     }
 
-    let mut regid = state.register_id.write();
-    *regid = get_random_alphanumeric();
+    if form.max_uses.is_some_and(|uses| uses < 1) {
+        return Err(FrontendError::InvalidForm(
+            "an invite needs at least one use".to_string(),
+        ));
+    }
 
-    let regid = regid.to_string();
+    let expires_at = match form.expires_in_days {
+        Some(days) if !(1..=365).contains(&days) => {
+            return Err(FrontendError::InvalidForm(
+                "invites expire after 1 to 365 days".to_string(),
+            ));
+        }
+        Some(days) => Some(OffsetDateTime::now_utc() + Duration::days(days)),
+        None => None,
+    };
 
-    let output = state.templates.render_template(
-        "components/invite.jinja2",
-        context! {
-            register_id => regid,
-        },
-    )?;
+    let is_guest = form.is_guest.unwrap_or(false);
+    if is_guest {
+        if form.is_admin.unwrap_or(false) {
+            return Err(FrontendError::InvalidForm(
+                "guests cannot be admins".to_string(),
+            ));
+        }
 
-    Ok(Html(output).into_response())
-}
+        // a guest sees nothing but the invite's rooms
+        if form.rooms.is_empty() {
+            return Err(FrontendError::InvalidForm(
+                "pick the rooms guests may see".to_string(),
+            ));
+        }
+    }
 
-#[derive(serde::Deserialize)]
-struct Allow {
-    value: bool,
+    // only rooms the form offered, which leaves out conversations and rooms the creator cannot see
+    let allowed = invite_rooms(&state, &user.id).await?;
+    if let Some(room) = form
+        .rooms
+        .iter()
+        .find(|room| !allowed.iter().any(|r| &r.id == *room))
+    {
+        return Err(FrontendError::InvalidForm(format!(
+            "cannot invite people to room {}",
+            room
+        )));
+    }
+
+    let guest_days = match form.guest_days.as_deref().filter(|_| is_guest) {
+        Some(days) => parse_guest_days(days)?,
+        None => None,
+    };
+
+    let invite = database::invites::NewInvite {
+        email: form
+            .email
+            .map(|email| email.trim().to_string())
+            .filter(|email| !email.is_empty()),
+        max_uses: form.max_uses,
+        expires_at,
+        is_admin: form.is_admin.unwrap_or(false),
+        auto_approve: form.auto_approve.unwrap_or(false),
+        is_guest,
+        guest_days,
+        rooms: form.rooms,
+    };
+
+    let token = get_random_string(32);
+
+    let invite_id = database::invites::create_invite(
+        &state.db,
+        &users::hash_api_token(&token),
+        &invite,
+        &user.id,
+    )
+    .await
+    .map_err(FrontendError::InternalError)?;
+
+    // the id ties the entry to a later revocation
+    let details = [
+        Some(invite_id.as_str()),
+        invite.email.as_deref(),
+        invite.is_admin.then_some("admin"),
+        invite.is_guest.then_some("guest"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<&str>>()
+    .join(", ");
+    audit::record(
+        &state,
+        &user,
+        AuditAction::InviteCreated,
+        None,
+        Some(&details),
+    )
+    .await;
+
+    let link = format!("{}/register/{}", state.public_url, token);
+
+    render_invites(&state, &user, Some(link)).await
 }
 
 #[debug_handler]
-async fn handle_user_admin(
+async fn handle_revoke_invite(
     jar: CookieJar,
-    Path(userid): Path<String>,
-    allow: Query<Allow>,
+    Path(inviteid): Path<String>,
     State(state): State<Arc<FrontendState>>,
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    if !user.is_admin {
-        return Err(FrontendError::NoPermission);
+    permissions::require(&state, &user, Capability::ManageInvites).await?;
+
+    database::invites::revoke_invite(&state.db, &inviteid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::InviteRevoked,
+        None,
+        Some(&inviteid),
+    )
+    .await;
+
+    render_invites(&state, &user, None).await
+}
+
+fn parse_guest_days(value: &str) -> Result<Option<i64>, FrontendError> {
+    if value.trim().is_empty() {
+        return Ok(None);
     }
 
-    if user.id == userid {
-        // not allowed to enable/disable self
-        return Err(FrontendError::NoPermission);
+    match value.trim().parse::<i64>() {
+        Ok(days @ 1..=365) => Ok(Some(days)),
+        _ => Err(FrontendError::InvalidForm(
+            "guest access lasts 1 to 365 days".to_string(),
+        )),
     }
+}
 
-    database::users::make_admin_user(&state.db, &userid, allow.value)
+/// Rooms an invite can add people to, conversations between users are left out.
+pub(crate) async fn invite_rooms(
+    state: &FrontendState,
+    user_id: &str,
+) -> Result<Vec<database::rooms::Room>, FrontendError> {
+    let (_, rooms) = database::rooms::get_rooms(&state.db, user_id)
         .await
         .map_err(FrontendError::InternalError)?;
 
-    let template = "components/user-buttons-admin.jinja2";
+    Ok(rooms)
+}
+
+async fn render_invites(
+    state: &Arc<FrontendState>,
+    user: &UserCombined,
+    invite_link: Option<String>,
+) -> Result<Html<String>, FrontendError> {
+    let invites = database::invites::get_invites(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
 
+    let invite_rooms = invite_rooms(state, &user.id).await?;
+    let capabilities = permissions::capabilities(state, user).await?;
+
+    // like bot tokens only a hash is stored, the link is shown this once
     let output = state.templates.render_template(
-        template,
-        context! {
-            is_admin => allow.value,
-            userid => userid,
-        },
+        "components/invites.jinja2",
+        context! { invites => invites, invite_rooms => invite_rooms, invite_link => invite_link, capabilities => capabilities },
     )?;
 
-    Ok(Html(output).into_response())
+    Ok(Html(output))
 }
 
-#[derive(serde::Deserialize, Debug)]
-#[serde(untagged)]
-enum UserList {
-    Single(String),
-    Many(Vec<String>),
+#[derive(serde::Deserialize)]
+struct NewRoleForm {
+    name: String,
+    #[serde(default)]
+    capabilities: Vec<String>,
 }
 
-#[derive(serde::Deserialize, Debug)]
-struct UserRoomForm {
-    user: UserList,
+#[derive(serde::Deserialize)]
+struct RoleCapabilitiesForm {
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+fn capability_names(capabilities: &[Capability]) -> String {
+    capabilities
+        .iter()
+        .map(|c| c.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+fn parse_capabilities(values: &[String]) -> Result<Vec<Capability>, FrontendError> {
+    values
+        .iter()
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            Capability::parse(value)
+                .ok_or_else(|| FrontendError::InvalidForm(format!("unknown capability {}", value)))
+        })
+        .collect()
 }
 
 #[debug_handler]
-async fn handle_create_user_room(
+async fn handle_create_role(
     jar: CookieJar,
     State(state): State<Arc<FrontendState>>,
-    axum_extra::extract::Form(form): axum_extra::extract::Form<UserRoomForm>, // extra::form to read array values
+    axum_extra::extract::Form(form): axum_extra::extract::Form<NewRoleForm>, // extra::form to read array values
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    let mut users = match form.user {
-        UserList::Single(item) => vec![item],
-        UserList::Many(items) => items,
+    permissions::require(&state, &user, Capability::GrantAdmin).await?;
+
+    let capabilities = parse_capabilities(&form.capabilities)?;
+
+    let role_id = database::roles::create_role(&state.db, form.name.trim(), &capabilities)
+        .await
+        .map_err(|e| match e.downcast::<database::roles::RoleError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        })?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::RoleCreated,
+        Some(&role_id),
+        Some(&capability_names(&capabilities)),
+    )
+    .await;
+
+    render_roles(&state).await
+}
+
+#[debug_handler]
+async fn handle_update_role(
+    jar: CookieJar,
+    Path(roleid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    axum_extra::extract::Form(form): axum_extra::extract::Form<RoleCapabilitiesForm>, // extra::form to read array values
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
     };
 
-    let userids = users
-        .iter()
-        .map(|u| format!(r#"'{}'"#, u))
-        .collect::<Vec<String>>()
-        .join(",");
+    permissions::require(&state, &user, Capability::GrantAdmin).await?;
 
-    let usernames = database::users::get_usernames_by_id(&state.db, &userids)
+    let capabilities = parse_capabilities(&form.capabilities)?;
+
+    database::roles::set_role_capabilities(&state.db, &roleid, &capabilities)
         .await
-        .map_err(FrontendError::InternalError)?
-        .join(", ");
+        .map_err(FrontendError::InternalError)?;
 
-    // add self to it
-    users.push(user.id);
-    users.sort_unstable();
-    users.dedup();
+    audit::record(
+        &state,
+        &user,
+        AuditAction::RoleChanged,
+        Some(&roleid),
+        Some(&capability_names(&capabilities)),
+    )
+    .await;
 
-    if users.is_empty() {
-        return Err(FrontendError::InvalidForm(
-            "needs at least one user!".into(),
-        ));
-    }
+    render_roles(&state).await
+}
 
-    let room_id = users.join("-");
+#[debug_handler]
+async fn handle_delete_role(
+    jar: CookieJar,
+    Path(roleid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
 
-    let room_id =
-        database::rooms::create_room(&state.db, &room_id, &usernames, "", true, true, &users)
-            .await
-            .map_err(FrontendError::InternalError)?;
+    permissions::require(&state, &user, Capability::GrantAdmin).await?;
 
-    Ok((
-        HxRedirect(format!("/chatroom/{}", room_id).parse().unwrap()),
-        "",
+    // the name is gone along with the role
+    let name = database::roles::get_roles(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?
+        .into_iter()
+        .find(|role| role.id == roleid)
+        .map(|role| role.name);
+
+    database::roles::delete_role(&state.db, &roleid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::RoleDeleted,
+        Some(&roleid),
+        name.as_deref(),
     )
-        .into_response())
+    .await;
+
+    render_roles(&state).await
+}
+
+async fn render_roles(state: &Arc<FrontendState>) -> Result<Html<String>, FrontendError> {
+    let roles = database::roles::get_roles(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/roles.jinja2",
+        context! { roles => roles, all_capabilities => permissions::all_capabilities() },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct UserRolesForm {
+    #[serde(default)]
+    roles: Vec<String>,
 }
 
 #[debug_handler]
-async fn handle_enable_user(
+async fn handle_set_user_roles(
     jar: CookieJar,
     Path(userid): Path<String>,
-    allow: Query<Allow>,
     State(state): State<Arc<FrontendState>>,
+    axum_extra::extract::Form(form): axum_extra::extract::Form<UserRolesForm>, // extra::form to read array values
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::GrantAdmin).await?;
+
+    database::roles::set_user_roles(&state.db, &userid, &form.roles)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::UserRolesChanged,
+        Some(&userid),
+        None,
+    )
+    .await;
+
+    Ok(Html(""))
+}
+
+/// A page of audit log entries for the filters in the query.
+#[debug_handler]
+async fn handle_get_audit_log(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Query(query): Query<audit::AuditLogQuery>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ViewAuditLog).await?;
+
+    let output = state.templates.render_template(
+        "components/audit-log-entries.jinja2",
+        audit::entries_context(&state, &query).await?,
+    )?;
+
+    Ok(Html(output))
+}
+
+/// Sidebar links to the workspace pages the user may open.
+#[debug_handler]
+async fn handle_get_workspace_links(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let output = state.templates.render_template(
+        "components/workspace-links.jinja2",
+        context! { capabilities => permissions::capabilities(&state, &user).await? },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct NewCommand {
+    name: String,
+    description: String,
+    url: String,
+}
+
+#[debug_handler]
+async fn handle_create_command(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<NewCommand>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    let name = form.name.trim_start_matches('/').to_lowercase();
+    if !commands::is_valid_name(&name) || state.commands.is_builtin(&name) {
+        return Err(FrontendError::InvalidForm(format!(
+            "invalid command name: {}",
+            name
+        )));
+    }
+
+    if !form.url.starts_with("http://") && !form.url.starts_with("https://") {
+        return Err(FrontendError::InvalidForm(format!(
+            "invalid command url: {}",
+            form.url
+        )));
+    }
+
+    database::commands::create_command(
+        &state.db,
+        &name,
+        &form.description,
+        &form.url,
+        &get_random_string(32),
+        &user.id,
+    )
+    .await
+    .map_err(FrontendError::InternalError)?;
+
+    render_commands(&state).await
+}
+
+#[debug_handler]
+async fn handle_delete_command(
+    jar: CookieJar,
+    Path(name): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    database::commands::delete_command(&state.db, &name)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    render_commands(&state).await
+}
+
+async fn render_commands(state: &Arc<FrontendState>) -> Result<Html<String>, FrontendError> {
+    let commands = database::commands::get_commands(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/commands.jinja2",
+        context! { commands => commands },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct NewBot {
+    name: String,
+}
+
+#[debug_handler]
+async fn handle_create_bot(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<NewBot>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    if form.name.trim().is_empty() {
+        return Err(FrontendError::InvalidForm("missing name field".to_string()));
+    }
+
+    let token = get_random_string(40);
+
+    let bot_id = database::bots::create_bot(
+        &state.db,
+        form.name.trim(),
+        &users::hash_api_token(&token),
+        &user.id,
+    )
+    .await
+    .map_err(|e| match e.downcast::<database::handles::HandleError>() {
+        Ok(e) => FrontendError::InvalidForm(e.to_string()),
+        Err(e) => FrontendError::InternalError(e),
+    })?;
+
+    audit::record(&state, &user, AuditAction::BotCreated, Some(&bot_id), None).await;
+
+    render_bots(&state, Some(token)).await
+}
+
+#[debug_handler]
+async fn handle_rotate_bot_token(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    let token = get_random_string(40);
+
+    database::bots::set_bot_token(&state.db, &userid, &users::hash_api_token(&token))
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::BotTokenRotated,
+        Some(&userid),
+        None,
+    )
+    .await;
+
+    render_bots(&state, Some(token)).await
+}
+
+async fn render_bots(
+    state: &Arc<FrontendState>,
+    token: Option<String>,
+) -> Result<Html<String>, FrontendError> {
+    let bots = database::bots::get_bots(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    // the plain token is only ever shown in this response
+    let output = state.templates.render_template(
+        "components/bots.jinja2",
+        context! { bots => bots, token => token },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct Allow {
+    value: bool,
+}
+
+#[debug_handler]
+async fn handle_user_admin(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    allow: Query<Allow>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::GrantAdmin).await?;
+
+    if user.id == userid {
+        // not allowed to enable/disable self
+        return Err(FrontendError::NoPermission);
+    }
+
+    database::users::make_admin_user(&state.db, &userid, allow.value)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let action = if allow.value {
+        AuditAction::AdminGranted
+    } else {
+        AuditAction::AdminRevoked
+    };
+    audit::record(&state, &user, action, Some(&userid), None).await;
+
+    let template = "components/user-buttons-admin.jinja2";
+
+    let output = state.templates.render_template(
+        template,
+        context! {
+            is_admin => allow.value,
+            userid => userid,
+            capabilities => permissions::capabilities(&state, &user).await?,
+        },
+    )?;
+
+    Ok(Html(output).into_response())
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(untagged)]
+enum UserList {
+    Single(String),
+    Many(Vec<String>),
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct UserRoomForm {
+    user: UserList,
+}
+
+#[debug_handler]
+async fn handle_create_user_room(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    axum_extra::extract::Form(form): axum_extra::extract::Form<UserRoomForm>, // extra::form to read array values
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    // guests talk in the rooms they were invited to, nowhere else
+    if user.is_guest {
+        return Err(FrontendError::NoPermission);
+    }
+
+    let mut users = match form.user {
+        UserList::Single(item) => vec![item],
+        UserList::Many(items) => items,
+    };
+
+    let userids = users
+        .iter()
+        .map(|u| format!(r#"'{}'"#, u))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let usernames = database::users::get_usernames_by_id(&state.db, &userids)
+        .await
+        .map_err(FrontendError::InternalError)?
+        .join(", ");
+
+    for other in &users {
+        database::blocks::ensure_not_blocked_by(&state.db, &user.id, other)
+            .await
+            .map_err(blocked_error)?;
+    }
+
+    // add self to it
+    users.push(user.id);
+    users.sort_unstable();
+    users.dedup();
+
+    if users.is_empty() {
+        return Err(FrontendError::InvalidForm(
+            "needs at least one user!".into(),
+        ));
+    }
+
+    let room_id = users.join("-");
+
+    let room_id =
+        database::rooms::create_room(&state.db, &room_id, &usernames, "", true, true, &users)
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+    Ok((
+        HxRedirect(format!("/chatroom/{}", room_id).parse().unwrap()),
+        "",
+    )
+        .into_response())
+}
+
+#[debug_handler]
+async fn handle_enable_user(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    allow: Query<Allow>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ApproveUsers).await?;
+
+    if user.id == userid {
+        // not allowed to enable/disable self
+        return Err(FrontendError::NoPermission);
+    }
+
+    let otheruser = database::users::get_user_with_profile(&state.db, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    // approvers look after regular accounts, taking an admin out takes an admin
+    if !allow.value && otheruser.is_admin {
+        permissions::require_admin(&user)?;
+    }
+
+    // enabling someone still waiting in the queue approves them
+    if allow.value {
+        database::approvals::enable_account(&state.db, &userid, &user.id).await
+    } else {
+        database::users::deactivate_user(&state.db, &userid).await
+    }
+    .map_err(FrontendError::InternalError)?;
+
+    let action = if allow.value {
+        AuditAction::UserEnabled
+    } else {
+        AuditAction::UserDisabled
+    };
+    audit::record(&state, &user, action, Some(&userid), None).await;
+
+    let template = if allow.value {
+        "components/user-buttons-enabled.jinja2"
+    } else {
+        "components/user-buttons-disabled.jinja2"
+    };
+
+    let output = state.templates.render_template(
+        template,
+        context! {
+            is_admin => otheruser.is_admin,
+            userid => userid,
+            capabilities => permissions::capabilities(&state, &user).await?,
+            user => user,
+        },
+    )?;
+
+    Ok(Html(output).into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct GuestExpiryForm {
+    // no end date when empty
+    guest_days: Option<String>,
+}
+
+#[debug_handler]
+async fn handle_set_guest_expiry(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<GuestExpiryForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ApproveUsers).await?;
+
+    let expires_at = parse_guest_days(form.guest_days.as_deref().unwrap_or_default())?
+        .map(|days| OffsetDateTime::now_utc() + Duration::days(days));
+
+    database::guests::set_guest_expiry(&state.db, &userid, expires_at)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let details = match expires_at {
+        Some(at) => format!("until {}", at.date()),
+        None => "no end date".to_string(),
+    };
+    audit::record(
+        &state,
+        &user,
+        AuditAction::GuestAccessChanged,
+        Some(&userid),
+        Some(&details),
+    )
+    .await;
+
+    let item = database::users::get_user_with_profile(&state.db, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state
+        .templates
+        .render_template("components/user-guest.jinja2", context! { item => item })?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_convert_guest(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ApproveUsers).await?;
+
+    database::guests::convert_to_member(&state.db, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::GuestConverted,
+        Some(&userid),
+        None,
+    )
+    .await;
+
+    let item = database::users::get_user_with_profile(&state.db, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state
+        .templates
+        .render_template("components/user-guest.jinja2", context! { item => item })?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct DeleteUser {
+    // anonymize or delete
+    content: String,
+}
+
+#[debug_handler]
+async fn handle_delete_user(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<DeleteUser>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    if user.id == userid || userid == database::users::DELETED_USER_ID {
+        return Err(FrontendError::NoPermission);
+    }
+
+    let content = match form.content.as_str() {
+        "anonymize" => database::users::DeletedContent::Anonymize,
+        "delete" => database::users::DeletedContent::Delete,
+        _ => {
+            return Err(FrontendError::InvalidForm(
+                "choose whether to anonymize or delete the content".to_string(),
+            ))
+        }
+    };
+
+    // the handle goes along with the account
+    let otheruser = database::users::get_user_with_profile(&state.db, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let files = database::users::delete_user(&state.db, &userid, &user.id, content)
+        .await
+        .map_err(|e| match e.downcast::<database::users::DeleteUserError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        })?;
+
+    tracing::info!("{} deleted user {} ({:?})", user.id, userid, content);
+    audit::record(
+        &state,
+        &user,
+        AuditAction::UserDeleted,
+        Some(&userid),
+        Some(&format!("{}, content {}", otheruser.username, form.content)),
+    )
+    .await;
+
+    for file in files {
+        let path = std::path::Path::new(&state.uploads_path).join(&file);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("failed to remove upload {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    // swaps the user out of the list
+    Ok(Html(""))
+}
+
+const APPROVALS_CHANGED: &str = "approvals-changed";
+
+#[debug_handler]
+async fn handle_get_pending_approvals(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ApproveUsers).await?;
+
+    let pending = database::approvals::count_pending(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/approvals-pending.jinja2",
+        context! { pending => pending },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_approve_user(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ApproveUsers).await?;
+
+    database::approvals::approve_user(&state.db, &userid, &user.id)
+        .await
+        .map_err(
+            |e| match e.downcast::<database::approvals::ApprovalError>() {
+                Ok(e) => FrontendError::InvalidForm(e.to_string()),
+                Err(e) => FrontendError::InternalError(e),
+            },
+        )?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::UserApproved,
+        Some(&userid),
+        None,
+    )
+    .await;
+
+    if let Err(e) = state
+        .room_manager
+        .notify(&NewNotification {
+            user_id: &userid,
+            kind: NotificationKind::Approved,
+            actor_id: Some(&user.id),
+            room_id: None,
+            message_id: None,
+            body: None,
+        })
+        .await
+    {
+        tracing::warn!("failed to notify approved user: {}", e);
+    }
+
+    send_approval_decision(
+        &state,
+        &userid,
+        format!(
+            "your SpeakWith account was approved, you can sign in now:\n\n{}/login\n",
+            state.public_url
+        ),
+    )
+    .await?;
+
+    render_approvals(&state).await
+}
+
+#[derive(serde::Deserialize)]
+struct Rejection {
+    reason: Option<String>,
+}
+
+#[debug_handler]
+async fn handle_reject_user(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<Rejection>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ApproveUsers).await?;
+
+    if user.id == userid {
+        return Err(FrontendError::NoPermission);
+    }
+
+    let reason = form
+        .reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+
+    database::approvals::reject_user(&state.db, &userid, &user.id, reason.as_deref())
+        .await
+        .map_err(
+            |e| match e.downcast::<database::approvals::ApprovalError>() {
+                Ok(e) => FrontendError::InvalidForm(e.to_string()),
+                Err(e) => FrontendError::InternalError(e),
+            },
+        )?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::UserRejected,
+        Some(&userid),
+        reason.as_deref(),
+    )
+    .await;
+
+    send_approval_decision(
+        &state,
+        &userid,
+        match &reason {
+            Some(reason) => format!(
+                "your registration on SpeakWith was declined:\n\n{}\n",
+                reason
+            ),
+            None => "your registration on SpeakWith was declined.\n".to_string(),
+        },
+    )
+    .await?;
+
+    render_approvals(&state).await
+}
+
+// tells the user how an admin decided, when there is a way to reach them
+async fn send_approval_decision(
+    state: &FrontendState,
+    user_id: &str,
+    message: String,
+) -> Result<(), FrontendError> {
+    let Some(mailer) = &state.mailer else {
+        return Ok(());
+    };
+
+    let user = database::users::get_user_with_profile(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let mail = mailer::Mail {
+        to: user.email,
+        subject: "Your SpeakWith registration".to_string(),
+        body: format!("Hi {},\n\n{}", user.username, message),
+        html: None,
+    };
+
+    if let Err(e) = mailer.send(&mail).await {
+        tracing::error!("failed to send approval decision mail: {}", e);
+    }
+
+    Ok(())
+}
+
+async fn render_approvals(state: &FrontendState) -> Result<impl IntoResponse, FrontendError> {
+    let pending = database::approvals::get_pending_users(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/approvals-list.jinja2",
+        context! { pending => pending },
+    )?;
+
+    Ok((HxResponseTrigger::normal([APPROVALS_CHANGED]), Html(output)))
+}
+
+// lets the moderation link refresh its count
+const REPORTS_CHANGED: &str = "reports-changed";
+
+#[derive(serde::Deserialize)]
+struct ReportForm {
+    reason: String,
+}
+
+#[debug_handler]
+async fn handle_report_message(
+    jar: CookieJar,
+    Path(messageid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<ReportForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::reports::report_message(&state.db, &messageid, &user.id, form.reason.trim())
+        .await
+        .map_err(|e| match e.downcast::<ReportError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::NotFound(e.to_string()),
+        })?;
+
+    let output = state
+        .templates
+        .render_template("components/report-sent.jinja2", context! {})?;
+
+    Ok((HxResponseTrigger::normal([REPORTS_CHANGED]), Html(output)))
+}
+
+#[debug_handler]
+async fn handle_get_pending_reports(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ModerateMessages).await?;
+
+    let pending = database::reports::count_pending(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/reports-pending.jinja2",
+        context! { pending => pending },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct ResolveForm {
+    action: String,
+    note: Option<String>,
+}
+
+#[debug_handler]
+async fn handle_resolve_report(
+    jar: CookieJar,
+    Path(reportid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<ResolveForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require(&state, &user, Capability::ModerateMessages).await?;
+
+    let Some(resolution) = Resolution::parse(&form.action) else {
+        return Err(FrontendError::InvalidForm(format!(
+            "unknown action: {}",
+            form.action
+        )));
+    };
+
+    let note = form
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+
+    let report = database::reports::get_report(&state.db, &reportid)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    if report.resolution.is_some() {
+        return Err(FrontendError::InvalidForm(
+            ReportError::AlreadyResolved.to_string(),
+        ));
+    }
+
+    // claim the report before acting so two moderators cannot both act on it
+    database::reports::resolve_report(&state.db, &reportid, resolution, &user.id, note)
+        .await
+        .map_err(|e| match e.downcast::<ReportError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        })?;
+
+    let acted = async {
+        Ok(match resolution {
+            Resolution::Dismissed => AuditAction::ReportDismissed,
+            Resolution::MessageDeleted => {
+                database::messages::delete_message(&state.db, &report.message_id)
+                    .await
+                    .map_err(FrontendError::InternalError)?;
+                AuditAction::MessageDeleted
+            }
+            Resolution::UserWarned => {
+                moderation::warn_author(&state, &user, &report, note).await?;
+                AuditAction::UserWarned
+            }
+            Resolution::UserDisabled => {
+                let Some(author_id) = &report.author_id else {
+                    return Err(FrontendError::InvalidForm(
+                        "the author no longer has an account".into(),
+                    ));
+                };
+
+                if *author_id == user.id {
+                    return Err(FrontendError::NoPermission);
+                }
+
+                let author = database::users::get_user_with_profile(&state.db, author_id)
+                    .await
+                    .map_err(FrontendError::InternalError)?;
+
+                // same rule as disabling from the user list
+                if author.is_admin {
+                    permissions::require_admin(&user)?;
+                }
+
+                database::users::deactivate_user(&state.db, author_id)
+                    .await
+                    .map_err(FrontendError::InternalError)?;
+                AuditAction::UserDisabled
+            }
+        })
+    };
+
+    let action = match acted.await {
+        Ok(action) => action,
+        Err(e) => {
+            if let Err(err) = database::reports::reopen_report(&state.db, &reportid).await {
+                tracing::warn!("could not reopen report {}: {}", reportid, err);
+            }
+            return Err(e);
+        }
+    };
+
+    let details = format!("reported for: {}", report.reason);
+    audit::record(
+        &state,
+        &user,
+        action,
+        report.author_id.as_deref(),
+        Some(&details),
+    )
+    .await;
+
+    let output = state.templates.render_template(
+        "components/reports-list.jinja2",
+        moderation::queue_context(&state).await?,
+    )?;
+
+    Ok((HxResponseTrigger::normal([REPORTS_CHANGED]), Html(output)))
+}
+
+#[derive(serde::Deserialize)]
+struct Pagination {
+    page: i32,
+}
+
+#[debug_handler]
+async fn handle_pagination(
+    jar: CookieJar,
+    Path(roomid): Path<String>,
+    pagination: Query<Pagination>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let room = database::rooms::get_room(&state.db, &roomid, &user.id)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    let page = pagination.page;
+
+    let messages = state
+        .room_manager
+        .get_room_messages(&roomid, page, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let next_page = if messages.len() < database::messages::MAX_FETCH as usize {
+        0
+    } else {
+        page + 1
+    };
+
+    let output = state.templates.render_template(
+        "components/message-list.jinja2",
+        context! {
+            roomid => roomid, currentRoom => room, messages => messages, page => next_page
+        },
+    )?;
+
+    Ok(Html(output).into_response())
+}
+
+const MUTES_REFRESH: std::time::Duration = std::time::Duration::from_secs(30);
+// below the delay before the sidebar refreshes its activity, which then already sees the read
+const READ_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[debug_handler]
+async fn handle_join_room(
+    jar: CookieJar,
+    Path(roomid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    tracing::info!("connected to {} as {}", roomid, user.username);
+
+    let rcv = state
+        .room_manager
+        .join_room(roomid, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let muted = database::blocks::get_muted_ids(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+    // mutes rarely change while connected, they are reloaded once the copy gets old
+    let mutes = Arc::new(parking_lot::Mutex::new((Instant::now(), muted)));
+    let read_pending = Arc::new(AtomicBool::new(false));
+
+    let user_id = user.id;
+    let viewer_id = user_id.clone();
+    let db = state.db.clone();
+    let ss = BroadcastStream::new(rcv)
+        .filter_map(|c| c.ok())
+        .then(move |event| {
+            let db = db.clone();
+            let viewer_id = viewer_id.clone();
+            let mutes = mutes.clone();
+            let read_pending = read_pending.clone();
+            async move {
+                match event {
+                    RoomEvent::Message(mut c) => {
+                        if mutes.lock().0.elapsed() > MUTES_REFRESH {
+                            match database::blocks::get_muted_ids(&db, &viewer_id).await {
+                                Ok(muted) => *mutes.lock() = (Instant::now(), muted),
+                                Err(e) => tracing::warn!("failed to reload mutes: {}", e),
+                            }
+                        }
+                        c.is_muted = mutes.lock().1.contains(&c.user_id);
+
+                        // seen as it arrives, so it does not show up as unread elsewhere, a
+                        // burst of messages is marked once it is over
+                        if !read_pending.swap(true, Ordering::AcqRel) {
+                            let room_id = c.room_id.clone();
+                            tokio::spawn(async move {
+                                tokio::time::sleep(READ_DEBOUNCE).await;
+                                read_pending.store(false, Ordering::Release);
+                                if let Err(e) =
+                                    database::preferences::mark_read(&db, &viewer_id, &room_id)
+                                        .await
+                                {
+                                    tracing::warn!("failed to mark {} read: {}", room_id, e);
+                                }
+                            });
+                        }
+
+                        RoomEvent::Message(c)
+                    }
+                    event => event,
+                }
+            }
+        })
+        .filter_map(move |event| match event {
+            RoomEvent::Message(c) => Some(
+                state
+                    .templates
+                    .render_template("components/message.jinja2", context! { message => c })
+                    .unwrap(),
+            ),
+            RoomEvent::PollUpdated { poll_id } => Some(
+                state
+                    .templates
+                    .render_template(
+                        "components/poll-refresh.jinja2",
+                        context! { poll_id => poll_id },
+                    )
+                    .unwrap(),
+            ),
+            RoomEvent::Ephemeral {
+                user_id: target,
+                message,
+            } if target == user_id => Some(
+                state
+                    .templates
+                    .render_template(
+                        "components/ephemeral.jinja2",
+                        context! { message => message },
+                    )
+                    .unwrap(),
+            ),
+            _ => None,
+        })
+        .map(|rendered| Event::default().event("IncomingMessage").data(rendered))
+        .map(Ok::<Event, Infallible>);
+
+    Ok(Sse::new(ss)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+pub(crate) const ACTIVITY_CHANGED: &str = "activity-changed";
+
+#[debug_handler]
+async fn handle_get_activity(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let activity = database::preferences::get_activity(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/room-activity.jinja2",
+        context! { activity => activity },
+    )?;
+
+    Ok(Html(output))
+}
+
+/// New activity, alerts and notifications for the user across every room, for the sidebar,
+/// desktop notifications and the notification center.
+#[debug_handler]
+async fn handle_activity_stream(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let rcv = state.room_manager.subscribe_user(&user.id);
+
+    let ss = BroadcastStream::new(rcv)
+        .filter_map(|c| c.ok())
+        .map(move |event| match event {
+            UserEvent::Activity { room_id } => Event::default().event("activity").data(room_id),
+            UserEvent::Alert {
+                room_id,
+                sender,
+                message,
+            } => Event::default().event("alert").data(
+                state
+                    .templates
+                    .render_template(
+                        "components/alert.jinja2",
+                        context! { room_id => room_id, sender => sender, message => message },
+                    )
+                    .unwrap(),
+            ),
+            UserEvent::Notification { id } => Event::default().event("notification").data(id),
+        })
+        .map(Ok::<Event, Infallible>);
+
+    Ok(Sse::new(ss)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+pub(crate) const NOTIFICATIONS_CHANGED: &str = "notifications-changed";
+// the notification center shows the latest ones, older ones are only kept
+const NOTIFICATION_LIST_LENGTH: i64 = 50;
+
+#[debug_handler]
+async fn handle_get_notifications(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    render_notifications(&state, &user.id).await
+}
+
+#[debug_handler]
+async fn handle_get_notification_count(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let unread = database::notifications::get_unread_count(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/notification-count.jinja2",
+        context! { unread => unread },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_mark_notification_read(
+    jar: CookieJar,
+    Path(notificationid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::notifications::mark_read(&state.db, &user.id, &notificationid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok((
+        HxResponseTrigger::normal([NOTIFICATIONS_CHANGED]),
+        render_notifications(&state, &user.id).await?,
+    ))
+}
+
+#[debug_handler]
+async fn handle_mark_all_notifications_read(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::notifications::mark_all_read(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok((
+        HxResponseTrigger::normal([NOTIFICATIONS_CHANGED]),
+        render_notifications(&state, &user.id).await?,
+    ))
+}
+
+async fn render_notifications(
+    state: &FrontendState,
+    user_id: &str,
+) -> Result<Html<String>, FrontendError> {
+    let items =
+        database::notifications::get_notifications(&state.db, user_id, NOTIFICATION_LIST_LENGTH)
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/notification-list.jinja2",
+        context! { items => items },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct PushSubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+// the shape of PushSubscription.toJSON() in the browser
+#[derive(serde::Deserialize)]
+struct PushSubscriptionForm {
+    endpoint: String,
+    keys: PushSubscriptionKeys,
+}
+
+#[debug_handler]
+async fn handle_push_subscribe(
+    jar: CookieJar,
+    headers: HeaderMap,
+    State(state): State<Arc<FrontendState>>,
+    Json(form): Json<PushSubscriptionForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    if state.push.is_none() {
+        return Err(FrontendError::InvalidForm(
+            "push notifications are not set up".into(),
+        ));
+    }
+
+    let subscription = push::Subscription {
+        endpoint: form.endpoint,
+        p256dh: form.keys.p256dh,
+        auth: form.keys.auth,
+    };
+
+    // the stand-in push service is served over plain http next to the app
+    let stub = state
+        .push_stub
+        .then(|| format!("{}/push-stub/", state.public_url));
+    subscription
+        .validate(stub.as_deref())
+        .map_err(|e| FrontendError::InvalidForm(e.to_string()))?;
+
+    let user_agent = headers.get("user-agent").and_then(|v| v.to_str().ok());
+
+    database::push::save_subscription(
+        &state.db,
+        &user.id,
+        &subscription.endpoint,
+        &subscription.p256dh,
+        &subscription.auth,
+        user_agent,
+    )
+    .await
+    .map_err(FrontendError::InternalError)?;
+
+    render_push_devices(&state, &user).await
+}
+
+#[debug_handler]
+async fn handle_get_push_devices(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    render_push_devices(&state, &user).await
+}
+
+#[debug_handler]
+async fn handle_delete_push_device(
+    jar: CookieJar,
+    Path(subscriptionid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::push::delete_subscription(&state.db, &user.id, &subscriptionid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    render_push_devices(&state, &user).await
+}
+
+async fn render_push_devices(
+    state: &FrontendState,
+    user: &UserCombined,
+) -> Result<Html<String>, FrontendError> {
+    let devices = database::push::get_subscriptions(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/push-devices.jinja2",
+        context! { devices => devices },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct RoomNotificationsForm {
+    level: Option<String>,
+    // 0 unmutes the room
+    mute_minutes: Option<i64>,
+}
+
+#[debug_handler]
+async fn handle_set_room_notifications(
+    jar: CookieJar,
+    Path(roomid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<RoomNotificationsForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::rooms::get_room(&state.db, &roomid, &user.id)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    if let Some(level) = form.level {
+        let level = database::preferences::NotifyLevel::parse(&level)
+            .ok_or_else(|| FrontendError::InvalidForm(format!("unknown level {level}")))?;
+
+        database::preferences::set_room_level(&state.db, &user.id, &roomid, level)
+            .await
+            .map_err(FrontendError::InternalError)?;
+    }
+
+    if let Some(minutes) = form.mute_minutes {
+        let until = match minutes {
+            0 => None,
+            minutes @ 1..=43200 => Some(OffsetDateTime::now_utc() + Duration::minutes(minutes)),
+            _ => {
+                return Err(FrontendError::InvalidForm(
+                    "a room can be muted for up to 30 days".into(),
+                ))
+            }
+        };
+
+        database::preferences::mute_room(&state.db, &user.id, &roomid, until)
+            .await
+            .map_err(FrontendError::InternalError)?;
+    }
+
+    let notifications = notifications::room_context(&state, &user.id, &roomid).await?;
+
+    let output = state.templates.render_template(
+        "components/room-notifications.jinja2",
+        context! { notifications => notifications },
+    )?;
+
+    Ok((HxResponseTrigger::normal([ACTIVITY_CHANGED]), Html(output)))
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct MessageForm {
+    pub msg: String,
+    pub uploads: Option<Vec<String>>,
+}
+
+#[debug_handler]
+async fn handle_send_message(
+    jar: CookieJar,
+    Path(roomid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    axum_extra::extract::Form(form): axum_extra::extract::Form<MessageForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    tracing::info!("uploads: {:?}", form.uploads);
+
+    let msg = match commands::parse(&form.msg) {
+        Some((name, args)) => {
+            let invocation = commands::Invocation {
+                name,
+                args,
+                room_id: roomid.clone(),
+                user_id: user.id.clone(),
+                user_name: user.username.clone(),
+                is_admin: user.is_admin,
+            };
+
+            let reply = state
+                .commands
+                .dispatch(&invocation)
+                .await
+                .map_err(FrontendError::InternalError)?;
+
+            match reply {
+                Reply::Message(msg) => msg,
+                Reply::Ephemeral(message) => {
+                    database::drafts::delete_draft(&state.db, &user.id, &roomid)
+                        .await
+                        .map_err(FrontendError::InternalError)?;
+
+                    // only the caller gets to see this, in place of the send response
+                    let output = state.templates.render_template(
+                        "components/ephemeral.jinja2",
+                        context! { message => message },
+                    )?;
+
+                    return Ok(
+                        (HxResponseTrigger::normal([DRAFTS_CHANGED]), Html(output)).into_response()
+                    );
+                }
+            }
+        }
+        None => form.msg,
+    };
+
+    state
+        .room_manager
+        .send_message(&roomid, &user, &msg, form.uploads.unwrap_or_default())
+        .await
+        .map_err(blocked_error)?;
+
+    database::drafts::delete_draft(&state.db, &user.id, &roomid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok((HxResponseTrigger::normal([DRAFTS_CHANGED]), "").into_response())
+}
+
+// lets the sidebar refresh its draft list
+const DRAFTS_CHANGED: &str = "drafts-changed";
+
+#[debug_handler]
+async fn handle_save_draft(
+    jar: CookieJar,
+    Path(roomid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    axum_extra::extract::Form(form): axum_extra::extract::Form<MessageForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::rooms::get_room(&state.db, &roomid, &user.id)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    database::drafts::save_draft(
+        &state.db,
+        &user.id,
+        &roomid,
+        &form.msg,
+        &form.uploads.unwrap_or_default(),
+    )
+    .await
+    .map_err(FrontendError::InternalError)?;
+
+    Ok((HxResponseTrigger::normal([DRAFTS_CHANGED]), ""))
+}
+
+#[debug_handler]
+async fn handle_get_drafts(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let drafts = database::drafts::get_draft_rooms(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state
+        .templates
+        .render_template("components/drafts.jinja2", context! { drafts => drafts })?;
+
+    Ok(Html(output))
+}
+
+// lets the saved page and the sidebar badge refresh
+const SAVED_CHANGED: &str = "saved-changed";
+
+#[derive(serde::Deserialize)]
+struct SaveForm {
+    #[serde(default)]
+    remind_in: i64, // minutes, 0 for no reminder
+}
+
+#[debug_handler]
+async fn handle_save_message(
+    jar: CookieJar,
+    Path(messageid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<SaveForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let remind_at = match form.remind_in {
+        0 => None,
+        minutes @ 1..=43200 => Some(OffsetDateTime::now_utc() + Duration::minutes(minutes)),
+        _ => {
+            return Err(FrontendError::InvalidForm(
+                "a reminder can be up to 30 days away".into(),
+            ))
+        }
+    };
+
+    database::saved::save_message(&state.db, &user.id, &messageid, remind_at)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    let output = state.templates.render_template(
+        "components/save-button.jinja2",
+        context! { message_id => messageid, is_saved => true },
+    )?;
+
+    Ok((HxResponseTrigger::normal([SAVED_CHANGED]), Html(output)))
+}
+
+#[debug_handler]
+async fn handle_unsave_message(
+    jar: CookieJar,
+    Path(messageid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::saved::unsave_message(&state.db, &user.id, &messageid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/save-button.jinja2",
+        context! { message_id => messageid, is_saved => false },
+    )?;
+
+    Ok((HxResponseTrigger::normal([SAVED_CHANGED]), Html(output)))
+}
+
+#[debug_handler]
+async fn handle_get_saved(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let saved = database::saved::get_saved_messages(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state
+        .templates
+        .render_template("components/saved-list.jinja2", context! { saved => saved })?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_get_saved_due(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let due = database::saved::count_due_reminders(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state
+        .templates
+        .render_template("components/saved-due.jinja2", context! { due => due })?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct PollForm {
+    question: String,
+    #[serde(default)]
+    option: Vec<String>,
+    is_multi: Option<bool>,
+    is_anonymous: Option<bool>,
+    #[serde(default)]
+    closes_in: i64, // minutes, 0 never closes
+}
+
+#[debug_handler]
+async fn handle_create_poll(
+    jar: CookieJar,
+    Path(roomid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    axum_extra::extract::Form(form): axum_extra::extract::Form<PollForm>, // extra::form to read array values
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let options = form
+        .option
+        .into_iter()
+        .map(|o| o.trim().to_string())
+        .filter(|o| !o.is_empty())
+        .collect::<Vec<String>>();
+
+    let closes_at = match form.closes_in {
+        0 => None,
+        minutes @ 1..=43200 => Some(OffsetDateTime::now_utc() + Duration::minutes(minutes)),
+        _ => {
+            return Err(FrontendError::InvalidForm(
+                "a poll can stay open for up to 30 days".into(),
+            ))
+        }
+    };
+
+    let poll = database::polls::NewPoll {
+        question: form.question.trim().to_string(),
+        options,
+        is_multi: form.is_multi.unwrap_or_default(),
+        is_anonymous: form.is_anonymous.unwrap_or_default(),
+        closes_at,
+    };
+
+    state
+        .room_manager
+        .send_poll(&roomid, &user, &poll)
+        .await
+        .map_err(poll_error)?;
+
+    Ok("".into_response())
+}
+
+#[debug_handler]
+async fn handle_get_poll(
+    jar: CookieJar,
+    Path(pollid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    render_poll(&state, &pollid, &user).await
+}
+
+#[debug_handler]
+async fn handle_poll_vote(
+    jar: CookieJar,
+    Path((pollid, optionid)): Path<(String, String)>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    // only resolves if the user can see the room the poll is in
+    let poll = database::polls::get_poll(&state.db, &pollid, &user.id)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    database::polls::toggle_vote(&state.db, &poll, &optionid, &user.id)
+        .await
+        .map_err(poll_error)?;
+
+    state.room_manager.poll_updated(&poll.room_id, &poll.id);
+
+    render_poll(&state, &pollid, &user).await
+}
+
+#[debug_handler]
+async fn handle_close_poll(
+    jar: CookieJar,
+    Path(pollid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let poll = database::polls::get_poll(&state.db, &pollid, &user.id)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    database::polls::close_poll(&state.db, &poll, &user.id)
+        .await
+        .map_err(poll_error)?;
+
+    state.room_manager.poll_updated(&poll.room_id, &poll.id);
+
+    render_poll(&state, &pollid, &user).await
+}
+
+async fn render_poll(
+    state: &Arc<FrontendState>,
+    pollid: &str,
+    user: &UserCombined,
+) -> Result<Html<String>, FrontendError> {
+    let poll = database::polls::get_poll(&state.db, pollid, &user.id)
+        .await
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+
+    let options = database::polls::get_poll_options(&state.db, &poll, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let total: i64 = options.iter().map(|o| o.votes).sum();
+
+    let output = state.templates.render_template(
+        "components/poll.jinja2",
+        context! { poll => poll, options => options, total => total, user => user },
+    )?;
+
+    Ok(Html(output))
+}
+
+fn poll_error(e: anyhow::Error) -> FrontendError {
+    match e.downcast::<database::polls::PollError>() {
+        Ok(e) => FrontendError::InvalidForm(e.to_string()),
+        Err(e) => blocked_error(e),
+    }
+}
+
+fn blocked_error(e: anyhow::Error) -> FrontendError {
+    match e.downcast::<database::blocks::BlockError>() {
+        Ok(e) => FrontendError::InvalidForm(e.to_string()),
+        Err(e) => FrontendError::InternalError(e),
+    }
+}
+
+// the room is the target so both names end up in the entry
+async fn record_room_member_change(
+    state: &FrontendState,
+    user: &UserCombined,
+    action: AuditAction,
+    room_id: &str,
+    user_id: &str,
+) -> Result<(), FrontendError> {
+    let member = database::users::get_user_with_profile(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(state, user, action, Some(room_id), Some(&member.username)).await;
+
+    Ok(())
+}
+
+#[debug_handler]
+async fn handle_add_user_to_room(
+    jar: CookieJar,
+    Path((roomid, userid)): Path<(String, String)>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    if !database::rooms::is_member_of_room(&state.db, &roomid, &user.id).await {
+        permissions::require(&state, &user, Capability::ManageRoomMembers).await?;
+    }
+
+    database::blocks::ensure_not_blocked_by(&state.db, &user.id, &userid)
+        .await
+        .map_err(blocked_error)?;
+
+    database::rooms::add_user_to_room(&state.db, &roomid, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    record_room_member_change(
+        &state,
+        &user,
+        AuditAction::RoomMemberAdded,
+        &roomid,
+        &userid,
+    )
+    .await?;
+
+    if userid != user.id {
+        if let Err(e) = state
+            .room_manager
+            .notify(&NewNotification {
+                user_id: &userid,
+                kind: NotificationKind::RoomInvite,
+                actor_id: Some(&user.id),
+                room_id: Some(&roomid),
+                message_id: None,
+                body: None,
+            })
+            .await
+        {
+            tracing::warn!("failed to notify added room member: {}", e);
+        }
+    }
+
+    let room_users = database::rooms::get_room_users(&state.db, &roomid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/room-user.jinja2",
+        context! { roomUsers => room_users, roomid => roomid },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_remove_user_from_room(
+    jar: CookieJar,
+    Path((roomid, userid)): Path<(String, String)>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    if !database::rooms::is_member_of_room(&state.db, &roomid, &user.id).await {
+        permissions::require(&state, &user, Capability::ManageRoomMembers).await?;
+    }
+
+    database::rooms::remove_user_from_room(&state.db, &roomid, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    record_room_member_change(
+        &state,
+        &user,
+        AuditAction::RoomMemberRemoved,
+        &roomid,
+        &userid,
+    )
+    .await?;
+
+    let room_users = database::rooms::get_room_users(&state.db, &roomid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/room-user.jinja2",
+        context! { roomUsers => room_users, roomid => roomid },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_login(
+    mut jar: CookieJar,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<LoginForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    if state.auth_backends.is_empty() {
+        return Err(FrontendError::InvalidForm(
+            "password login is disabled".to_string(),
+        ));
+    }
+
+    let ip = client_ip(&state, connect_info, &headers);
+    let email = form.email.trim().to_lowercase();
+    check_login_throttle(&state, &email, &ip).await?;
+
+    let result =
+        users::authenticate(&state.db, &state.auth_backends, &form.email, &form.password).await;
+
+    let outcome = match &result {
+        Ok(_) => None,
+        Err(AuthError::UserNotEnabled(user_id) | AuthError::AwaitingApproval(user_id)) => {
+            Some((LoginOutcome::NotEnabled, Some(user_id)))
+        }
+        Err(AuthError::InternalError(_)) => None,
+        Err(_) => Some((LoginOutcome::InvalidCredentials, None)),
+    };
+    if let Some((outcome, user_id)) = outcome {
+        tracing::warn!(
+            "failed login for {} from {}: {}",
+            email,
+            ip,
+            outcome.as_str()
+        );
+        audit::record_login(&state, &email, &ip, outcome, user_id.map(|id| id.as_str())).await?;
+    }
+
+    let user_id = match result {
+        Ok(user_id) => user_id,
+        Err(AuthError::AwaitingApproval(user_id)) => {
+            // a directory backend just created the account
+            request_approval(&state, &user_id).await?;
+            jar = set_approval_status(&state, jar, &user_id)?;
+
+            return Ok((HxRedirect("/awaiting-approval".parse().unwrap()), jar, "").into_response());
+        }
+        Err(AuthError::UserNotEnabled(user_id)) => {
+            jar = set_approval_status(&state, jar, &user_id)?;
+
+            return Ok((HxRedirect("/awaiting-approval".parse().unwrap()), jar, "").into_response());
+        }
+        Err(AuthError::InternalError(e)) => return Err(FrontendError::InternalError(e)),
+        Err(_) => return Err(FrontendError::InvalidCredentials),
+    };
+
+    // a directory backend may just have created the very first account
+    if !state.has_admin.load(Ordering::Relaxed) {
+        admin_created(&state);
+    }
+
+    if needs_second_factor(&state, &user_id).await? {
+        jar = set_pending_login(&state, jar, &user_id)?;
+
+        return Ok((HxRedirect("/login/2fa".parse().unwrap()), jar, "").into_response());
+    }
+
+    // with a second factor the attempt only counts as successful once that is in too
+    audit::record_login(&state, &email, &ip, LoginOutcome::Success, Some(&user_id)).await?;
+
+    let (user_token, expires_at) = users::start_session(&state.db, &state.secret, &user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    jar = set_token(jar, user_token, expires_at);
+
+    // Ok(user_id)
+    Ok((HxRedirect("/".parse().unwrap()), jar, "").into_response())
+}
+
+pub(crate) const PENDING_LOGIN_COOKIE: &str = "pending_login";
+
+/// Remembers who got past the first step until they enter their second factor.
+pub(crate) fn set_pending_login(
+    state: &FrontendState,
+    jar: CookieJar,
+    user_id: &str,
+) -> Result<CookieJar, FrontendError> {
+    let pending = users::generate_pending_login_token(&state.secret, user_id)
+        .map_err(FrontendError::InternalError)?;
+
+    let mut cookie = Cookie::new(PENDING_LOGIN_COOKIE, pending);
+    cookie.set_same_site(SameSite::Strict);
+    cookie.set_path("/");
+    cookie.set_http_only(true);
+
+    Ok(jar.add(cookie))
+}
+
+// the peer address, or what the reverse proxy in front of us says it is
+pub(crate) fn client_ip(
+    state: &FrontendState,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+) -> String {
+    let forwarded = headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .filter(|_| state.behind_proxy);
+
+    forwarded
+        .or_else(|| connect_info.map(|ConnectInfo(addr)| addr.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+async fn check_login_throttle(
+    state: &FrontendState,
+    email: &str,
+    ip: &str,
+) -> Result<(), FrontendError> {
+    let locked_until = users::throttle::login_locked_until(&state.db, email, ip)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let Some(until) = locked_until else {
+        return Ok(());
+    };
+
+    audit::record_login(state, email, ip, LoginOutcome::Locked, None).await?;
+
+    Err(FrontendError::TooManyAttempts(
+        (until - OffsetDateTime::now_utc()).whole_seconds().max(1),
+    ))
+}
+
+pub(crate) async fn needs_second_factor(
+    state: &FrontendState,
+    user_id: &str,
+) -> Result<bool, FrontendError> {
+    if database::two_factor::is_enabled(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?
+    {
+        return Ok(true);
+    }
+
+    let user = database::users::get_user_with_profile(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+    let policy = database::two_factor::get_policy(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok(policy.requires(user.is_admin))
+}
+
+/// The user who passed the password step and is now on the second one.
+pub(crate) fn extract_pending_login(jar: &CookieJar, secret: &str) -> Option<String> {
+    let token = jar.get(PENDING_LOGIN_COOKIE)?.value();
+
+    users::extract_pending_login(secret, token).ok()
+}
+
+/// Generates and stores a fresh secret, returning what the setup template needs.
+pub(crate) async fn begin_two_factor_setup(
+    state: &FrontendState,
+    user: &UserCombined,
+) -> Result<minijinja::Value, FrontendError> {
+    let secret = users::totp::generate_secret();
+    database::two_factor::set_pending_secret(&state.db, &user.id, &secret)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let url =
+        users::totp::otpauth_url(&secret, &user.email).map_err(FrontendError::InternalError)?;
+    let qr = users::totp::qr_svg(&url).map_err(FrontendError::InternalError)?;
+
+    Ok(context! { secret => secret, url => url, qr => qr })
+}
+
+// checks an authenticator code, or spends a recovery code when that is what was typed
+async fn verify_second_factor(
+    state: &FrontendState,
+    user_id: &str,
+    code: &str,
+) -> Result<(), FrontendError> {
+    let totp = database::two_factor::get_totp(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?
+        .filter(|t| t.is_enabled)
+        .ok_or(FrontendError::InvalidForm(
+            "two-factor authentication is not enabled".to_string(),
+        ))?;
+
+    let valid = match users::totp::verify_code(&totp.secret, code) {
+        Some(step) => database::two_factor::use_step(&state.db, user_id, step)
+            .await
+            .map_err(FrontendError::InternalError)?,
+        None => database::two_factor::use_recovery_code(
+            &state.db,
+            user_id,
+            &users::totp::hash_recovery_code(code),
+        )
+        .await
+        .map_err(FrontendError::InternalError)?,
+    };
+
+    if !valid {
+        return Err(FrontendError::InvalidForm("invalid code".to_string()));
+    }
+
+    Ok(())
+}
+
+// turns on the pending secret once a code for it checks out, returns the plain recovery codes
+async fn confirm_two_factor_setup(
+    state: &FrontendState,
+    user_id: &str,
+    code: &str,
+) -> Result<Vec<String>, FrontendError> {
+    let totp = database::two_factor::get_totp(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?
+        .filter(|t| !t.is_enabled)
+        .ok_or(FrontendError::InvalidForm(
+            "no two-factor setup in progress".to_string(),
+        ))?;
+
+    let Some(step) = users::totp::verify_code(&totp.secret, code) else {
+        return Err(FrontendError::InvalidForm("invalid code".to_string()));
+    };
+
+    let codes = users::totp::generate_recovery_codes();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| users::totp::hash_recovery_code(c))
+        .collect();
+
+    database::two_factor::enable_totp(&state.db, user_id, step, &hashes)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok(codes)
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TwoFactorCodeForm {
+    code: String,
+}
+
+#[debug_handler]
+async fn handle_login_two_factor(
+    mut jar: CookieJar,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<TwoFactorCodeForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user_id) = extract_pending_login(&jar, &state.secret) else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    // codes are short, guessing them is throttled like passwords
+    let user = database::users::get_user_with_profile(&state.db, &user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+    let ip = client_ip(&state, connect_info, &headers);
+    let email = user.email.to_lowercase();
+    check_login_throttle(&state, &email, &ip).await?;
+
+    let enabled = database::two_factor::is_enabled(&state.db, &user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let result = if enabled {
+        verify_second_factor(&state, &user_id, &form.code)
+            .await
+            .map(|_| None)
+    } else {
+        confirm_two_factor_setup(&state, &user_id, &form.code)
+            .await
+            .map(Some)
+    };
+
+    let outcome = match &result {
+        Ok(_) => LoginOutcome::Success,
+        Err(FrontendError::InvalidForm(_)) => LoginOutcome::InvalidSecondFactor,
+        Err(_) => return result.map(|_| "".into_response()),
+    };
+    audit::record_login(&state, &email, &ip, outcome, Some(&user_id)).await?;
+
+    let codes = result?;
+
+    let (user_token, expires_at) = users::start_session(&state.db, &state.secret, &user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    jar = set_token(jar, user_token, expires_at);
+    jar = jar.remove(Cookie::build(PENDING_LOGIN_COOKIE).path("/"));
+
+    // a fresh enrollment shows its recovery codes before moving on
+    let Some(codes) = codes else {
+        return Ok((HxRedirect("/".parse().unwrap()), jar, "").into_response());
+    };
+
+    let output = state.templates.render_template(
+        "components/recovery-codes.jinja2",
+        context! { codes => codes, continue_url => "/" },
+    )?;
+
+    Ok((jar, Html(output)).into_response())
+}
+
+pub(crate) async fn two_factor_status(
+    state: &FrontendState,
+    user: &UserCombined,
+) -> Result<minijinja::Value, FrontendError> {
+    let enabled = database::two_factor::is_enabled(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+    let recovery_codes = database::two_factor::count_recovery_codes(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+    let policy = database::two_factor::get_policy(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok(context! {
+        enabled => enabled,
+        recovery_codes => recovery_codes,
+        required => policy.requires(user.is_admin),
+    })
+}
+
+#[debug_handler]
+async fn handle_two_factor_setup(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    if database::two_factor::is_enabled(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?
+    {
+        return Err(FrontendError::InvalidForm(
+            "two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let setup = begin_two_factor_setup(&state, &user).await?;
+    let output = state
+        .templates
+        .render_template("components/two-factor-setup.jinja2", setup)?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_two_factor_enable(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<TwoFactorCodeForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let codes = confirm_two_factor_setup(&state, &user.id, &form.code).await?;
+
+    let output = state.templates.render_template(
+        "components/recovery-codes.jinja2",
+        context! { codes => codes },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_two_factor_recovery_codes(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<TwoFactorCodeForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    verify_second_factor(&state, &user.id, &form.code).await?;
+
+    let codes = users::totp::generate_recovery_codes();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| users::totp::hash_recovery_code(c))
+        .collect();
+
+    database::two_factor::replace_recovery_codes(&state.db, &user.id, &hashes)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/recovery-codes.jinja2",
+        context! { codes => codes },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_two_factor_disable(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<TwoFactorCodeForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let policy = database::two_factor::get_policy(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    if policy.requires(user.is_admin) {
+        return Err(FrontendError::InvalidForm(
+            "two-factor authentication is required for your account".to_string(),
+        ));
+    }
+
+    verify_second_factor(&state, &user.id, &form.code).await?;
+
+    database::two_factor::reset_two_factor(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let two_factor = two_factor_status(&state, &user).await?;
+    let output = state.templates.render_template(
+        "components/two-factor.jinja2",
+        context! { two_factor => two_factor },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TwoFactorPolicyForm {
+    policy: database::two_factor::TwoFactorPolicy,
+}
+
+#[debug_handler]
+async fn handle_two_factor_policy(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<TwoFactorPolicyForm>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    database::two_factor::set_policy(&state.db, form.policy)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::TwoFactorPolicyChanged,
+        None,
+        Some(form.policy.as_str()),
+    )
+    .await;
+
+    let output = state.templates.render_template(
+        "components/two-factor-policy.jinja2",
+        context! { policy => form.policy.as_str() },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_logout(
+    mut jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    if let Some(claims) = validate_token(jar.clone(), &state.db, &state.secret).await {
+        database::sessions::delete_session(&state.db, &claims.sid)
+            .await
+            .map_err(FrontendError::InternalError)?;
+    }
+
+    jar = jar.remove(Cookie::build("token").path("/"));
+
+    Ok((HxRedirect("/login".parse().unwrap()), jar, "").into_response())
+}
+
+#[debug_handler]
+async fn handle_revoke_other_sessions(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(claims) = validate_token(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    database::sessions::delete_other_sessions(&state.db, &claims.sub, &claims.sid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let sessions = database::sessions::get_user_sessions(&state.db, &claims.sub)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = state.templates.render_template(
+        "components/sessions.jinja2",
+        context! { sessions => sessions, current_session => claims.sid },
+    )?;
+
+    Ok(Html(output))
+}
+
+const RESET_LINK_DURATION: Duration = Duration::hours(1);
+// handed over by an admin out of band, so it gets more time
+const ADMIN_RESET_LINK_DURATION: Duration = Duration::days(1);
+
+#[derive(serde::Deserialize)]
+struct ForgotPassword {
+    email: String,
+}
+
+#[debug_handler]
+async fn handle_forgot_password(
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<ForgotPassword>,
+) -> Result<impl IntoResponse, FrontendError> {
+    if !state.password_login {
+        return Err(FrontendError::InvalidForm(
+            "password login is disabled".to_string(),
+        ));
+    }
+
+    let Some(mailer) = &state.mailer else {
+        return render_reset_requested(&state, false);
+    };
+
+    // the response never tells whether the address belongs to someone
+    match database::users::get_user_by_email(&state.db, form.email.trim()).await {
+        Ok(user) if user.is_enabled && !user.is_bot => {
+            if database::sso::is_externally_managed(&state.db, &user.id)
+                .await
+                .map_err(FrontendError::InternalError)?
+            {
+                tracing::info!("password reset requested for an externally managed user");
+                return render_reset_requested(&state, true);
+            }
+
+            let link = create_reset_link(&state, &user.id, RESET_LINK_DURATION, None).await?;
+
+            let mail = mailer::Mail {
+                to: user.email,
+                subject: "Reset your SpeakWith password".to_string(),
+                body: format!(
+                    "Hi {},\n\nsomeone asked to reset your password, open this link within the next hour to choose a new one:\n\n{}\n\nIf that was not you, just ignore this mail.\n",
+                    user.username, link
+                ),
+                html: None,
+            };
+
+            if let Err(e) = mailer.send(&mail).await {
+                tracing::error!("failed to send reset mail: {}", e);
+            }
+        }
+        _ => tracing::info!("password reset requested for unknown user"),
+    }
+
+    render_reset_requested(&state, true)
+}
+
+fn render_reset_requested(
+    state: &FrontendState,
+    has_mailer: bool,
+) -> Result<Html<String>, FrontendError> {
+    let output = state.templates.render_template(
+        "components/reset-requested.jinja2",
+        context! { has_mailer => has_mailer },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[derive(serde::Deserialize)]
+struct PasswordReset {
+    password: String,
+    retype: String,
+}
+
+#[debug_handler]
+async fn handle_reset_password(
+    Path(token): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Form(form): Form<PasswordReset>,
+) -> Result<impl IntoResponse, FrontendError> {
+    if !state.password_login {
+        return Err(FrontendError::InvalidForm(
+            "password login is disabled".to_string(),
+        ));
+    }
+
+    if form.password.is_empty() {
+        return Err(FrontendError::InvalidForm(
+            "missing password field".to_string(),
+        ));
+    }
+
+    if form.password != form.retype {
+        return Err(FrontendError::InvalidForm(
+            "passwords do not match".to_string(),
+        ));
+    }
+
+    database::password_resets::reset_password(
+        &state.db,
+        &users::hash_api_token(&token),
+        &form.password,
+    )
+    .await
+    .map_err(
+        |e| match e.downcast::<database::password_resets::PasswordResetError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        },
+    )?;
+
+    Ok((HxRedirect("/login".parse().unwrap()), "").into_response())
+}
+
+#[debug_handler]
+async fn handle_admin_reset_link(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    let otheruser = database::users::get_user_with_profile(&state.db, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    if otheruser.is_bot {
+        return Err(FrontendError::InvalidForm(
+            "bots do not have a password".to_string(),
+        ));
+    }
+
+    let link = create_reset_link(
+        &state,
+        &otheruser.id,
+        ADMIN_RESET_LINK_DURATION,
+        Some(&user.id),
+    )
+    .await?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::PasswordResetLinkCreated,
+        Some(&otheruser.id),
+        None,
+    )
+    .await;
+
+    let output = state.templates.render_template(
+        "components/reset-link.jinja2",
+        context! { link => link, username => otheruser.username },
+    )?;
+
+    Ok(Html(output))
+}
+
+#[debug_handler]
+async fn handle_admin_two_factor_reset(
+    jar: CookieJar,
+    Path(userid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    permissions::require_admin(&user)?;
+
+    let otheruser = database::users::get_user_with_profile(&state.db, &userid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    database::two_factor::reset_two_factor(&state.db, &otheruser.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    audit::record(
+        &state,
+        &user,
+        AuditAction::TwoFactorReset,
+        Some(&otheruser.id),
+        None,
+    )
+    .await;
+
+    let output = state.templates.render_template(
+        "components/two-factor-reset.jinja2",
+        context! { username => otheruser.username },
+    )?;
+
+    Ok(Html(output))
+}
+
+/// Stores a single use reset token for the user and returns the link to redeem it.
+async fn create_reset_link(
+    state: &Arc<FrontendState>,
+    user_id: &str,
+    valid_for: Duration,
+    created_by: Option<&str>,
+) -> Result<String, FrontendError> {
+    let token = get_random_string(40);
+
+    database::password_resets::create_password_reset(
+        &state.db,
+        user_id,
+        &users::hash_api_token(&token),
+        OffsetDateTime::now_utc() + valid_for,
+        created_by,
+    )
+    .await
+    .map_err(
+        |e| match e.downcast::<database::password_resets::PasswordResetError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        },
+    )?;
+
+    Ok(format!("{}/reset-password/{}", state.public_url, token))
+}
+
+#[derive(serde::Deserialize)]
+struct PasswordUpdate {
+    pub current: String,
+    pub update: String,
+}
+
+#[debug_handler]
+async fn handle_update_user_password(
+    mut jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Form(update): Form<PasswordUpdate>,
 ) -> Result<impl IntoResponse, FrontendError> {
-    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
+    let Some(user) = extract_user(jar.clone(), &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    if !user.is_admin {
-        return Err(FrontendError::NoPermission);
-    }
-
-    if user.id == userid {
-        // not allowed to enable/disable self
-        return Err(FrontendError::NoPermission);
-    }
-
-    let otheruser = database::users::get_user_with_profile(&state.db, &userid)
+    database::users::update_user_password(&state.db, &user.email, &update.current, &update.update)
         .await
         .map_err(FrontendError::InternalError)?;
 
-    database::users::enable_user(&state.db, &userid, allow.value)
+    // every session was revoked with the old password, keep this one signed in
+    let (user_token, expires_at) = users::start_session(&state.db, &state.secret, &user.id)
         .await
         .map_err(FrontendError::InternalError)?;
 
-    let template = if allow.value {
-        "components/user-buttons-enabled.jinja2"
-    } else {
-        "components/user-buttons-disabled.jinja2"
-    };
-
-    let output = state.templates.render_template(
-        template,
-        context! {
-            is_admin => otheruser.is_admin,
-            userid => userid,
-        },
-    )?;
+    jar = set_token(jar, user_token, expires_at);
 
-    Ok(Html(output).into_response())
+    Ok((jar, ""))
 }
 
 #[derive(serde::Deserialize)]
-struct Pagination {
-    page: i32,
+struct ProfileUpdate {
+    pub name: String,
+    pub bio: String,
+    pub display_name: Option<String>,
+    pub title: Option<String>,
+    pub timezone: Option<String>,
+    pub pronouns: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_text: Option<String>,
+    // minutes until the status clears, kept as it was when empty and the status did not change
+    pub status_clear_after: Option<String>,
+}
+
+fn profile_field(
+    value: Option<String>,
+    label: &str,
+    max_chars: usize,
+) -> Result<Option<String>, FrontendError> {
+    let value = value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+
+    if value
+        .as_ref()
+        .is_some_and(|v| v.chars().count() > max_chars)
+    {
+        return Err(FrontendError::InvalidForm(format!(
+            "{} can be at most {} characters",
+            label, max_chars
+        )));
+    }
+
+    Ok(value)
+}
+
+// IANA names like Europe/Berlin, the browser does the actual conversion
+fn is_timezone(tz: &str) -> bool {
+    tz.len() <= 64
+        && tz.starts_with(|c: char| c.is_ascii_alphabetic())
+        && tz
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'))
 }
 
 #[debug_handler]
-async fn handle_pagination(
+async fn handle_update_user_profile(
     jar: CookieJar,
-    Path(roomid): Path<String>,
-    pagination: Query<Pagination>,
     State(state): State<Arc<FrontendState>>,
+    Form(update): Form<ProfileUpdate>,
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    let room = database::rooms::get_room(&state.db, &roomid, &user.id)
-        .await
-        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
+    let name = update.name.trim();
+    let display_name = profile_field(update.display_name, "display name", 64)?;
+    let title = profile_field(update.title, "title", 64)?;
+    let pronouns = profile_field(update.pronouns, "pronouns", 32)?;
+    let status_emoji = profile_field(update.status_emoji, "status emoji", 8)?;
+    let status_text = profile_field(update.status_text, "status", 100)?;
 
-    let page = pagination.page;
+    let timezone = profile_field(update.timezone, "timezone", 64)?;
+    if timezone.as_deref().is_some_and(|tz| !is_timezone(tz)) {
+        return Err(FrontendError::InvalidForm(
+            "timezone should look like Europe/Berlin".to_string(),
+        ));
+    }
 
-    let messages = state
-        .room_manager
-        .get_room_messages(&roomid, page, &user.id)
+    let current = database::users::get_profile(&state.db, &user.id)
         .await
         .map_err(FrontendError::InternalError)?;
 
-    let next_page = if messages.len() < database::messages::MAX_FETCH as usize {
-        0
+    let status_expires_at = if status_emoji.is_none() && status_text.is_none() {
+        None
     } else {
-        page + 1
+        match update
+            .status_clear_after
+            .as_deref()
+            .filter(|v| !v.is_empty())
+        {
+            Some(minutes) => match minutes.parse::<i64>() {
+                Ok(minutes @ 1..=43200) => {
+                    Some(OffsetDateTime::now_utc() + Duration::minutes(minutes))
+                }
+                _ => {
+                    return Err(FrontendError::InvalidForm(
+                        "a status can be kept for up to 30 days".to_string(),
+                    ))
+                }
+            },
+            None if current.status_emoji == status_emoji && current.status_text == status_text => {
+                current.status_expires_at
+            }
+            None => None,
+        }
     };
 
-    let output = state.templates.render_template(
-        "components/message-list.jinja2",
-        context! {
-            roomid => roomid, currentRoom => room, messages => messages, page => next_page
+    let profile = database::users::update_user_profile(
+        &state.db,
+        &user.id,
+        &database::users::ProfileUpdate {
+            username: name,
+            display_name: display_name.as_deref(),
+            bio: &update.bio,
+            title: title.as_deref(),
+            timezone: timezone.as_deref(),
+            pronouns: pronouns.as_deref(),
+            status_emoji: status_emoji.as_deref(),
+            status_text: status_text.as_deref(),
+            status_expires_at,
         },
+    )
+    .await
+    .map_err(|e| match e.downcast::<database::handles::HandleError>() {
+        Ok(e) => FrontendError::InvalidForm(e.to_string()),
+        Err(e) => FrontendError::InternalError(e),
+    })?;
+
+    let output = state.templates.render_template(
+        "components/user-profile-edit.jinja2",
+        context! { profile => profile, email => user.email },
     )?;
 
-    Ok(Html(output).into_response())
+    Ok(Html(output))
 }
 
 #[debug_handler]
-async fn handle_join_room(
+async fn handle_get_profile_card(
     jar: CookieJar,
-    Path(roomid): Path<String>,
+    Path(userid): Path<String>,
     State(state): State<Arc<FrontendState>>,
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    tracing::info!("connected to {} as {}", roomid, user.username);
-
-    let rcv = state
-        .room_manager
-        .join_room(roomid, &user.id)
+    let profile = database::users::get_profile(&state.db, &userid)
         .await
-        .map_err(FrontendError::InternalError)?;
+        .map_err(|e| FrontendError::NotFound(e.to_string()))?;
 
-    let ss = BroadcastStream::new(rcv)
-        .filter_map(|c| c.ok())
-        .map(move |c| {
-            let rendered = state
-                .templates
-                .render_template("components/message.jinja2", context! { message => c })
-                .unwrap();
-            Event::default().event("IncomingMessage").data(rendered)
-        })
-        .map(Ok::<Event, Infallible>);
+    // nothing to block or mute on your own card
+    let relation = if user.id == userid {
+        None
+    } else {
+        Some(
+            database::blocks::get_relation(&state.db, &user.id, &userid)
+                .await
+                .map_err(FrontendError::InternalError)?,
+        )
+    };
 
-    Ok(Sse::new(ss)
-        .keep_alive(KeepAlive::default())
-        .into_response())
-}
+    let output = state.templates.render_template(
+        "components/profile-card.jinja2",
+        context! { profile => profile, relation => relation },
+    )?;
 
-#[derive(serde::Deserialize, Default)]
-pub struct MessageForm {
-    pub msg: String,
-    pub uploads: Option<Vec<String>>,
+    Ok(Html(output))
 }
 
 #[debug_handler]
-async fn handle_send_message(
+async fn handle_block_user(
     jar: CookieJar,
-    Path(roomid): Path<String>,
+    Path(userid): Path<String>,
+    allow: Query<Allow>,
     State(state): State<Arc<FrontendState>>,
-    axum_extra::extract::Form(form): axum_extra::extract::Form<MessageForm>,
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    tracing::info!("uploads: {:?}", form.uploads);
-
-    state
-        .room_manager
-        .send_message(
-            &roomid,
-            &user.id,
-            &user.username,
-            user.image,
-            &form.msg,
-            form.uploads.unwrap_or_default(),
-        )
+    database::blocks::set_blocked(&state.db, &user.id, &userid, allow.value)
         .await
-        .map_err(FrontendError::InternalError)?;
+        .map_err(blocked_error)?;
 
-    // Ok(user_id)
-    Ok("".into_response())
+    render_relation(&state, &user, &userid).await
 }
 
 #[debug_handler]
-async fn handle_add_user_to_room(
+async fn handle_mute_user(
     jar: CookieJar,
-    Path((roomid, userid)): Path<(String, String)>,
+    Path(userid): Path<String>,
+    allow: Query<Allow>,
     State(state): State<Arc<FrontendState>>,
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    if !user.is_admin && !database::rooms::is_member_of_room(&state.db, &roomid, &user.id).await {
-        return Err(FrontendError::NoPermission);
-    }
-
-    database::rooms::add_user_to_room(&state.db, &roomid, &userid)
+    database::blocks::set_muted(&state.db, &user.id, &userid, allow.value)
         .await
-        .map_err(FrontendError::InternalError)?;
+        .map_err(blocked_error)?;
 
-    let room_users = database::rooms::get_room_users(&state.db, &roomid)
+    render_relation(&state, &user, &userid).await
+}
+
+async fn render_relation(
+    state: &FrontendState,
+    user: &UserCombined,
+    other_id: &str,
+) -> Result<Html<String>, FrontendError> {
+    let relation = database::blocks::get_relation(&state.db, &user.id, other_id)
         .await
         .map_err(FrontendError::InternalError)?;
 
     let output = state.templates.render_template(
-        "components/room-user.jinja2",
-        context! { roomUsers => room_users, roomid => roomid },
+        "components/user-relation.jinja2",
+        context! { relation => relation, other_id => other_id },
     )?;
 
     Ok(Html(output))
 }
 
+#[derive(serde::Deserialize)]
+struct QuietHoursForm {
+    start: String,
+    end: String,
+}
+
 #[debug_handler]
-async fn handle_remove_user_from_room(
+async fn handle_update_quiet_hours(
     jar: CookieJar,
-    Path((roomid, userid)): Path<(String, String)>,
     State(state): State<Arc<FrontendState>>,
+    Form(form): Form<QuietHoursForm>,
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    if !user.is_admin && !database::rooms::is_member_of_room(&state.db, &roomid, &user.id).await {
-        return Err(FrontendError::NoPermission);
-    }
+    let hours = notifications::parse_quiet_hours(&form.start, &form.end)?;
 
-    database::rooms::remove_user_from_room(&state.db, &roomid, &userid)
+    database::preferences::set_quiet_hours(&state.db, &user.id, &hours)
         .await
-        .map_err(FrontendError::InternalError)?;
+        .map_err(
+            |e| match e.downcast::<database::preferences::PreferenceError>() {
+                Ok(e) => FrontendError::InvalidForm(e.to_string()),
+                Err(e) => FrontendError::InternalError(e),
+            },
+        )?;
 
-    let room_users = database::rooms::get_room_users(&state.db, &roomid)
-        .await
-        .map_err(FrontendError::InternalError)?;
+    let quiet_hours = notifications::quiet_hours_context(&state, &user.id).await?;
 
     let output = state.templates.render_template(
-        "components/room-user.jinja2",
-        context! { roomUsers => room_users, roomid => roomid },
+        "components/quiet-hours.jinja2",
+        context! { quiet_hours => quiet_hours },
     )?;
 
     Ok(Html(output))
 }
 
-#[debug_handler]
-async fn handle_login(
-    mut jar: CookieJar,
-    State(state): State<Arc<FrontendState>>,
-    Form(form): Form<LoginForm>,
-) -> Result<impl IntoResponse, FrontendError> {
-    let user_id = database::users::verify_userpassword(&state.db, &form.email, &form.password)
-        .await
-        .map_err(|e| match e {
-            database::users::DBUserErrors::UserNotEnabled => FrontendError::UserNotEnabled,
-            database::users::DBUserErrors::InternalError(e) => FrontendError::InternalError(e),
-            _ => FrontendError::InvalidCredentials,
-        })?;
-    let user_token =
-        users::generate_token(&state.secret, &user_id).map_err(FrontendError::InternalError)?;
-
-    jar = set_token(jar, user_token);
-
-    // Ok(user_id)
-    Ok((HxRedirect("/".parse().unwrap()), jar, "").into_response())
-}
-
 #[derive(serde::Deserialize)]
-struct PasswordUpdate {
-    pub current: String,
-    pub update: String,
+struct DigestForm {
+    frequency: String,
 }
 
 #[debug_handler]
-async fn handle_update_user_password(
+async fn handle_update_digest(
     jar: CookieJar,
     State(state): State<Arc<FrontendState>>,
-    Form(update): Form<PasswordUpdate>,
+    Form(form): Form<DigestForm>,
 ) -> Result<impl IntoResponse, FrontendError> {
     let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
-    database::users::update_user_password(&state.db, &user.email, &update.current, &update.update)
-        .await
-        .map_err(FrontendError::InternalError)?;
-
-    Ok("")
-}
-
-#[derive(serde::Deserialize)]
-struct ProfileUpdate {
-    pub name: String,
-    pub bio: String,
-}
-
-#[debug_handler]
-async fn handle_update_user_profile(
-    jar: CookieJar,
-    State(state): State<Arc<FrontendState>>,
-    Form(update): Form<ProfileUpdate>,
-) -> Result<impl IntoResponse, FrontendError> {
-    let Some(user) = extract_user(jar, &state.db, &state.secret).await else {
-        return Err(FrontendError::Unauthorized);
+    let Some(frequency) = database::digests::DigestFrequency::parse(&form.frequency) else {
+        return Err(FrontendError::InvalidForm(format!(
+            "{} is not a digest frequency",
+            form.frequency
+        )));
     };
 
-    let user = database::users::update_user_profile(&state.db, &user.id, &update.name, &update.bio)
+    database::digests::set_frequency(&state.db, &user.id, frequency)
         .await
         .map_err(FrontendError::InternalError)?;
 
-    let output = state.templates.render_template(
-        "components/user-profile-edit.jinja2",
-        context! { username => user.username, email => user.email, bio => user.bio },
-    )?;
+    let digest = notifications::digest_context(&state, &user.id).await?;
+
+    let output = state
+        .templates
+        .render_template("components/digest.jinja2", context! { digest => digest })?;
 
     Ok(Html(output))
 }
@@ -538,7 +3535,7 @@ async fn handle_upload_to_room(
         })?
         .to_string();
 
-    let body_with_err = field.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err));
+    let body_with_err = field.map_err(std::io::Error::other);
 
     let (file_url, file_type) = uploads::upload_file(
         body_with_err,
@@ -552,7 +3549,7 @@ async fn handle_upload_to_room(
     let (upload_id, trx) = database::uploads::add_upload_and_continue(
         &state.db,
         &user.id,
-        Some(roomid),
+        Some(roomid.clone()),
         Some(file_name.clone()),
         Some(file_url.clone()),
     )
@@ -563,6 +3560,11 @@ async fn handle_upload_to_room(
         .await
         .map_err(|e| FrontendError::InternalError(e.into()))?;
 
+    // keep the pending upload around in case the user navigates away before sending
+    database::drafts::add_draft_upload(&state.db, &user.id, &roomid, &upload_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
     println!("file_type: {}", file_type);
 
     let output = state.templates.render_template(
@@ -607,7 +3609,7 @@ async fn handle_update_user_image(
         })?
         .to_string();
 
-    let body_with_err = field.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err));
+    let body_with_err = field.map_err(std::io::Error::other);
 
     let (file_url, _) = uploads::upload_file(
         body_with_err,
@@ -644,11 +3646,12 @@ async fn handle_update_user_image(
     Ok(Html(output))
 }
 
-#[derive(Default, Debug)]
+#[derive(Default)]
 struct RegistrationForm {
     pub name: String,
     pub email: String,
     pub password: String,
+    pub invite: String,
     pub filename: String,
     pub image: Option<String>,
 }
@@ -659,6 +3662,12 @@ async fn handle_registration(
     State(state): State<Arc<FrontendState>>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, FrontendError> {
+    if !state.password_login {
+        return Err(FrontendError::InvalidForm(
+            "password login is disabled".to_string(),
+        ));
+    }
+
     let user_id = xid::new().to_string();
 
     let mut form = RegistrationForm::default();
@@ -680,8 +3689,7 @@ async fn handle_registration(
                     })?
                     .to_string();
 
-                let body_with_err =
-                    field.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err));
+                let body_with_err = field.map_err(std::io::Error::other);
 
                 let (path, _) = uploads::upload_file(
                     body_with_err,
@@ -710,6 +3718,11 @@ async fn handle_registration(
                     FrontendError::InvalidForm(format!("failed to read email: {}", e))
                 })?;
             }
+            "invite" => {
+                form.invite = field.text().await.map_err(|e| {
+                    FrontendError::InvalidForm(format!("failed to read invite: {}", e))
+                })?;
+            }
             _ => {}
         }
     }
@@ -730,7 +3743,7 @@ async fn handle_registration(
         ));
     }
 
-    let (_, trx) = database::uploads::add_upload_and_continue(
+    let (_, mut trx) = database::uploads::add_upload_and_continue(
         &state.db,
         &user_id,
         None,
@@ -742,47 +3755,158 @@ async fn handle_registration(
 
     let has_admin = state.has_admin.load(Ordering::Relaxed);
 
+    // once there is an admin every account needs an invite
+    let invite = if has_admin {
+        let invite = database::invites::redeem_invite(
+            &mut trx,
+            &users::hash_api_token(&form.invite),
+            &form.email,
+        )
+        .await
+        .map_err(|e| match e.downcast::<database::invites::InviteError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        })?;
+
+        Some(invite)
+    } else {
+        None
+    };
+
     let user = database::users::User {
         email: form.email,
         password: form.password,
-        is_admin: !has_admin,
-        is_enabled: !has_admin,
+        is_admin: invite.as_ref().is_none_or(|invite| invite.is_admin),
+        is_enabled: invite.as_ref().is_none_or(|invite| invite.auto_approve),
+        is_guest: invite.as_ref().is_some_and(|invite| invite.is_guest),
+        guest_expires_at: invite
+            .as_ref()
+            .filter(|invite| invite.is_guest)
+            .and_then(|invite| invite.guest_days)
+            .map(|days| OffsetDateTime::now_utc() + Duration::days(days)),
         ..Default::default()
     };
+    let is_enabled = user.is_enabled;
 
     let profile = database::users::UserProfile {
-        username: form.name,
+        username: form.name.trim().to_string(),
         image: form.image,
         ..Default::default()
     };
 
-    let (user_id, trx) = database::users::create_user(&user_id, trx, user, profile)
+    let (user_id, mut trx) = database::users::create_user(&user_id, trx, user, profile)
         .await
-        .map_err(FrontendError::InternalError)?;
-
-    let user_token =
-        users::generate_token(&user_id, &state.secret).map_err(FrontendError::InternalError)?;
+        .map_err(|e| match e.downcast::<database::handles::HandleError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        })?;
 
-    jar = set_token(jar, user_token);
+    if let Some(invite) = &invite {
+        database::invites::record_invite_use(&mut trx, &invite.id, &user_id)
+            .await
+            .map_err(FrontendError::InternalError)?;
+    }
 
     if !has_admin {
-        let mut register_id = state.register_id.write();
-        *register_id = get_random_alphanumeric(); // just created a new admin, set registration_id
-        state.has_admin.store(true, Ordering::Relaxed);
+        admin_created(&state);
     }
 
     trx.commit()
         .await
         .map_err(|e| FrontendError::InternalError(e.into()))?;
 
+    if !is_enabled {
+        request_approval(&state, &user_id).await?;
+        jar = set_approval_status(&state, jar, &user_id)?;
+
+        return Ok((HxRedirect("/awaiting-approval".parse().unwrap()), jar, "").into_response());
+    }
+
+    let (user_token, expires_at) = users::start_session(&state.db, &state.secret, &user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    jar = set_token(jar, user_token, expires_at);
+
     // Ok(user_id)
     Ok((HxRedirect("/".parse().unwrap()), jar, "").into_response())
 }
 
-pub fn get_random_alphanumeric() -> String {
+pub(crate) const APPROVAL_STATUS_COOKIE: &str = "approval_status";
+
+// lets someone whose account is not enabled see how their registration stands
+fn set_approval_status(
+    state: &FrontendState,
+    jar: CookieJar,
+    user_id: &str,
+) -> Result<CookieJar, FrontendError> {
+    let token = users::generate_approval_status_token(&state.secret, user_id)
+        .map_err(FrontendError::InternalError)?;
+
+    let mut cookie = Cookie::new(APPROVAL_STATUS_COOKIE, token);
+    cookie.set_same_site(SameSite::Strict);
+    cookie.set_path("/");
+    cookie.set_http_only(true);
+
+    Ok(jar.add(cookie))
+}
+
+/// Queues a disabled account for review and mails the admins about it.
+pub(crate) async fn request_approval(
+    state: &FrontendState,
+    user_id: &str,
+) -> Result<(), FrontendError> {
+    let queued = database::approvals::request_approval(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    // the pending count in the sidebar is how admins find out without a mailer
+    let (true, Some(mailer)) = (queued, &state.mailer) else {
+        return Ok(());
+    };
+
+    let user = database::users::get_user_with_profile(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let approvers = database::roles::get_users_with_capability(&state.db, Capability::ApproveUsers)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let admins = database::users::get_user_list(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?
+        .into_iter()
+        .filter(|admin| approvers.contains(&admin.id));
+
+    for admin in admins {
+        let mail = mailer::Mail {
+            to: admin.email,
+            subject: format!("{} is waiting for approval on SpeakWith", user.username),
+            body: format!(
+                "Hi {},\n\n{} ({}) registered and is waiting for someone to approve the account:\n\n{}/approvals\n",
+                admin.username, user.username, user.email, state.public_url
+            ),
+            html: None,
+        };
+
+        if let Err(e) = mailer.send(&mail).await {
+            tracing::error!("failed to send approval request mail: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// The first account is the admin, from here on registering needs an invite.
+pub(crate) fn admin_created(state: &FrontendState) {
+    state.has_admin.store(true, Ordering::Relaxed);
+}
+
+pub fn get_random_string(len: usize) -> String {
     thread_rng()
         .sample_iter(&Alphanumeric)
-        .take(10)
+        .take(len)
         .map(char::from)
         .collect()
 }
@@ -835,6 +3959,10 @@ async fn handle_create_room(
         return Err(FrontendError::Unauthorized);
     };
 
+    if !form.is_private.unwrap_or_default() {
+        permissions::require(&state, &user, Capability::CreatePublicRooms).await?;
+    }
+
     let room_id = form.name.to_case(Case::Kebab);
 
     let room_id = database::rooms::create_room(
@@ -857,20 +3985,27 @@ async fn handle_create_room(
         .into_response())
 }
 
-fn set_token(jar: CookieJar, user_token: String) -> CookieJar {
+pub(crate) fn set_token(
+    jar: CookieJar,
+    user_token: String,
+    expires_at: OffsetDateTime,
+) -> CookieJar {
     let mut cookie = Cookie::new("token", user_token);
     cookie.set_same_site(SameSite::Strict);
     cookie.set_path("/");
-    cookie.set_expires(OffsetDateTime::now_utc() + Duration::weeks(52));
+    cookie.set_http_only(true);
+    cookie.set_expires(expires_at);
     jar.add(cookie)
 }
 
-pub(crate) fn validate_token(jar: CookieJar, secret: &str) -> Option<String> {
+pub(crate) async fn validate_token(
+    jar: CookieJar,
+    db: &Database,
+    secret: &str,
+) -> Option<users::Claims> {
     let token = jar.get("token")?.value();
 
-    let user_id = users::extract_sub(secret, token).ok()??;
-
-    Some(user_id)
+    users::validate_session(db, secret, token).await.ok()
 }
 
 pub(crate) async fn extract_user(
@@ -878,9 +4013,9 @@ pub(crate) async fn extract_user(
     db: &Database,
     secret: &str,
 ) -> Option<UserCombined> {
-    let user_id = validate_token(jar, secret)?;
+    let claims = validate_token(jar, db, secret).await?;
 
-    let user = database::users::get_user_with_profile(db, &user_id)
+    let user = database::users::get_user_with_profile(db, &claims.sub)
         .await
         .ok()?;
 
@@ -0,0 +1,16 @@
+// shows the pushes sent for direct messages and mentions while no SpeakWith tab is open
+self.addEventListener('push', (event) => {
+  const data = event.data ? event.data.json() : {};
+  event.waitUntil(
+    self.registration.showNotification(data.title || 'SpeakWith', {
+      body: data.body,
+      tag: data.tag,
+      data: { url: data.url || '/' },
+    })
+  );
+});
+
+self.addEventListener('notificationclick', (event) => {
+  event.notification.close();
+  event.waitUntil(self.clients.openWindow(event.notification.data.url));
+});
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use database::{
+    audit::{AuditAction, AuditEntry, AuditEvent, AuditFilter},
+    login_attempts::LoginOutcome,
+    users::UserCombined,
+};
+use minijinja::{context, Value};
+use time::{format_description::well_known::Rfc3339, macros::format_description, Date, Duration};
+
+use crate::{FrontendError, FrontendState};
+
+/// Writes `event` to the audit log, the action it describes already happened and is not undone
+/// when that fails.
+pub(crate) async fn record_event(state: &FrontendState, event: AuditEvent<'_>) {
+    if let Err(e) = database::audit::record(&state.db, &event).await {
+        tracing::error!(
+            "failed to write {} to the audit log: {}",
+            event.action.as_str(),
+            e
+        );
+    }
+}
+
+pub(crate) async fn record(
+    state: &FrontendState,
+    actor: &UserCombined,
+    action: AuditAction,
+    target_id: Option<&str>,
+    details: Option<&str>,
+) {
+    record_event(
+        state,
+        AuditEvent {
+            actor_id: Some(&actor.id),
+            action,
+            target_id,
+            details,
+            ip: None,
+        },
+    )
+    .await
+}
+
+/// Counts the attempt towards lockouts and keeps it in the audit log.
+pub(crate) async fn record_login(
+    state: &FrontendState,
+    email: &str,
+    ip: &str,
+    outcome: LoginOutcome,
+    user_id: Option<&str>,
+) -> Result<(), FrontendError> {
+    database::login_attempts::record_login_attempt(&state.db, email, ip, outcome)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let (action, details) = match outcome {
+        LoginOutcome::Success => (AuditAction::LoginSucceeded, None),
+        _ => (
+            AuditAction::LoginFailed,
+            Some(format!("{}, {}", email, outcome.as_str())),
+        ),
+    };
+
+    record_event(
+        state,
+        AuditEvent {
+            actor_id: user_id,
+            action,
+            target_id: None,
+            details: details.as_deref(),
+            ip: Some(ip),
+        },
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Every action with a label, for the log filter.
+pub(crate) fn all_actions() -> Vec<Value> {
+    AuditAction::ALL
+        .iter()
+        .map(|a| context! { id => a.as_str(), label => a.label() })
+        .collect()
+}
+
+pub(crate) const PAGE_SIZE: i64 = 50;
+
+/// Filters from the log viewer, shared by the page, its pages of entries and the export.
+#[derive(serde::Deserialize, serde::Serialize, Default, Debug)]
+pub(crate) struct AuditLogQuery {
+    action: Option<String>,
+    q: Option<String>,
+    // days as yyyy-mm-dd, both included
+    since: Option<String>,
+    until: Option<String>,
+    #[serde(default)]
+    page: i64,
+}
+
+impl AuditLogQuery {
+    fn filter(&self) -> Result<AuditFilter, FrontendError> {
+        let action =
+            match self.action.as_deref().filter(|v| !v.is_empty()) {
+                Some(value) => Some(AuditAction::parse(value).ok_or_else(|| {
+                    FrontendError::InvalidForm(format!("unknown action {}", value))
+                })?),
+                None => None,
+            };
+
+        Ok(AuditFilter {
+            action,
+            search: self
+                .q
+                .as_ref()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+            since: parse_day(self.since.as_deref())?,
+            // the last representable day has no end, so it leaves the range open
+            until: parse_day(self.until.as_deref())?
+                .and_then(|day| day.checked_add(Duration::days(1))),
+        })
+    }
+}
+
+fn parse_day(value: Option<&str>) -> Result<Option<time::OffsetDateTime>, FrontendError> {
+    let Some(value) = value.filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+
+    let day = Date::parse(value, format_description!("[year]-[month]-[day]"))
+        .map_err(|_| FrontendError::InvalidForm(format!("{} is not a day", value)))?;
+
+    Ok(Some(day.midnight().assume_utc()))
+}
+
+/// One page of matching entries with what the viewer needs to show them.
+pub(crate) async fn entries_context(
+    state: &FrontendState,
+    query: &AuditLogQuery,
+) -> Result<Value, FrontendError> {
+    let filter = query.filter()?;
+    let page = query.page.max(0);
+
+    // one more than shown tells whether there is another page
+    let mut entries = database::audit::get_entries(
+        &state.db,
+        &filter,
+        Some(PAGE_SIZE + 1),
+        page.saturating_mul(PAGE_SIZE),
+    )
+    .await
+    .map_err(FrontendError::InternalError)?;
+
+    let more = entries.len() as i64 > PAGE_SIZE;
+    entries.truncate(PAGE_SIZE as usize);
+
+    let labels: HashMap<&str, &str> = AuditAction::ALL
+        .iter()
+        .map(|a| (a.as_str(), a.label()))
+        .collect();
+
+    Ok(context! {
+        entries => entries,
+        labels => labels,
+        more => more,
+        next_page => page.saturating_add(1),
+        query => query,
+    })
+}
+
+/// Every matching entry as one JSON object per line.
+pub(crate) async fn export(
+    state: &FrontendState,
+    query: &AuditLogQuery,
+) -> Result<String, FrontendError> {
+    let entries = database::audit::get_entries(&state.db, &query.filter()?, None, 0)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let mut output = String::new();
+    for entry in entries {
+        output.push_str(&export_line(&entry)?);
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+fn export_line(entry: &AuditEntry) -> Result<String, FrontendError> {
+    let created_at = entry
+        .created_at
+        .format(&Rfc3339)
+        .map_err(|e| FrontendError::InternalError(e.into()))?;
+
+    serde_json::to_string(&serde_json::json!({
+        "id": entry.id,
+        "created_at": created_at,
+        "action": entry.action,
+        "actor_id": entry.actor_id,
+        "actor_name": entry.actor_name,
+        "target_id": entry.target_id,
+        "target_name": entry.target_name,
+        "details": entry.details,
+        "ip": entry.ip,
+    }))
+    .map_err(|e| FrontendError::InternalError(e.into()))
+}
@@ -0,0 +1,217 @@
+use std::{convert::Infallible, sync::Arc};
+
+use anyhow::Result;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive},
+        IntoResponse, Sse,
+    },
+    routing::get,
+    Json, Router,
+};
+use database::users::UserCombined;
+use rooms::RoomEvent;
+use thiserror::Error;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt as _;
+
+use crate::FrontendState;
+
+pub(crate) fn setup_bot_api(state: Arc<FrontendState>) -> Router {
+    Router::new()
+        .route("/me", get(handle_me))
+        .route("/rooms", get(handle_rooms))
+        .route(
+            "/rooms/:roomid/messages",
+            get(handle_messages).post(handle_post_message),
+        )
+        .route("/rooms/:roomid/events", get(handle_events))
+        .with_state(state)
+}
+
+#[derive(Error, Debug)]
+pub enum BotApiError {
+    #[error("missing or invalid token")]
+    Unauthorized,
+    #[error("bot is not invited to this room")]
+    NotInvited,
+    #[error("invalid request : {0}")]
+    InvalidRequest(String),
+    #[error("internal server error : {0}")]
+    InternalError(anyhow::Error),
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for BotApiError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            BotApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            BotApiError::NotInvited => StatusCode::FORBIDDEN,
+            BotApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            BotApiError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let body = ErrorBody {
+            error: self.to_string(),
+        };
+
+        (status, Json(body)).into_response()
+    }
+}
+
+#[debug_handler]
+async fn handle_me(
+    headers: HeaderMap,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, BotApiError> {
+    let bot = extract_bot(&headers, &state).await?;
+
+    Ok(Json(bot))
+}
+
+#[debug_handler]
+async fn handle_rooms(
+    headers: HeaderMap,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, BotApiError> {
+    let bot = extract_bot(&headers, &state).await?;
+
+    let rooms = database::rooms::get_member_rooms(&state.db, &bot.id)
+        .await
+        .map_err(BotApiError::InternalError)?;
+
+    Ok(Json(rooms))
+}
+
+#[derive(serde::Deserialize)]
+struct Pagination {
+    #[serde(default)]
+    page: i32,
+}
+
+#[debug_handler]
+async fn handle_messages(
+    headers: HeaderMap,
+    Path(roomid): Path<String>,
+    pagination: Query<Pagination>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, BotApiError> {
+    let bot = extract_bot(&headers, &state).await?;
+    ensure_invited(&state, &roomid, &bot).await?;
+
+    let messages = state
+        .room_manager
+        .get_room_messages(&roomid, pagination.page, &bot.id)
+        .await
+        .map_err(BotApiError::InternalError)?;
+
+    Ok(Json(messages))
+}
+
+#[derive(serde::Deserialize)]
+struct NewMessage {
+    text: String,
+}
+
+#[debug_handler]
+async fn handle_post_message(
+    headers: HeaderMap,
+    Path(roomid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+    Json(message): Json<NewMessage>,
+) -> Result<impl IntoResponse, BotApiError> {
+    let bot = extract_bot(&headers, &state).await?;
+    ensure_invited(&state, &roomid, &bot).await?;
+
+    if message.text.trim().is_empty() {
+        return Err(BotApiError::InvalidRequest("empty message".to_string()));
+    }
+
+    state
+        .room_manager
+        .send_message(&roomid, &bot, &message.text, vec![])
+        .await
+        .map_err(BotApiError::InternalError)?;
+
+    Ok(StatusCode::CREATED)
+}
+
+#[derive(serde::Serialize)]
+struct EphemeralBody {
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+struct PollBody {
+    poll_id: String,
+}
+
+#[debug_handler]
+async fn handle_events(
+    headers: HeaderMap,
+    Path(roomid): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, BotApiError> {
+    let bot = extract_bot(&headers, &state).await?;
+    ensure_invited(&state, &roomid, &bot).await?;
+
+    let rcv = state
+        .room_manager
+        .join_room(roomid, &bot.id)
+        .await
+        .map_err(BotApiError::InternalError)?;
+
+    let bot_id = bot.id;
+    let ss = BroadcastStream::new(rcv)
+        .filter_map(|c| c.ok())
+        .filter_map(move |event| match event {
+            RoomEvent::Message(c) => Event::default().event("message").json_data(c).ok(),
+            RoomEvent::PollUpdated { poll_id } => Event::default()
+                .event("poll")
+                .json_data(PollBody { poll_id })
+                .ok(),
+            RoomEvent::Ephemeral { user_id, message } if user_id == bot_id => Event::default()
+                .event("ephemeral")
+                .json_data(EphemeralBody { message })
+                .ok(),
+            _ => None,
+        })
+        .map(Ok::<Event, Infallible>);
+
+    Ok(Sse::new(ss).keep_alive(KeepAlive::default()))
+}
+
+async fn ensure_invited(
+    state: &Arc<FrontendState>,
+    roomid: &str,
+    bot: &UserCombined,
+) -> Result<(), BotApiError> {
+    // even public rooms are off limits until someone invites the bot
+    if !database::rooms::is_member_of_room(&state.db, roomid, &bot.id).await {
+        return Err(BotApiError::NotInvited);
+    }
+
+    Ok(())
+}
+
+async fn extract_bot(
+    headers: &HeaderMap,
+    state: &Arc<FrontendState>,
+) -> Result<UserCombined, BotApiError> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(BotApiError::Unauthorized)?;
+
+    database::bots::get_bot_by_token(&state.db, &users::hash_api_token(token))
+        .await
+        .map_err(|_| BotApiError::Unauthorized)
+}
@@ -1,20 +1,34 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 use anyhow::Result;
-use api::{extract_user, get_random_alphanumeric, setup_api, validate_token};
+use api::{
+    admin_created, begin_two_factor_setup, client_ip, extract_pending_login, extract_user,
+    invite_rooms, needs_second_factor, request_approval, set_pending_login, set_token, setup_api,
+    two_factor_status, validate_token, ACTIVITY_CHANGED, APPROVAL_STATUS_COOKIE,
+    NOTIFICATIONS_CHANGED,
+};
 use assets::setup_asset_handler;
 use axum::{
     debug_handler,
-    extract::{Path, Request, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Redirect},
+    extract::{Path, Query, Request, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, RETRY_AFTER, SET_COOKIE},
+        StatusCode,
+    },
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
     Router,
 };
 use axum_extra::extract::CookieJar;
-use axum_htmx::HxRequest;
-use database::{rooms::RoomUser, Database};
+use axum_htmx::{HxRequest, HxResponseTrigger};
+use bot_api::setup_bot_api;
+use database::{roles::Capability, rooms::RoomUser, Database};
 use minijinja::context;
-use parking_lot::RwLock;
+use permissions::all_capabilities;
+use sso::setup_sso;
 use templates::Templates;
 use thiserror::Error;
 use tower::ServiceExt;
@@ -22,6 +36,13 @@ use tower_http::services::ServeDir;
 
 mod api;
 mod assets;
+mod audit;
+mod bot_api;
+mod jobs;
+mod moderation;
+mod notifications;
+mod permissions;
+mod sso;
 mod templates;
 
 #[derive(Error, Debug)]
@@ -34,8 +55,6 @@ pub enum FrontendError {
     Unauthorized,
     #[error("no permission")]
     NoPermission,
-    #[error("no permission")]
-    UserNotEnabled,
     #[error("needs_admin")]
     NeedsAdmin,
     #[error("already logged in")]
@@ -44,6 +63,8 @@ pub enum FrontendError {
     InvalidCredentials,
     #[error("invalid file")]
     InvalidForm(String),
+    #[error("too many attempts")]
+    TooManyAttempts(i64), // seconds until the next try
 }
 
 impl IntoResponse for FrontendError {
@@ -54,6 +75,21 @@ impl IntoResponse for FrontendError {
             FrontendError::AlreadyLoggedIn => Redirect::temporary("/").into_response(),
             FrontendError::InvalidForm(e) => (StatusCode::BAD_REQUEST, e).into_response(),
             FrontendError::NoPermission => (StatusCode::UNAUTHORIZED).into_response(),
+            FrontendError::InvalidCredentials => {
+                (StatusCode::UNAUTHORIZED, "Invalid email or password").into_response()
+            }
+            FrontendError::TooManyAttempts(seconds) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, seconds.to_string())],
+                match seconds {
+                    ..=59 => format!("Too many failed attempts, try again in {} seconds", seconds),
+                    _ => format!(
+                        "Too many failed attempts, try again in {} minutes",
+                        (seconds + 59) / 60
+                    ),
+                },
+            )
+                .into_response(),
             FrontendError::InternalError(e) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
             }
@@ -62,15 +98,39 @@ impl IntoResponse for FrontendError {
     }
 }
 
+pub struct Options {
+    // where users reach the app, links in mails are built from it
+    pub public_url: String,
+    pub mailer: Option<Arc<dyn mailer::Mailer>>,
+    pub oidc: Option<users::oidc::OidcConfig>,
+    // with single sign-on in place the password forms can be switched off
+    pub password_login: bool,
+    // checked in order on login, empty when only single sign-on is allowed
+    pub auth_backends: Vec<Arc<dyn users::auth::AuthBackend>>,
+    // take the client address from x-forwarded-for
+    pub behind_proxy: bool,
+    pub push: Option<Arc<dyn push::PushService>>,
+    // the stand-in push service is mounted, so its plain http endpoints are accepted
+    pub push_stub: bool,
+}
+
 #[derive(Clone)]
 struct FrontendState {
     has_admin: Arc<AtomicBool>,
     templates: Templates,
     room_manager: Arc<rooms::Manager>,
+    commands: Arc<commands::Dispatcher>,
     db: Database,
     secret: String,
     uploads_path: String,
-    register_id: Arc<RwLock<String>>,
+    public_url: String,
+    mailer: Option<Arc<dyn mailer::Mailer>>,
+    oidc: Option<Arc<users::oidc::OidcProvider>>,
+    password_login: bool,
+    auth_backends: Vec<Arc<dyn users::auth::AuthBackend>>,
+    behind_proxy: bool,
+    push: Option<Arc<dyn push::PushService>>,
+    push_stub: bool,
 }
 
 pub async fn initialize(
@@ -78,34 +138,76 @@ pub async fn initialize(
     secret: &str,
     data_path: String,
     db: Database,
+    options: Options,
 ) -> Result<Router> {
     let has_admin = database::users::has_admin(&db).await?;
-    let register_id = if has_admin {
-        get_random_alphanumeric()
-    } else {
-        String::from("admin")
+
+    let public_url = options.public_url.trim_end_matches('/').to_string();
+
+    let oidc = match options.oidc {
+        Some(config) => {
+            let redirect_url = format!("{}{}", public_url, sso::CALLBACK_PATH);
+            Some(Arc::new(
+                users::oidc::OidcProvider::discover(config, &redirect_url).await?,
+            ))
+        }
+        None => None,
     };
 
     let templates = Templates::default();
 
+    let room_manager = Arc::new(rooms::Manager::new(db.clone()));
+    let commands = Arc::new(commands::Dispatcher::new(db.clone(), room_manager.clone())?);
+
     let state = Arc::new(FrontendState {
         has_admin: Arc::new(AtomicBool::new(has_admin)),
         templates,
         secret: secret.to_string(),
-        room_manager: Arc::new(rooms::Manager::new(db.clone())),
-        register_id: Arc::new(RwLock::new(register_id)),
+        room_manager,
+        commands,
         uploads_path: format!("{}/uploads", &data_path),
         db,
+        public_url,
+        mailer: options.mailer,
+        oidc,
+        password_login: options.password_login,
+        auth_backends: options.auth_backends,
+        behind_proxy: options.behind_proxy,
+        push: options.push,
+        push_stub: options.push_stub,
     });
 
     std::fs::create_dir_all(&state.uploads_path)?;
 
+    jobs::spawn(state.clone());
+
     let router = Router::new()
         .route("/", axum::routing::get(home_handler))
         .route("/login", axum::routing::get(login_handler))
+        .route("/login/2fa", axum::routing::get(login_two_factor_handler))
+        .route(
+            "/forgot-password",
+            axum::routing::get(forgot_password_handler),
+        )
+        .route(
+            "/reset-password/:token",
+            axum::routing::get(reset_password_handler),
+        )
         .route("/register/:id", axum::routing::get(register_handler))
+        .route(
+            "/awaiting-approval",
+            axum::routing::get(awaiting_approval_handler),
+        )
         .route("/users", axum::routing::get(user_handler))
+        .route("/approvals", axum::routing::get(approvals_handler))
+        .route("/moderation", axum::routing::get(moderation_handler))
+        .route("/audit-log", axum::routing::get(audit_log_handler))
+        .route(
+            "/audit-log/export",
+            axum::routing::get(audit_log_export_handler),
+        )
         .route("/profile", axum::routing::get(profile_handler))
+        .route("/saved", axum::routing::get(saved_handler))
         .route("/chatroom/:roomid", axum::routing::get(room_handler))
         .route("/template/*path", axum::routing::get(template_handler))
         .with_state(state.clone())
@@ -113,19 +215,49 @@ pub async fn initialize(
             "/uploads",
             axum::routing::get(uploads_handler).with_state(state.clone()),
         )
-        .nest("/htmx", setup_api(state))
-        .nest("/assets", setup_asset_handler());
+        .nest("/api/bot", setup_bot_api(state.clone()))
+        .nest("/auth/oidc", setup_sso(state.clone()))
+        .nest("/htmx", setup_api(state.clone()))
+        .nest("/assets", setup_asset_handler())
+        .layer(middleware::from_fn_with_state(state, refresh_session));
 
     Ok(router)
 }
 
+// slides the session forward once its token gets old, unless the handler already set a new one
+async fn refresh_session(
+    State(state): State<Arc<FrontendState>>,
+    jar: CookieJar,
+    request: Request,
+    next: Next,
+) -> Response {
+    let claims = jar
+        .get("token")
+        .and_then(|token| users::extract_sub(&state.secret, token.value()).ok());
+
+    let response = next.run(request).await;
+
+    let Some(claims) = claims.filter(|c| c.needs_refresh()) else {
+        return response;
+    };
+
+    if response.headers().contains_key(SET_COOKIE) {
+        return response;
+    }
+
+    match users::refresh_session(&state.db, &state.secret, &claims).await {
+        Ok((token, expires_at)) => (set_token(jar, token, expires_at), response).into_response(),
+        Err(_) => response,
+    }
+}
+
 #[debug_handler]
 async fn uploads_handler(
     jar: CookieJar,
     State(state): State<Arc<FrontendState>>,
     req: Request,
 ) -> Result<impl IntoResponse, FrontendError> {
-    let Some(_) = validate_token(jar, &state.secret) else {
+    let Some(_) = validate_token(jar, &state.db, &state.secret).await else {
         return Err(FrontendError::Unauthorized);
     };
 
@@ -156,12 +288,37 @@ async fn profile_handler(
     HxRequest(is_htmx): HxRequest,
     State(state): State<Arc<FrontendState>>,
 ) -> Result<impl IntoResponse, FrontendError> {
-    let user = redirect_to_register(jar, &state).await?;
+    let user = redirect_to_register(jar.clone(), &state).await?;
+
+    let current_session = validate_token(jar, &state.db, &state.secret)
+        .await
+        .map(|claims| claims.sid);
+
+    let sessions = database::sessions::get_user_sessions(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let two_factor = two_factor_status(&state, &user).await?;
+
+    let profile = database::users::get_profile(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let blocked_users = database::blocks::get_blocked_users(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let quiet_hours = notifications::quiet_hours_context(&state, &user.id).await?;
+
+    let digest = notifications::digest_context(&state, &user.id).await?;
+
+    let push_key = state.push.as_ref().map(|push| push.public_key());
 
     let output = if is_htmx {
-        state
-            .templates
-            .render_template("components/profile.jinja2", context! { user => user })?
+        state.templates.render_template(
+            "components/profile.jinja2",
+            context! { user => user, profile => profile, sessions => sessions, current_session => current_session, two_factor => two_factor, blocked_users => blocked_users, quiet_hours => quiet_hours, digest => digest, push_key => push_key },
+        )?
     } else {
         let (user_rooms, rooms) = database::rooms::get_rooms(&state.db, &user.id)
             .await
@@ -169,13 +326,198 @@ async fn profile_handler(
 
         state.templates.render_template(
             "profile.jinja2",
-            context! { rooms => rooms, user_rooms => user_rooms , user => user },
+            context! { rooms => rooms, user_rooms => user_rooms , user => user, profile => profile, sessions => sessions, current_session => current_session, two_factor => two_factor, blocked_users => blocked_users, quiet_hours => quiet_hours, digest => digest, push_key => push_key },
+        )?
+    };
+
+    Ok(Html(output).into_response())
+}
+
+#[debug_handler]
+async fn saved_handler(
+    jar: CookieJar,
+    HxRequest(is_htmx): HxRequest,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let user = redirect_to_register(jar, &state).await?;
+
+    let saved = database::saved::get_saved_messages(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = if is_htmx {
+        state.templates.render_template(
+            "components/saved.jinja2",
+            context! { user => user, saved => saved },
+        )?
+    } else {
+        let (user_rooms, rooms) = database::rooms::get_rooms(&state.db, &user.id)
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+        state.templates.render_template(
+            "saved.jinja2",
+            context! { rooms => rooms, user_rooms => user_rooms , user => user, saved => saved },
         )?
     };
 
     Ok(Html(output).into_response())
 }
 
+#[debug_handler]
+async fn audit_log_handler(
+    jar: CookieJar,
+    HxRequest(is_htmx): HxRequest,
+    State(state): State<Arc<FrontendState>>,
+    Query(query): Query<audit::AuditLogQuery>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let user = redirect_to_register(jar, &state).await?;
+
+    permissions::require(&state, &user, Capability::ViewAuditLog).await?;
+
+    let entries = audit::entries_context(&state, &query).await?;
+    let actions = audit::all_actions();
+
+    let output = if is_htmx {
+        state.templates.render_template(
+            "components/audit-log.jinja2",
+            context! { user => user, actions => actions, ..entries },
+        )?
+    } else {
+        let (user_rooms, rooms) = database::rooms::get_rooms(&state.db, &user.id)
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+        state.templates.render_template(
+            "audit-log.jinja2",
+            context! { rooms => rooms, user_rooms => user_rooms, user => user, actions => actions, ..entries },
+        )?
+    };
+
+    Ok(Html(output).into_response())
+}
+
+#[debug_handler]
+async fn audit_log_export_handler(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+    Query(query): Query<audit::AuditLogQuery>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let user = redirect_to_register(jar, &state).await?;
+
+    permissions::require(&state, &user, Capability::ViewAuditLog).await?;
+
+    let output = audit::export(&state, &query).await?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/x-ndjson"),
+            (
+                CONTENT_DISPOSITION,
+                r#"attachment; filename="audit-log.jsonl""#,
+            ),
+        ],
+        output,
+    ))
+}
+
+#[debug_handler]
+async fn moderation_handler(
+    jar: CookieJar,
+    HxRequest(is_htmx): HxRequest,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let user = redirect_to_register(jar, &state).await?;
+
+    permissions::require(&state, &user, Capability::ModerateMessages).await?;
+
+    let queue = moderation::queue_context(&state).await?;
+
+    let output = if is_htmx {
+        state.templates.render_template(
+            "components/moderation.jinja2",
+            context! { user => user, ..queue },
+        )?
+    } else {
+        let (user_rooms, rooms) = database::rooms::get_rooms(&state.db, &user.id)
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+        state.templates.render_template(
+            "moderation.jinja2",
+            context! { rooms => rooms, user_rooms => user_rooms, user => user, ..queue },
+        )?
+    };
+
+    Ok(Html(output).into_response())
+}
+
+#[debug_handler]
+async fn approvals_handler(
+    jar: CookieJar,
+    HxRequest(is_htmx): HxRequest,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let user = redirect_to_register(jar, &state).await?;
+
+    permissions::require(&state, &user, Capability::ApproveUsers).await?;
+
+    let pending = database::approvals::get_pending_users(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let output = if is_htmx {
+        state.templates.render_template(
+            "components/approvals.jinja2",
+            context! { user => user, pending => pending },
+        )?
+    } else {
+        let (user_rooms, rooms) = database::rooms::get_rooms(&state.db, &user.id)
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+        state.templates.render_template(
+            "approvals.jinja2",
+            context! { rooms => rooms, user_rooms => user_rooms, user => user, pending => pending },
+        )?
+    };
+
+    Ok(Html(output).into_response())
+}
+
+#[debug_handler]
+async fn awaiting_approval_handler(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let user_id = jar
+        .get(APPROVAL_STATUS_COOKIE)
+        .and_then(|token| users::extract_approval_status(&state.secret, token.value()).ok());
+
+    render_awaiting_approval(&state, user_id.as_deref()).await
+}
+
+/// Explains to someone whose account is not enabled whether it is pending, declined or disabled.
+pub(crate) async fn render_awaiting_approval(
+    state: &FrontendState,
+    user_id: Option<&str>,
+) -> Result<Html<String>, FrontendError> {
+    let approval = match user_id {
+        Some(user_id) => database::approvals::get_approval(&state.db, user_id)
+            .await
+            .map_err(FrontendError::InternalError)?,
+        None => None,
+    };
+
+    let output = state.templates.render_template(
+        "awaiting-approval.jinja2",
+        // accounts disabled outside the queue have no approval to show
+        context! { approval => approval, user_known => user_id.is_some() },
+    )?;
+
+    Ok(Html(output))
+}
+
 #[debug_handler]
 async fn home_handler(
     jar: CookieJar,
@@ -197,6 +539,14 @@ async fn home_handler(
     Ok(Html(output).into_response())
 }
 
+// an upload restored from a draft, shaped like the upload response
+#[derive(serde::Serialize)]
+struct PendingUpload {
+    upload_id: String,
+    path: String,
+    file_type: String,
+}
+
 #[debug_handler]
 async fn room_handler(
     HxRequest(is_htmx): HxRequest,
@@ -232,10 +582,37 @@ async fn room_handler(
         vec![]
     };
 
+    let draft = database::drafts::get_draft(&state.db, &user.id, &roomid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let draft_uploads = database::drafts::get_draft_uploads(&state.db, &user.id, &roomid)
+        .await
+        .map_err(FrontendError::InternalError)?
+        .into_iter()
+        .map(|upload| PendingUpload {
+            file_type: mime_guess::from_path(&upload.filename)
+                .first_or_octet_stream()
+                .type_()
+                .to_string(),
+            path: upload.url,
+            upload_id: upload.id,
+        })
+        .collect::<Vec<PendingUpload>>();
+
+    let notifications = notifications::room_context(&state, &user.id, &roomid).await?;
+
+    database::preferences::mark_read(&state.db, &user.id, &roomid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+    database::notifications::mark_room_read(&state.db, &user.id, &roomid)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
     let output = if is_htmx {
         state.templates.render_template(
             "components/chatroom.jinja2",
-            context! { roomid => roomid, currentRoom => room, messages => messages, page => page, user => user, roomUsers => room_users },
+            context! { roomid => roomid, currentRoom => room, messages => messages, page => page, user => user, roomUsers => room_users, draft => draft, draftUploads => draft_uploads, notifications => notifications },
         )?
     } else {
         let (user_rooms, rooms) = database::rooms::get_rooms(&state.db, &user.id)
@@ -244,11 +621,16 @@ async fn room_handler(
 
         state.templates.render_template(
             "room.jinja2",
-            context! { rooms => rooms, roomid => roomid, currentRoom => room, user_rooms => user_rooms , messages => messages, page => page, user => user, roomUsers => room_users },
+            context! { rooms => rooms, roomid => roomid, currentRoom => room, user_rooms => user_rooms , messages => messages, page => page, user => user, roomUsers => room_users, draft => draft, draftUploads => draft_uploads, notifications => notifications },
         )?
     };
 
-    Ok(Html(output).into_response())
+    // the room no longer shows as unread in the sidebar or the notification center
+    Ok((
+        HxResponseTrigger::normal([ACTIVITY_CHANGED, NOTIFICATIONS_CHANGED]),
+        Html(output),
+    )
+        .into_response())
 }
 
 async fn redirect_to_home(jar: CookieJar, state: &Arc<FrontendState>) -> Result<(), FrontendError> {
@@ -282,17 +664,29 @@ async fn register_handler(
 ) -> Result<impl IntoResponse, FrontendError> {
     redirect_to_home(jar, &state).await?;
 
-    if id != *state.register_id.read() {
-        return Err(FrontendError::NotFound(
-            "invalid registration link".to_string(),
-        ));
+    // accounts come from single sign-on only
+    if !state.password_login {
+        return Ok(Redirect::temporary("/login").into_response());
     }
 
+    // the very first account is registered without an invite and becomes the admin
+    let email = if id == "admin" && !state.has_admin.load(Ordering::Relaxed) {
+        None
+    } else {
+        let invite = database::invites::get_usable_invite(&state.db, &users::hash_api_token(&id))
+            .await
+            .map_err(FrontendError::InternalError)?
+            .ok_or_else(|| FrontendError::NotFound("invalid registration link".to_string()))?;
+
+        invite.email
+    };
+
     let templates = &state.templates;
 
-    let output = templates.render_template("register.jinja2", context! {})?;
+    let output =
+        templates.render_template("register.jinja2", context! { invite => id, email => email })?;
 
-    Ok(Html(output))
+    Ok(Html(output).into_response())
 }
 
 #[debug_handler]
@@ -303,39 +697,94 @@ async fn user_handler(
 ) -> Result<impl IntoResponse, FrontendError> {
     let user = redirect_to_register(jar, &state).await?;
 
-    if !user.is_admin {
-        return Err(FrontendError::NoPermission);
-    }
+    permissions::require(&state, &user, Capability::ViewUsers).await?;
 
     let user_list = database::users::get_user_list(&state.db)
         .await
         .map_err(FrontendError::InternalError)?;
 
-    let output = if is_htmx {
-        let register_id = state.register_id.read();
-        let register_id = register_id.as_str().to_string();
+    let commands = database::commands::get_commands(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
 
+    let bots = database::bots::get_bots(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let policy = database::two_factor::get_policy(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?
+        .as_str();
+
+    let failed_logins = database::login_attempts::get_failed_attempts(&state.db, 20)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let invites = database::invites::get_invites(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let invite_rooms = invite_rooms(&state, &user.id).await?;
+
+    let roles = database::roles::get_roles(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let member_roles = database::roles::get_member_roles(&state.db)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let capabilities = permissions::capabilities(&state, &user).await?;
+
+    let output = if is_htmx {
         state.templates.render_template(
             "components/users.jinja2",
-            context! { register_id => register_id, user => user, userlist => user_list },
+            context! { invites => invites, invite_rooms => invite_rooms, user => user, userlist => user_list, commands => commands, bots => bots, policy => policy, failed_logins => failed_logins, roles => roles, member_roles => member_roles, all_capabilities => all_capabilities(), capabilities => capabilities },
         )?
     } else {
         let (user_rooms, rooms) = database::rooms::get_rooms(&state.db, &user.id)
             .await
             .map_err(FrontendError::InternalError)?;
 
-        let register_id = state.register_id.read();
-        let register_id = register_id.as_str().to_string();
-
         state.templates.render_template(
             "users.jinja2",
-            context! { rooms => rooms, user_rooms => user_rooms, register_id => register_id, user => user, userlist => user_list },
+            context! { rooms => rooms, user_rooms => user_rooms, invites => invites, invite_rooms => invite_rooms, user => user, userlist => user_list, commands => commands, bots => bots, policy => policy, failed_logins => failed_logins, roles => roles, member_roles => member_roles, all_capabilities => all_capabilities(), capabilities => capabilities },
         )?
     };
 
     Ok(Html(output))
 }
 
+#[debug_handler]
+async fn login_two_factor_handler(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(user_id) = extract_pending_login(&jar, &state.secret) else {
+        return Err(FrontendError::Unauthorized);
+    };
+
+    let enabled = database::two_factor::is_enabled(&state.db, &user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    // the policy requires a second factor this user does not have yet, enroll them right here
+    let setup = if enabled {
+        None
+    } else {
+        let user = database::users::get_user_with_profile(&state.db, &user_id)
+            .await
+            .map_err(FrontendError::InternalError)?;
+        Some(begin_two_factor_setup(&state, &user).await?)
+    };
+
+    let output = state
+        .templates
+        .render_template("login-2fa.jinja2", context! { setup => setup })?;
+
+    Ok(Html(output))
+}
+
 #[debug_handler]
 async fn login_handler(
     jar: CookieJar,
@@ -345,7 +794,57 @@ async fn login_handler(
 
     let templates = &state.templates;
 
-    let output = templates.render_template("login.jinja2", context! {})?;
+    let output = templates.render_template(
+        "login.jinja2",
+        context! {
+            sso => state.oidc.is_some(),
+            login_form => !state.auth_backends.is_empty(),
+            password_reset => state.password_login,
+        },
+    )?;
 
     Ok(Html(output))
 }
+
+#[debug_handler]
+async fn forgot_password_handler(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    redirect_to_home(jar, &state).await?;
+
+    if !state.password_login {
+        return Ok(Redirect::temporary("/login").into_response());
+    }
+
+    let output = state.templates.render_template(
+        "forgot-password.jinja2",
+        context! { has_mailer => state.mailer.is_some() },
+    )?;
+
+    Ok(Html(output).into_response())
+}
+
+#[debug_handler]
+async fn reset_password_handler(
+    Path(token): Path<String>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    if !state.password_login {
+        return Ok(Redirect::temporary("/login").into_response());
+    }
+
+    let username = database::password_resets::get_password_reset_user(
+        &state.db,
+        &users::hash_api_token(&token),
+    )
+    .await
+    .ok();
+
+    let output = state.templates.render_template(
+        "reset-password.jinja2",
+        context! { token => token, username => username },
+    )?;
+
+    Ok(Html(output).into_response())
+}
@@ -0,0 +1,235 @@
+use std::{sync::Arc, time::Duration};
+
+use database::{
+    audit::{AuditAction, AuditEvent},
+    digests::{DigestRecipient, MissedMessage},
+};
+use minijinja::context;
+use push::{Delivery, PushService, Subscription};
+use rooms::OfflineAlert;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{audit, FrontendState};
+
+const GUEST_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+// how often to look for people whose digest is due
+const DIGEST_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// a digest is a reminder to come back, not a transcript
+const DIGEST_MESSAGES: i64 = 50;
+// push services cap payloads at around 4KB
+const PUSH_BODY_LENGTH: usize = 500;
+
+/// Starts the work that runs on a timer or on events instead of on a request.
+pub(crate) fn spawn(state: Arc<FrontendState>) {
+    if let Some(push) = state.push.clone() {
+        let mut alerts = state.room_manager.subscribe_offline();
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                match alerts.recv().await {
+                    Ok(alert) => push_alert(&state, push.as_ref(), alert).await,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("{} push alerts were dropped", missed)
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    if let Some(mailer) = state.mailer.clone() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DIGEST_INTERVAL);
+            loop {
+                interval.tick().await;
+                send_digests(&state, mailer.as_ref()).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GUEST_EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            expire_guests(&state).await;
+        }
+    });
+}
+
+/// Pushes an alert to every device the user subscribed, forgetting the ones that expired.
+async fn push_alert(state: &FrontendState, push: &dyn PushService, alert: OfflineAlert) {
+    let subscriptions = match database::push::get_subscriptions(&state.db, &alert.user_id).await {
+        Ok(subscriptions) => subscriptions,
+        Err(e) => {
+            tracing::warn!("failed to look up push subscriptions: {}", e);
+            return;
+        }
+    };
+
+    let body: String = alert.message.chars().take(PUSH_BODY_LENGTH).collect();
+    let payload = serde_json::json!({
+        "title": alert.sender,
+        "body": body,
+        "tag": alert.room_id,
+        "url": format!("{}/chatroom/{}", state.public_url, alert.room_id),
+    })
+    .to_string();
+
+    for subscription in subscriptions {
+        let target = Subscription {
+            endpoint: subscription.endpoint,
+            p256dh: subscription.p256dh,
+            auth: subscription.auth,
+        };
+
+        let result = match push.send(&target, payload.as_bytes()).await {
+            Ok(Delivery::Sent) => database::push::mark_used(&state.db, &subscription.id).await,
+            Ok(Delivery::Expired) => {
+                tracing::info!("push subscription {} expired", subscription.id);
+                database::push::remove_expired(&state.db, &subscription.id).await
+            }
+            Err(e) => {
+                tracing::warn!("failed to push to {}: {}", target.endpoint, e);
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            tracing::warn!("failed to update push subscription: {}", e);
+        }
+    }
+}
+
+async fn expire_guests(state: &FrontendState) {
+    match database::guests::deactivate_expired_guests(&state.db).await {
+        Ok(user_ids) => {
+            for user_id in user_ids {
+                tracing::info!("deactivated guest {} whose access expired", user_id);
+                audit::record_event(
+                    state,
+                    AuditEvent {
+                        actor_id: None,
+                        action: AuditAction::GuestExpired,
+                        target_id: Some(&user_id),
+                        details: None,
+                        ip: None,
+                    },
+                )
+                .await;
+            }
+        }
+        Err(e) => tracing::warn!("failed to deactivate expired guests: {}", e),
+    }
+}
+
+/// Mails everyone whose digest is due the direct messages and mentions they missed.
+async fn send_digests(state: &FrontendState, mailer: &dyn mailer::Mailer) {
+    let recipients = match database::digests::get_due_recipients(&state.db).await {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            tracing::warn!("failed to look up digest recipients: {}", e);
+            return;
+        }
+    };
+
+    for recipient in recipients {
+        let missed =
+            match database::digests::get_missed(&state.db, &recipient.user_id, DIGEST_MESSAGES)
+                .await
+            {
+                Ok(missed) => missed,
+                Err(e) => {
+                    tracing::warn!("failed to collect missed messages: {}", e);
+                    continue;
+                }
+            };
+
+        // nothing missed, check again next round
+        if missed.is_empty() {
+            continue;
+        }
+
+        let mail = match digest_mail(state, &recipient, missed) {
+            Ok(mail) => mail,
+            Err(e) => {
+                tracing::warn!("failed to render digest: {}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = mailer.send(&mail).await {
+            tracing::warn!("failed to send digest to {}: {}", recipient.user_id, e);
+            continue;
+        }
+
+        if let Err(e) = database::digests::mark_sent(&state.db, &recipient.user_id).await {
+            tracing::warn!("failed to record digest: {}", e);
+        }
+    }
+}
+
+fn digest_mail(
+    state: &FrontendState,
+    recipient: &DigestRecipient,
+    missed: Vec<MissedMessage>,
+) -> Result<mailer::Mail, crate::FrontendError> {
+    let count = missed.len();
+
+    // grouped by room, in the order the rooms first had something missed
+    let mut rooms: Vec<(String, String, bool, Vec<MissedMessage>)> = Vec::new();
+    for message in missed {
+        match rooms.iter_mut().find(|(id, ..)| *id == message.room_id) {
+            Some((.., messages)) => messages.push(message),
+            None => rooms.push((
+                message.room_id.clone(),
+                message.room_name.clone(),
+                message.is_direct,
+                vec![message],
+            )),
+        }
+    }
+    let rooms: Vec<_> = rooms
+        .into_iter()
+        .map(|(id, name, is_direct, messages)| {
+            // direct rooms are named after whoever the creator picked, which may be the recipient
+            let mut senders: Vec<&str> = Vec::new();
+            for message in &messages {
+                if !senders.contains(&message.user_name.as_str()) {
+                    senders.push(&message.user_name);
+                }
+            }
+
+            context! {
+                name => name,
+                senders => senders.join(", "),
+                is_direct => is_direct,
+                url => format!("{}/chatroom/{}", state.public_url, id),
+                messages => messages,
+            }
+        })
+        .collect();
+
+    let ctx = context! {
+        name => recipient.display_name.as_deref().unwrap_or(&recipient.username),
+        count => count,
+        rooms => rooms,
+        public_url => state.public_url,
+    };
+
+    Ok(mailer::Mail {
+        to: recipient.email.clone(),
+        subject: match count {
+            1 => "You missed a message on SpeakWith".to_string(),
+            _ => format!("You missed {} messages on SpeakWith", count),
+        },
+        body: state
+            .templates
+            .render_template("mail/digest.txt.jinja2", &ctx)?,
+        html: Some(
+            state
+                .templates
+                .render_template("mail/digest.html.jinja2", &ctx)?,
+        ),
+    })
+}
@@ -0,0 +1,83 @@
+use database::{reports::Report, users::UserCombined};
+use minijinja::{context, Value};
+
+use crate::{FrontendError, FrontendState};
+
+// the queue is worked through from the oldest, nobody pages through hundreds
+const PENDING_SHOWN: i64 = 100;
+const RESOLVED_SHOWN: i64 = 20;
+
+/// Open reports with the messages around them, and the ones handled lately.
+pub(crate) async fn queue_context(state: &FrontendState) -> Result<Value, FrontendError> {
+    let pending = database::reports::get_reports(&state.db, false, PENDING_SHOWN)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let mut reports = Vec::with_capacity(pending.len());
+    for report in pending {
+        let surrounding = database::reports::get_context(&state.db, &report.id)
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+        reports.push(context! { surrounding => surrounding, ..Value::from_serializable(&report) });
+    }
+
+    let resolved = database::reports::get_reports(&state.db, true, RESOLVED_SHOWN)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok(context! { reports => reports, resolved => resolved })
+}
+
+/// Sends the author a direct message from the moderator about the reported message.
+pub(crate) async fn warn_author(
+    state: &FrontendState,
+    moderator: &UserCombined,
+    report: &Report,
+    note: Option<&str>,
+) -> Result<(), FrontendError> {
+    let (Some(author_id), Some(author_name)) = (&report.author_id, &report.author_name) else {
+        return Err(FrontendError::InvalidForm(
+            "the author no longer has an account".into(),
+        ));
+    };
+
+    if *author_id == moderator.id {
+        return Err(FrontendError::NoPermission);
+    }
+
+    let mut users = vec![author_id.clone(), moderator.id.clone()];
+    users.sort_unstable();
+
+    // the same room a direct message between the two would use
+    let room_id = database::rooms::create_room(
+        &state.db,
+        &users.join("-"),
+        author_name,
+        "",
+        true,
+        true,
+        &users,
+    )
+    .await
+    .map_err(FrontendError::InternalError)?;
+
+    let excerpt: String = report.message.chars().take(80).collect();
+    let message = format!(
+        r#"Moderator warning about your message in #{} ("{}"): {}"#,
+        report.room_name.as_deref().unwrap_or(&report.room_id),
+        excerpt,
+        note.unwrap_or("please keep to the rules of this workspace.")
+    );
+
+    state
+        .room_manager
+        .send_message(&room_id, moderator, &message, vec![])
+        .await
+        .map_err(|e| match e.downcast::<database::blocks::BlockError>() {
+            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+            Err(e) => FrontendError::InternalError(e),
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use database::{
+    digests::DigestFrequency,
+    preferences::{NotifyLevel, QuietHours},
+};
+use minijinja::{context, Value};
+use time::OffsetDateTime;
+
+use crate::{FrontendError, FrontendState};
+
+// how long a room can be muted for from its menu, in minutes, 0 being until unmuted
+pub(crate) const MUTE_CHOICES: &[(i64, &str)] = &[
+    (60, "For 1 hour"),
+    (8 * 60, "For 8 hours"),
+    (24 * 60, "For 24 hours"),
+    (7 * 24 * 60, "For a week"),
+];
+
+/// The user's notification settings for a room, for the menu in its title.
+pub(crate) async fn room_context(
+    state: &FrontendState,
+    user_id: &str,
+    room_id: &str,
+) -> Result<Value, FrontendError> {
+    let preference = database::preferences::get_room_preference(&state.db, user_id, room_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let levels: Vec<_> = NotifyLevel::ALL
+        .iter()
+        .map(|l| context! { value => l.as_str(), label => l.label() })
+        .collect();
+    let mute_choices: Vec<_> = MUTE_CHOICES
+        .iter()
+        .map(|(minutes, label)| context! { minutes => minutes, label => label })
+        .collect();
+
+    Ok(context! {
+        room_id => room_id,
+        level => preference.level,
+        is_muted => preference.is_muted(OffsetDateTime::now_utc()),
+        muted_until => preference.muted_until,
+        levels => levels,
+        mute_choices => mute_choices,
+    })
+}
+
+/// Quiet hours as HH:MM for time inputs.
+pub(crate) async fn quiet_hours_context(
+    state: &FrontendState,
+    user_id: &str,
+) -> Result<Value, FrontendError> {
+    let hours = database::preferences::get_quiet_hours(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let format = |minutes: Option<i64>| minutes.map(|m| format!("{:02}:{:02}", m / 60, m % 60));
+
+    Ok(context! {
+        start => format(hours.start),
+        end => format(hours.end),
+    })
+}
+
+/// The user's digest frequency with the choices for the profile.
+pub(crate) async fn digest_context(
+    state: &FrontendState,
+    user_id: &str,
+) -> Result<Value, FrontendError> {
+    let frequency = database::digests::get_frequency(&state.db, user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    let frequencies: Vec<_> = DigestFrequency::ALL
+        .iter()
+        .map(|f| context! { value => f.as_str(), label => f.label() })
+        .collect();
+
+    Ok(context! {
+        frequency => frequency.as_str(),
+        frequencies => frequencies,
+        has_mailer => state.mailer.is_some(),
+    })
+}
+
+/// Reads the HH:MM a time input sends, empty meaning no quiet hours.
+pub(crate) fn parse_quiet_hours(start: &str, end: &str) -> Result<QuietHours, FrontendError> {
+    let parse = |value: &str| -> Result<Option<i64>, FrontendError> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let invalid = || FrontendError::InvalidForm(format!("{value} is not a time of day"));
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        let hours: i64 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i64 = minutes.parse().map_err(|_| invalid())?;
+        if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+            return Err(invalid());
+        }
+
+        Ok(Some(hours * 60 + minutes))
+    };
+
+    Ok(QuietHours {
+        start: parse(start)?,
+        end: parse(end)?,
+    })
+}
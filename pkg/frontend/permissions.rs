@@ -0,0 +1,66 @@
+use database::{roles::Capability, users::UserCombined};
+use minijinja::{context, Value};
+
+use crate::{FrontendError, FrontendState};
+
+/// Capability names the user holds, for templates to decide what to show.
+pub(crate) async fn capabilities(
+    state: &FrontendState,
+    user: &UserCombined,
+) -> Result<Vec<&'static str>, FrontendError> {
+    // admins hold every capability whatever their roles say
+    let capabilities = if user.is_admin {
+        Capability::ALL.to_vec()
+    } else {
+        database::roles::get_capabilities(&state.db, &user.id)
+            .await
+            .map_err(FrontendError::InternalError)?
+    };
+
+    Ok(capabilities.iter().map(|c| c.as_str()).collect())
+}
+
+/// Every capability with a label, for the role editor.
+pub(crate) fn all_capabilities() -> Vec<Value> {
+    Capability::ALL
+        .iter()
+        .map(|c| context! { id => c.as_str(), label => c.label() })
+        .collect()
+}
+
+pub(crate) async fn has_capability(
+    state: &FrontendState,
+    user: &UserCombined,
+    capability: Capability,
+) -> Result<bool, FrontendError> {
+    if user.is_admin {
+        return Ok(true);
+    }
+
+    let capabilities = database::roles::get_capabilities(&state.db, &user.id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    Ok(capabilities.contains(&capability))
+}
+
+pub(crate) async fn require(
+    state: &FrontendState,
+    user: &UserCombined,
+    capability: Capability,
+) -> Result<(), FrontendError> {
+    if !has_capability(state, user, capability).await? {
+        return Err(FrontendError::NoPermission);
+    }
+
+    Ok(())
+}
+
+/// For what no role can grant, like bots, commands and security settings.
+pub(crate) fn require_admin(user: &UserCombined) -> Result<(), FrontendError> {
+    if !user.is_admin {
+        return Err(FrontendError::NoPermission);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
+
+use axum::{
+    debug_handler,
+    extract::{ConnectInfo, Query, State},
+    http::HeaderMap,
+    response::{Html, IntoResponse, Redirect},
+    routing::get,
+    Router,
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+
+use database::{login_attempts::LoginOutcome, users::ExternalLink};
+
+use crate::{
+    admin_created, audit, client_ip, needs_second_factor, render_awaiting_approval,
+    request_approval, set_pending_login, set_token, FrontendError, FrontendState,
+};
+
+const AUTH_REQUEST_COOKIE: &str = "oidc_request";
+
+pub(crate) const CALLBACK_PATH: &str = "/auth/oidc/callback";
+
+pub(crate) fn setup_sso(state: Arc<FrontendState>) -> Router {
+    Router::new()
+        .route("/login", get(handle_sso_login))
+        .route("/callback", get(handle_sso_callback))
+        .with_state(state)
+}
+
+#[debug_handler]
+async fn handle_sso_login(
+    jar: CookieJar,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(oidc) = &state.oidc else {
+        return Err(FrontendError::NotFound(
+            "single sign-on is not configured".to_string(),
+        ));
+    };
+
+    let (url, request) = oidc
+        .authorize_url(&state.secret)
+        .map_err(FrontendError::InternalError)?;
+
+    // Lax, the identity provider sends the browser back with a top level navigation
+    let mut cookie = Cookie::new(AUTH_REQUEST_COOKIE, request);
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_path("/");
+    cookie.set_http_only(true);
+
+    Ok((jar.add(cookie), Redirect::to(&url)))
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[debug_handler]
+async fn handle_sso_callback(
+    mut jar: CookieJar,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(query): Query<CallbackQuery>,
+    State(state): State<Arc<FrontendState>>,
+) -> Result<impl IntoResponse, FrontendError> {
+    let Some(oidc) = &state.oidc else {
+        return Err(FrontendError::NotFound(
+            "single sign-on is not configured".to_string(),
+        ));
+    };
+
+    if let Some(error) = query.error {
+        return Err(FrontendError::InvalidForm(format!(
+            "sign in failed: {}",
+            query.error_description.unwrap_or(error)
+        )));
+    }
+
+    let (Some(code), Some(oidc_state)) = (query.code, query.state) else {
+        return Err(FrontendError::InvalidForm(
+            "missing code or state".to_string(),
+        ));
+    };
+
+    let Some(request) = jar.get(AUTH_REQUEST_COOKIE).map(|c| c.value().to_string()) else {
+        return Err(FrontendError::Unauthorized);
+    };
+    jar = jar.remove(Cookie::build(AUTH_REQUEST_COOKIE).path("/"));
+
+    let identity = oidc
+        .exchange(&state.secret, &request, &oidc_state, &code)
+        .await
+        .map_err(|e| FrontendError::InvalidForm(format!("sign in failed: {}", e)))?;
+
+    let user =
+        match database::sso::find_identity_user(&state.db, &identity.issuer, &identity.subject)
+            .await
+            .map_err(FrontendError::InternalError)?
+        {
+            Some(user_id) => Some(
+                database::users::get_user_with_profile(&state.db, &user_id)
+                    .await
+                    .map_err(FrontendError::InternalError)?,
+            ),
+            // the first sign in links an existing account with the same verified address,
+            // later ones only go by the subject
+            None => match database::users::find_user_by_email(&state.db, &identity.email)
+                .await
+                .map_err(FrontendError::InternalError)?
+            {
+                Some(user) if user.is_bot => return Err(FrontendError::NoPermission),
+                Some(user) => {
+                    database::sso::link_identity(
+                        &state.db,
+                        &identity.issuer,
+                        &identity.subject,
+                        &user.id,
+                    )
+                    .await
+                    .map_err(|e| {
+                        match e.downcast::<database::sso::SsoError>() {
+                            Ok(e) => FrontendError::InvalidForm(e.to_string()),
+                            Err(e) => FrontendError::InternalError(e),
+                        }
+                    })?;
+                    Some(user)
+                }
+                None => None,
+            },
+        };
+
+    let (user_id, is_enabled) = match user {
+        Some(user) if user.is_bot => return Err(FrontendError::NoPermission),
+        Some(user) => (user.id, user.is_enabled),
+        None => {
+            // same rules as the registration form, the very first account becomes the admin
+            let has_admin = state.has_admin.load(Ordering::Relaxed);
+            let username = identity.name.unwrap_or_else(|| {
+                identity
+                    .email
+                    .split('@')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            });
+
+            let user_id = database::users::create_external_user(
+                &state.db,
+                &identity.email,
+                &username,
+                !has_admin,
+                !has_admin,
+                ExternalLink::Sso {
+                    issuer: &identity.issuer,
+                    subject: &identity.subject,
+                },
+            )
+            .await
+            .map_err(FrontendError::InternalError)?;
+
+            if !has_admin {
+                admin_created(&state);
+            } else {
+                request_approval(&state, &user_id).await?;
+            }
+
+            (user_id, !has_admin)
+        }
+    };
+
+    if !is_enabled {
+        let output = render_awaiting_approval(&state, Some(&user_id)).await?;
+
+        return Ok((jar, output).into_response());
+    }
+
+    // same second factor as a password login, the account may have been a local one first
+    if needs_second_factor(&state, &user_id).await? {
+        jar = set_pending_login(&state, jar, &user_id)?;
+
+        return Ok((
+            jar,
+            Html(r#"<meta http-equiv="refresh" content="0; url=/login/2fa">"#),
+        )
+            .into_response());
+    }
+
+    let ip = client_ip(&state, connect_info, &headers);
+    audit::record_login(
+        &state,
+        &identity.email.to_lowercase(),
+        &ip,
+        LoginOutcome::Success,
+        Some(&user_id),
+    )
+    .await?;
+
+    let (user_token, expires_at) = users::start_session(&state.db, &state.secret, &user_id)
+        .await
+        .map_err(FrontendError::InternalError)?;
+
+    jar = set_token(jar, user_token, expires_at);
+
+    // strict cookies are held back on a redirect that started at the identity provider,
+    // moving on from a page of our own makes the browser send the new token along
+    Ok((
+        jar,
+        Html(r#"<meta http-equiv="refresh" content="0; url=/">"#),
+    )
+        .into_response())
+}
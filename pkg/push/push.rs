@@ -0,0 +1,422 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::{future::BoxFuture, FutureExt};
+use reqwest::{StatusCode, Url};
+use ring::{
+    aead, agreement, hkdf,
+    rand::{SecureRandom, SystemRandom},
+    signature::{self, EcdsaKeyPair, KeyPair},
+};
+use thiserror::Error;
+
+pub mod stub;
+
+// file under the data path holding the VAPID private key as PKCS#8
+const VAPID_KEY_FILE: &str = "vapid.der";
+// push services reject VAPID tokens valid for longer than a day
+const TOKEN_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+// how long the push service keeps a message for an offline device
+const MESSAGE_TTL: u32 = 24 * 60 * 60;
+const RECORD_SIZE: u32 = 4096;
+// hosts of the browser vendors' push services, subdomains included, so subscribing
+// cannot make the server post to arbitrary addresses
+const PUSH_SERVICES: &[&str] = &[
+    "fcm.googleapis.com",
+    "android.googleapis.com",
+    "push.services.mozilla.com",
+    "notify.windows.com",
+    "push.apple.com",
+];
+
+#[derive(Error, Debug)]
+pub enum PushError {
+    #[error("push endpoints have to be https urls")]
+    InvalidEndpoint,
+    #[error("{0} is not a known push service")]
+    UnknownService(String),
+    #[error("the subscription keys are not valid")]
+    InvalidKeys,
+    #[error("the message could not be encrypted")]
+    Encryption,
+    #[error("the push service answered {0}")]
+    Rejected(StatusCode),
+}
+
+/// Where a browser wants its pushes sent, as handed out by `PushManager.subscribe`.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub endpoint: String,
+    // base64url encoded P-256 public key of the browser
+    pub p256dh: String,
+    // base64url encoded authentication secret
+    pub auth: String,
+}
+
+impl Subscription {
+    /// Checks the endpoint belongs to a known push service and the keys decode to what
+    /// RFC 8291 expects, `stub_prefix` lets the stand-in push service's endpoints through.
+    pub fn validate(&self, stub_prefix: Option<&str>) -> Result<(), PushError> {
+        let url = Url::parse(&self.endpoint).map_err(|_| PushError::InvalidEndpoint)?;
+        // compared after parsing so dot segments cannot climb out of the stub
+        let stub = stub_prefix.is_some_and(|prefix| url.as_str().starts_with(prefix));
+        if !stub {
+            if url.scheme() != "https" {
+                return Err(PushError::InvalidEndpoint);
+            }
+
+            let host = url.host_str().unwrap_or_default();
+            let known = url.port().is_none()
+                && url.username().is_empty()
+                && PUSH_SERVICES
+                    .iter()
+                    .any(|service| host == *service || host.ends_with(&format!(".{service}")));
+            if !known {
+                return Err(PushError::UnknownService(host.to_string()));
+            }
+        }
+
+        self.keys().map(|_| ())
+    }
+
+    fn keys(&self) -> Result<(Vec<u8>, Vec<u8>), PushError> {
+        let public = URL_SAFE_NO_PAD
+            .decode(self.p256dh.trim_end_matches('='))
+            .map_err(|_| PushError::InvalidKeys)?;
+        let auth = URL_SAFE_NO_PAD
+            .decode(self.auth.trim_end_matches('='))
+            .map_err(|_| PushError::InvalidKeys)?;
+
+        if public.len() != 65 || public[0] != 0x04 || auth.len() != 16 {
+            return Err(PushError::InvalidKeys);
+        }
+
+        Ok((public, auth))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Sent,
+    // the browser unsubscribed or the subscription ran out, it should be forgotten
+    Expired,
+}
+
+/// Anything that can deliver a push message to a subscription.
+pub trait PushService: Send + Sync {
+    /// The VAPID public key browsers subscribe with, base64url encoded.
+    fn public_key(&self) -> &str;
+
+    fn send<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        payload: &'a [u8],
+    ) -> BoxFuture<'a, Result<Delivery>>;
+}
+
+/// Sends pushes through the browser vendors' push services, signed with a VAPID key.
+pub struct WebPush {
+    key_pair: EcdsaKeyPair,
+    public_key: String,
+    // contact for the push service operators, a mailto: or https: url
+    subject: String,
+    rng: SystemRandom,
+    client: reqwest::Client,
+}
+
+impl WebPush {
+    /// Loads the VAPID key from the data path, generating one the first time.
+    pub fn new(data_path: &Path, subject: &str) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let path = data_path.join(VAPID_KEY_FILE);
+
+        let pkcs8 = match std::fs::read(&path) {
+            Ok(pkcs8) => pkcs8,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let document =
+                    EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                        .map_err(|_| PushError::Encryption)?;
+                std::fs::write(&path, document.as_ref())?;
+                tracing::info!("generated a VAPID key in {}", path.display());
+                document.as_ref().to_vec()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+                .map_err(|e| anyhow::anyhow!("invalid VAPID key in {}: {}", path.display(), e))?;
+        let public_key = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+
+        Ok(Self {
+            key_pair,
+            public_key,
+            subject: subject.to_string(),
+            rng,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// The VAPID token for the push service behind `endpoint`, per RFC 8292.
+    fn authorization(&self, endpoint: &Url) -> Result<String> {
+        let expires = SystemTime::now().duration_since(UNIX_EPOCH)? + TOKEN_LIFETIME;
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(
+            serde_json::json!({
+                "aud": endpoint.origin().ascii_serialization(),
+                "exp": expires.as_secs(),
+                "sub": self.subject,
+            })
+            .to_string(),
+        );
+
+        let message = format!("{header}.{claims}");
+        let signature = self
+            .key_pair
+            .sign(&self.rng, message.as_bytes())
+            .map_err(|_| PushError::Encryption)?;
+
+        Ok(format!(
+            "vapid t={}.{}, k={}",
+            message,
+            URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            self.public_key
+        ))
+    }
+
+    /// Encrypts the payload as a single aes128gcm record, per RFC 8291.
+    fn encrypt(&self, subscription: &Subscription, payload: &[u8]) -> Result<Vec<u8>> {
+        let (ua_public, auth) = subscription.keys()?;
+
+        let private = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &self.rng)
+            .map_err(|_| PushError::Encryption)?;
+        let as_public = private
+            .compute_public_key()
+            .map_err(|_| PushError::Encryption)?;
+        let as_public = as_public.as_ref();
+
+        let peer = agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, &ua_public);
+        let ikm = agreement::agree_ephemeral(private, &peer, |shared| {
+            let key_info = [b"WebPush: info\0".as_slice(), &ua_public, as_public].concat();
+            expand(
+                hkdf::Salt::new(hkdf::HKDF_SHA256, &auth).extract(shared),
+                &key_info,
+                32,
+            )
+        })
+        .map_err(|_| PushError::InvalidKeys)??;
+
+        let mut salt = [0u8; 16];
+        self.rng
+            .fill(&mut salt)
+            .map_err(|_| PushError::Encryption)?;
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &salt).extract(&ikm);
+        let cek = expand(prk.clone(), b"Content-Encoding: aes128gcm\0", 16)?;
+        let nonce = expand(prk, b"Content-Encoding: nonce\0", 12)?;
+
+        let key =
+            aead::UnboundKey::new(&aead::AES_128_GCM, &cek).map_err(|_| PushError::Encryption)?;
+        let nonce =
+            aead::Nonce::try_assume_unique_for_key(&nonce).map_err(|_| PushError::Encryption)?;
+
+        // a single record, so the padding delimiter marks it as the last one
+        let mut record = [payload, &[2]].concat();
+        aead::LessSafeKey::new(key)
+            .seal_in_place_append_tag(nonce, aead::Aad::empty(), &mut record)
+            .map_err(|_| PushError::Encryption)?;
+
+        let mut body = Vec::with_capacity(21 + as_public.len() + record.len());
+        body.extend_from_slice(&salt);
+        body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+        body.push(as_public.len() as u8);
+        body.extend_from_slice(as_public);
+        body.extend_from_slice(&record);
+
+        Ok(body)
+    }
+}
+
+impl PushService for WebPush {
+    fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    fn send<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        payload: &'a [u8],
+    ) -> BoxFuture<'a, Result<Delivery>> {
+        async move {
+            let endpoint = Url::parse(&subscription.endpoint)?;
+            let body = self.encrypt(subscription, payload)?;
+
+            let response = self
+                .client
+                .post(endpoint.clone())
+                .header("Authorization", self.authorization(&endpoint)?)
+                .header("Content-Encoding", "aes128gcm")
+                .header("Content-Type", "application/octet-stream")
+                .header("TTL", MESSAGE_TTL.to_string())
+                .header("Urgency", "high")
+                .body(body)
+                .send()
+                .await?;
+
+            match response.status() {
+                status if status.is_success() => Ok(Delivery::Sent),
+                StatusCode::NOT_FOUND | StatusCode::GONE => Ok(Delivery::Expired),
+                status => Err(PushError::Rejected(status).into()),
+            }
+        }
+        .boxed()
+    }
+}
+
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn expand(prk: hkdf::Prk, info: &[u8], len: usize) -> Result<Vec<u8>, PushError> {
+    let mut out = vec![0u8; len];
+    prk.expand(&[info], Len(len))
+        .and_then(|okm| okm.fill(&mut out))
+        .map_err(|_| PushError::Encryption)?;
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STUB: &str = "http://localhost:3000/push-stub/";
+
+    fn subscription(endpoint: &str) -> Subscription {
+        Subscription {
+            endpoint: endpoint.to_string(),
+            p256dh: URL_SAFE_NO_PAD.encode([[4u8].as_slice(), &[7; 64]].concat()),
+            auth: URL_SAFE_NO_PAD.encode([9; 16]),
+        }
+    }
+
+    #[test]
+    fn only_accepts_known_push_services() {
+        for endpoint in [
+            "https://fcm.googleapis.com/fcm/send/abc",
+            "https://updates.push.services.mozilla.com/wpush/v2/abc",
+            "https://wns2-par02p.notify.windows.com/w/?token=abc",
+            "https://web.push.apple.com/abc",
+        ] {
+            assert!(subscription(endpoint).validate(None).is_ok(), "{endpoint}");
+        }
+
+        for endpoint in [
+            "http://fcm.googleapis.com/fcm/send/abc",
+            "https://fcm.googleapis.com:8443/fcm/send/abc",
+            "https://fcm.googleapis.com.example.com/abc",
+            "https://evilfcm.googleapis.com.evil/abc",
+            "https://169.254.169.254/latest/meta-data",
+            "https://localhost/admin",
+            "http://localhost:3000/push-stub/abc",
+        ] {
+            assert!(subscription(endpoint).validate(None).is_err(), "{endpoint}");
+        }
+    }
+
+    #[test]
+    fn accepts_the_stub_only_when_mounted() {
+        let stub = subscription("http://localhost:3000/push-stub/abc");
+        assert!(stub.validate(Some(STUB)).is_ok());
+        assert!(stub.validate(None).is_err());
+
+        // still has to stay below the stub
+        let climbing = subscription("http://localhost:3000/push-stub/../htmx/admin");
+        assert!(climbing.validate(Some(STUB)).is_err());
+    }
+
+    #[test]
+    fn rejects_broken_keys() {
+        let mut short = subscription("https://fcm.googleapis.com/fcm/send/abc");
+        short.auth = URL_SAFE_NO_PAD.encode([9; 8]);
+        assert!(matches!(short.validate(None), Err(PushError::InvalidKeys)));
+    }
+
+    // the browser's side of RFC 8291, undoing what WebPush::encrypt did
+    fn decrypt(
+        ua_private: agreement::EphemeralPrivateKey,
+        ua_public: &[u8],
+        auth: &[u8],
+        body: &[u8],
+    ) -> Vec<u8> {
+        let (salt, rest) = body.split_at(16);
+        let (record_size, rest) = rest.split_at(4);
+        assert_eq!(record_size, RECORD_SIZE.to_be_bytes());
+        let (as_public, record) = rest[1..].split_at(rest[0] as usize);
+
+        let peer = agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, as_public);
+        let ikm = agreement::agree_ephemeral(ua_private, &peer, |shared| {
+            let key_info = [b"WebPush: info\0".as_slice(), ua_public, as_public].concat();
+            expand(
+                hkdf::Salt::new(hkdf::HKDF_SHA256, auth).extract(shared),
+                &key_info,
+                32,
+            )
+        })
+        .unwrap()
+        .unwrap();
+
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&ikm);
+        let cek = expand(prk.clone(), b"Content-Encoding: aes128gcm\0", 16).unwrap();
+        let nonce = expand(prk, b"Content-Encoding: nonce\0", 12).unwrap();
+
+        let key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, &cek).unwrap());
+        let mut record = record.to_vec();
+        let plain = key
+            .open_in_place(
+                aead::Nonce::try_assume_unique_for_key(&nonce).unwrap(),
+                aead::Aad::empty(),
+                &mut record,
+            )
+            .unwrap();
+
+        // the last record ends with the 0x02 delimiter
+        assert_eq!(plain.last(), Some(&2));
+        plain[..plain.len() - 1].to_vec()
+    }
+
+    #[test]
+    fn encrypts_for_the_browser() {
+        let dir = std::env::temp_dir().join(format!("speakwith-push-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let push = WebPush::new(&dir, "mailto:admin@example.com").unwrap();
+
+        let rng = SystemRandom::new();
+        let ua_private =
+            agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng).unwrap();
+        let ua_public = ua_private.compute_public_key().unwrap().as_ref().to_vec();
+        let auth = [5u8; 16];
+        let subscription = Subscription {
+            endpoint: "https://fcm.googleapis.com/fcm/send/abc".to_string(),
+            p256dh: URL_SAFE_NO_PAD.encode(&ua_public),
+            auth: URL_SAFE_NO_PAD.encode(auth),
+        };
+
+        let payload = br##"{"title":"#general","body":"hello"}"##;
+        let body = push.encrypt(&subscription, payload).unwrap();
+        assert_eq!(decrypt(ua_private, &ua_public, &auth, &body), payload);
+
+        // the key is kept, a second start signs with the same one
+        let again = WebPush::new(&dir, "mailto:admin@example.com").unwrap();
+        assert_eq!(again.public_key(), push.public_key());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
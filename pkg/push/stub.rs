@@ -0,0 +1,79 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use parking_lot::Mutex;
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Received {
+    pub ttl: Option<String>,
+    pub urgency: Option<String>,
+    // the encrypted body, only the browser holding the keys can read it
+    pub size: usize,
+}
+
+#[derive(Default)]
+struct Stub {
+    received: Mutex<HashMap<String, Vec<Received>>>,
+    expired: Mutex<HashSet<String>>,
+}
+
+/// Stands in for a browser vendor's push service. Every path below it is a subscription
+/// endpoint: POST delivers to it, GET lists what was delivered and DELETE expires it so
+/// later pushes get a 410.
+pub fn router() -> Router {
+    Router::new()
+        .route("/:id", post(deliver).get(list).delete(expire))
+        .with_state(Arc::new(Stub::default()))
+}
+
+async fn deliver(
+    Path(id): Path<String>,
+    State(stub): State<Arc<Stub>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if stub.expired.lock().contains(&id) {
+        return StatusCode::GONE;
+    }
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let signed = header("authorization").is_some_and(|v| v.starts_with("vapid t="));
+    let encrypted = header("content-encoding").as_deref() == Some("aes128gcm");
+    if !signed || !encrypted {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    tracing::info!("push stub received {} bytes for {}", body.len(), id);
+
+    stub.received.lock().entry(id).or_default().push(Received {
+        ttl: header("ttl"),
+        urgency: header("urgency"),
+        size: body.len(),
+    });
+
+    StatusCode::CREATED
+}
+
+async fn list(Path(id): Path<String>, State(stub): State<Arc<Stub>>) -> Json<Vec<Received>> {
+    Json(stub.received.lock().get(&id).cloned().unwrap_or_default())
+}
+
+async fn expire(Path(id): Path<String>, State(stub): State<Arc<Stub>>) -> StatusCode {
+    stub.expired.lock().insert(id);
+    StatusCode::NO_CONTENT
+}
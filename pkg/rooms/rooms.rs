@@ -1,21 +1,71 @@
 use std::collections::HashMap;
 
 use anyhow::Result;
-use database::{messages::ChatMessage, Database};
+use database::{
+    handles::{mentioned_handles, ROOM_MENTIONS},
+    messages::ChatMessage,
+    notifications::{NewNotification, NotificationKind},
+    polls::NewPoll,
+    preferences::{NotifyLevel, Recipient},
+    users::UserCombined,
+    Database,
+};
 use parking_lot::RwLock;
 use thiserror::Error;
 use time::OffsetDateTime;
+use time_tz::{timezones, OffsetDateTimeExt};
 use tokio::sync::broadcast::{Receiver, Sender};
 
+// notifications quote the start of the message, the room has the rest
+const NOTIFICATION_BODY_LENGTH: usize = 200;
+
+#[derive(Clone, Debug)]
+pub enum RoomEvent {
+    Message(ChatMessage),
+    // only delivered to the connection belonging to user_id
+    Ephemeral { user_id: String, message: String },
+    PollUpdated { poll_id: String },
+}
+
+/// Sent to one person wherever they are in the app, not just in the room.
+#[derive(Clone, Debug)]
+pub enum UserEvent {
+    // the room has something new for the sidebar
+    Activity {
+        room_id: String,
+    },
+    // worth a desktop notification
+    Alert {
+        room_id: String,
+        sender: String,
+        message: String,
+    },
+    // a new entry in the notification center
+    Notification {
+        id: String,
+    },
+}
+
+/// An alert for someone with nothing open to show it, to be pushed to their devices instead.
+#[derive(Clone, Debug)]
+pub struct OfflineAlert {
+    pub user_id: String,
+    pub room_id: String,
+    pub sender: String,
+    pub message: String,
+}
+
 pub struct Room {
     pub room_id: String,
-    pub sender: Sender<ChatMessage>,
+    pub sender: Sender<RoomEvent>,
     pub db: Database,
 }
 
 pub struct Manager {
     db: Database,
     rooms: RwLock<HashMap<String, Room>>,
+    users: RwLock<HashMap<String, Sender<UserEvent>>>,
+    offline: Sender<OfflineAlert>,
 }
 
 impl Manager {
@@ -23,15 +73,19 @@ impl Manager {
         Self {
             db,
             rooms: Default::default(),
+            users: Default::default(),
+            offline: tokio::sync::broadcast::channel::<OfflineAlert>(1000).0,
         }
     }
 
-    pub async fn join_room(&self, room_id: String, user_id: &str) -> Result<Receiver<ChatMessage>> {
+    pub async fn join_room(&self, room_id: String, user_id: &str) -> Result<Receiver<RoomEvent>> {
         let _ = database::rooms::get_room(&self.db, &room_id, user_id).await?;
+        database::preferences::mark_read(&self.db, user_id, &room_id).await?;
+        database::notifications::mark_room_read(&self.db, user_id, &room_id).await?;
 
         let mut rooms = self.rooms.write();
         let room = rooms.entry(room_id.clone()).or_insert_with(move || {
-            let (sender, _) = tokio::sync::broadcast::channel::<ChatMessage>(1000);
+            let (sender, _) = tokio::sync::broadcast::channel::<RoomEvent>(1000);
             Room {
                 room_id,
                 sender,
@@ -43,6 +97,19 @@ impl Manager {
         Ok(recv)
     }
 
+    pub fn subscribe_user(&self, user_id: &str) -> Receiver<UserEvent> {
+        let mut users = self.users.write();
+        users
+            .entry(user_id.to_string())
+            .or_insert_with(|| tokio::sync::broadcast::channel::<UserEvent>(100).0)
+            .subscribe()
+    }
+
+    /// Alerts for DMs and mentions that reached nobody because the user had nothing open.
+    pub fn subscribe_offline(&self) -> Receiver<OfflineAlert> {
+        self.offline.subscribe()
+    }
+
     pub async fn get_room_messages(
         &self,
         room_id: &str,
@@ -58,19 +125,14 @@ impl Manager {
     pub async fn send_message(
         &self,
         room_id: &str,
-        user_id: &str,
-        user_name: &str,
-        user_image: Option<String>,
+        user: &UserCombined,
         message: &str,
         uploads: Vec<String>,
     ) -> Result<()> {
-        let id =
-            database::messages::send_message(&self.db, room_id, user_id, message, &uploads).await?;
+        database::blocks::ensure_can_message(&self.db, room_id, &user.id).await?;
 
-        let rooms = self.rooms.read();
-        let room = rooms
-            .get(room_id)
-            .ok_or_else(|| ChatRoomErrors::RoomEmpty(room_id.to_string()))?;
+        let id = database::messages::send_message(&self.db, room_id, &user.id, message, &uploads)
+            .await?;
 
         let uploads = if uploads.is_empty() {
             None
@@ -81,18 +143,262 @@ impl Manager {
         let obj = ChatMessage {
             id,
             room_id: room_id.to_string(),
-            user_id: user_id.to_string(),
-            user_name: user_name.to_string(),
-            user_image,
+            user_id: user.id.clone(),
+            user_name: user.username.clone(),
+            user_display_name: user.display_name.clone(),
+            user_image: user.image.clone(),
+            user_is_bot: user.is_bot,
+            user_is_guest: user.is_guest,
             created_at: OffsetDateTime::now_utc(),
+            kind: database::messages::TEXT_MESSAGE.to_string(),
             message: message.to_string(),
             uploads,
+            is_saved: false,
+            is_muted: false,
         };
 
-        room.sender.send(obj)?;
+        let sender = obj
+            .user_display_name
+            .clone()
+            .unwrap_or(obj.user_name.clone());
+        let id = obj.id.clone();
+
+        // nobody may have joined this room yet, the message is stored for later either way
+        self.broadcast(room_id, RoomEvent::Message(obj));
+        // the message is already stored, a failed fan-out only costs notifications
+        if let Err(e) = self.fan_out(room_id, &user.id, &id, &sender, message).await {
+            tracing::warn!("could not notify about message {}: {}", id, e);
+        }
 
         Ok(())
     }
+
+    pub async fn send_poll(
+        &self,
+        room_id: &str,
+        user: &UserCombined,
+        poll: &NewPoll,
+    ) -> Result<()> {
+        database::blocks::ensure_can_message(&self.db, room_id, &user.id).await?;
+
+        let id = database::polls::create_poll(&self.db, room_id, &user.id, poll).await?;
+
+        let obj = ChatMessage {
+            id,
+            room_id: room_id.to_string(),
+            user_id: user.id.clone(),
+            user_name: user.username.clone(),
+            user_display_name: user.display_name.clone(),
+            user_image: user.image.clone(),
+            user_is_bot: user.is_bot,
+            user_is_guest: user.is_guest,
+            created_at: OffsetDateTime::now_utc(),
+            kind: database::messages::POLL_MESSAGE.to_string(),
+            message: poll.question.clone(),
+            uploads: None,
+            is_saved: false,
+            is_muted: false,
+        };
+
+        let sender = obj
+            .user_display_name
+            .clone()
+            .unwrap_or(obj.user_name.clone());
+        let id = obj.id.clone();
+
+        self.broadcast(room_id, RoomEvent::Message(obj));
+        if let Err(e) = self
+            .fan_out(room_id, &user.id, &id, &sender, &poll.question)
+            .await
+        {
+            tracing::warn!("could not notify about poll {}: {}", id, e);
+        }
+
+        Ok(())
+    }
+
+    /// Keeps a notification and delivers it to the user's open tabs.
+    pub async fn notify(&self, notification: &NewNotification<'_>) -> Result<()> {
+        let id = database::notifications::create_notification(&self.db, notification).await?;
+        self.notify_user(notification.user_id, UserEvent::Notification { id });
+
+        Ok(())
+    }
+
+    /// Tells everyone who can read the room about a new message, as far as their
+    /// notification preferences and quiet hours allow, and keeps who was mentioned.
+    async fn fan_out(
+        &self,
+        room_id: &str,
+        sender_id: &str,
+        message_id: &str,
+        sender: &str,
+        message: &str,
+    ) -> Result<()> {
+        let handles = mentioned_handles(message);
+        let everyone = handles
+            .iter()
+            .any(|h| ROOM_MENTIONS.iter().any(|m| h.eq_ignore_ascii_case(m)));
+
+        let recipients =
+            database::preferences::get_recipients(&self.db, room_id, sender_id).await?;
+
+        let now = OffsetDateTime::now_utc();
+        let body: String = message.chars().take(NOTIFICATION_BODY_LENGTH).collect();
+        let mut mentioned = vec![];
+        let mut notifications = vec![];
+        for recipient in &recipients {
+            if recipient.ignores_sender {
+                continue;
+            }
+
+            let is_mentioned = everyone
+                || handles
+                    .iter()
+                    .any(|h| h.eq_ignore_ascii_case(&recipient.username));
+            if is_mentioned {
+                mentioned.push(recipient.user_id.clone());
+            }
+
+            let level = NotifyLevel::parse(&recipient.level).unwrap_or(NotifyLevel::All);
+            let muted = recipient.muted_until.is_some_and(|until| until > now);
+            if muted || level == NotifyLevel::Nothing {
+                continue;
+            }
+
+            self.notify_user(
+                &recipient.user_id,
+                UserEvent::Activity {
+                    room_id: room_id.to_string(),
+                },
+            );
+
+            let kind = if is_mentioned {
+                Some(NotificationKind::Mention)
+            } else if recipient.is_direct {
+                Some(NotificationKind::DirectMessage)
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                notifications.push(NewNotification {
+                    user_id: &recipient.user_id,
+                    kind,
+                    actor_id: Some(sender_id),
+                    room_id: Some(room_id),
+                    message_id: Some(message_id),
+                    body: Some(&body),
+                });
+            }
+
+            if (level == NotifyLevel::All || is_mentioned) && !in_quiet_hours(recipient, now) {
+                if self.is_connected(&recipient.user_id) {
+                    self.notify_user(
+                        &recipient.user_id,
+                        UserEvent::Alert {
+                            room_id: room_id.to_string(),
+                            sender: sender.to_string(),
+                            message: message.to_string(),
+                        },
+                    );
+                } else if recipient.is_direct || is_mentioned {
+                    // nothing listening is fine, pushes are simply not set up
+                    let _ = self.offline.send(OfflineAlert {
+                        user_id: recipient.user_id.clone(),
+                        room_id: room_id.to_string(),
+                        sender: sender.to_string(),
+                        message: message.to_string(),
+                    });
+                }
+            }
+        }
+
+        // stored together so a big room costs one transaction instead of one per recipient
+        match database::notifications::create_notifications(&self.db, &notifications).await {
+            Ok(ids) => {
+                for (notification, id) in notifications.iter().zip(ids) {
+                    self.notify_user(notification.user_id, UserEvent::Notification { id });
+                }
+            }
+            Err(e) => tracing::warn!("could not store notifications for {}: {}", message_id, e),
+        }
+
+        database::preferences::record_mentions(&self.db, message_id, &mentioned).await?;
+
+        Ok(())
+    }
+
+    fn is_connected(&self, user_id: &str) -> bool {
+        let users = self.users.read();
+        users
+            .get(user_id)
+            .is_some_and(|sender| sender.receiver_count() > 0)
+    }
+
+    fn notify_user(&self, user_id: &str, event: UserEvent) {
+        let users = self.users.read();
+        if let Some(sender) = users.get(user_id) {
+            // nobody listening just means the user is offline
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Lets everyone in the room know a poll tally changed so they can refresh it.
+    pub fn poll_updated(&self, room_id: &str, poll_id: &str) {
+        self.broadcast(
+            room_id,
+            RoomEvent::PollUpdated {
+                poll_id: poll_id.to_string(),
+            },
+        );
+    }
+
+    fn broadcast(&self, room_id: &str, event: RoomEvent) {
+        let rooms = self.rooms.read();
+        if let Some(room) = rooms.get(room_id) {
+            // sending only fails when every listener has left, which is fine
+            let _ = room.sender.send(event);
+        }
+    }
+
+    pub fn send_ephemeral(&self, room_id: &str, user_id: &str, message: &str) -> Result<()> {
+        let rooms = self.rooms.read();
+        let room = rooms
+            .get(room_id)
+            .ok_or_else(|| ChatRoomErrors::RoomEmpty(room_id.to_string()))?;
+
+        room.sender.send(RoomEvent::Ephemeral {
+            user_id: user_id.to_string(),
+            message: message.to_string(),
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Whether it is currently within the recipient's do not disturb hours, on their own clock.
+fn in_quiet_hours(recipient: &Recipient, now: OffsetDateTime) -> bool {
+    let (Some(start), Some(end)) = (recipient.quiet_start, recipient.quiet_end) else {
+        return false;
+    };
+
+    // without a timezone the hours are taken as UTC
+    let local = match recipient
+        .timezone
+        .as_deref()
+        .and_then(timezones::get_by_name)
+    {
+        Some(tz) => now.to_timezone(tz),
+        None => now,
+    };
+    let minute = i64::from(local.hour()) * 60 + i64::from(local.minute());
+
+    if start < end {
+        (start..end).contains(&minute)
+    } else {
+        // the quiet hours run past midnight
+        minute >= start || minute < end
+    }
 }
 
 #[derive(Error, Debug)]
@@ -100,3 +406,71 @@ pub enum ChatRoomErrors {
     #[error("room not joined : {0}")]
     RoomEmpty(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month};
+
+    use super::*;
+
+    fn recipient(quiet: Option<(i64, i64)>, timezone: Option<&str>) -> Recipient {
+        Recipient {
+            user_id: "ada".to_string(),
+            username: "ada".to_string(),
+            timezone: timezone.map(str::to_string),
+            quiet_start: quiet.map(|(start, _)| start),
+            quiet_end: quiet.map(|(_, end)| end),
+            level: NotifyLevel::All.as_str().to_string(),
+            muted_until: None,
+            ignores_sender: false,
+            is_direct: false,
+        }
+    }
+
+    fn utc(month: Month, hour: u8, minute: u8) -> OffsetDateTime {
+        Date::from_calendar_date(2026, month, 15)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        // 12:00 to 13:30 UTC
+        let lunch = recipient(Some((12 * 60, 13 * 60 + 30)), None);
+        assert!(!in_quiet_hours(&lunch, utc(Month::January, 11, 59)));
+        assert!(in_quiet_hours(&lunch, utc(Month::January, 12, 0)));
+        assert!(in_quiet_hours(&lunch, utc(Month::January, 13, 29)));
+        assert!(!in_quiet_hours(&lunch, utc(Month::January, 13, 30)));
+
+        assert!(!in_quiet_hours(
+            &recipient(None, None),
+            utc(Month::January, 12, 0)
+        ));
+    }
+
+    #[test]
+    fn quiet_hours_past_midnight() {
+        let night = recipient(Some((22 * 60, 7 * 60)), None);
+        assert!(in_quiet_hours(&night, utc(Month::January, 23, 0)));
+        assert!(in_quiet_hours(&night, utc(Month::January, 0, 0)));
+        assert!(in_quiet_hours(&night, utc(Month::January, 6, 59)));
+        assert!(!in_quiet_hours(&night, utc(Month::January, 7, 0)));
+        assert!(!in_quiet_hours(&night, utc(Month::January, 21, 59)));
+    }
+
+    #[test]
+    fn quiet_hours_follow_the_recipients_clock() {
+        // 22:00 to 07:00 in Berlin, one hour ahead of UTC in winter and two in summer
+        let berlin = recipient(Some((22 * 60, 7 * 60)), Some("Europe/Berlin"));
+        assert!(in_quiet_hours(&berlin, utc(Month::January, 21, 0)));
+        assert!(!in_quiet_hours(&berlin, utc(Month::January, 20, 59)));
+        assert!(in_quiet_hours(&berlin, utc(Month::July, 20, 0)));
+        assert!(!in_quiet_hours(&berlin, utc(Month::July, 5, 0)));
+
+        // an unknown timezone falls back to UTC
+        let unknown = recipient(Some((22 * 60, 7 * 60)), Some("Mars/Olympus"));
+        assert!(in_quiet_hours(&unknown, utc(Month::January, 22, 0)));
+    }
+}
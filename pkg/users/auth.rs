@@ -0,0 +1,55 @@
+use database::{users::DBUserErrors, Database};
+use futures::{future::BoxFuture, FutureExt};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    // lets the next backend have a go
+    #[error("unknown user")]
+    UnknownUser,
+    #[error("invalid credentials")]
+    InvalidCredentials,
+    // carries the id of the account, the credentials were right
+    #[error("user not enabled")]
+    UserNotEnabled(String),
+    // the backend just created the account, it still needs an admin's approval
+    #[error("user awaiting approval")]
+    AwaitingApproval(String),
+    #[error("internal error : {0}")]
+    InternalError(#[from] anyhow::Error),
+}
+
+/// Something that can tell whether an email and password belong together.
+pub trait AuthBackend: Send + Sync {
+    /// Returns the id of the local user the credentials belong to.
+    fn authenticate<'a>(
+        &'a self,
+        db: &'a Database,
+        email: &'a str,
+        password: &'a str,
+    ) -> BoxFuture<'a, Result<String, AuthError>>;
+}
+
+/// Passwords stored as Argon2 hashes in the users table.
+pub struct PasswordBackend;
+
+impl AuthBackend for PasswordBackend {
+    fn authenticate<'a>(
+        &'a self,
+        db: &'a Database,
+        email: &'a str,
+        password: &'a str,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        async move {
+            database::users::verify_userpassword(db, email, password)
+                .await
+                .map_err(|e| match e {
+                    DBUserErrors::UnknownUser => AuthError::UnknownUser,
+                    DBUserErrors::UserNotEnabled(user_id) => AuthError::UserNotEnabled(user_id),
+                    DBUserErrors::InternalError(e) => AuthError::InternalError(e),
+                    _ => AuthError::InvalidCredentials,
+                })
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,661 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use database::{users::ExternalLink, Database};
+use futures::{future::BoxFuture, FutureExt};
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, Scope, SearchEntry};
+
+use crate::auth::{AuthBackend, AuthError};
+
+// result code of a bind with the wrong password
+const INVALID_CREDENTIALS: u32 = 49;
+
+const ATTRIBUTES: [&str; 4] = ["mail", "displayName", "cn", "description"];
+
+pub struct LdapConfig {
+    /// e.g. ldaps://ldap.example.com
+    pub url: String,
+    /// account used to look users up, anonymous when empty
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub base_dn: String,
+    /// `{email}` is replaced with the escaped address typed into the login form
+    pub user_filter: String,
+    /// members of this group are admins, admin rights are managed in the app without it
+    pub admin_group: Option<String>,
+}
+
+/// Simple bind against a directory, users are created locally on their first sign in.
+pub struct LdapBackend {
+    config: LdapConfig,
+}
+
+#[derive(Debug)]
+struct DirectoryEntry {
+    dn: String,
+    email: String,
+    name: String,
+    bio: Option<String>,
+}
+
+impl DirectoryEntry {
+    fn from_search(entry: SearchEntry, fallback_email: &str) -> Self {
+        let first = |attr: &str| entry.attrs.get(attr).and_then(|v| v.first()).cloned();
+
+        let email = first("mail").unwrap_or_else(|| fallback_email.to_string());
+        let name = first("displayName")
+            .or_else(|| first("cn"))
+            .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+
+        Self {
+            bio: first("description"),
+            dn: entry.dn,
+            email,
+            name,
+        }
+    }
+}
+
+impl LdapBackend {
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+
+    // a connection bound as the lookup account
+    async fn connect(&self) -> Result<Ldap> {
+        let (conn, mut ldap) = LdapConnAsync::new(&self.config.url).await?;
+        ldap3::drive!(conn);
+
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.simple_bind(
+                bind_dn,
+                self.config.bind_password.as_deref().unwrap_or_default(),
+            )
+            .await?
+            .success()?;
+        }
+
+        Ok(ldap)
+    }
+
+    async fn find_user(&self, ldap: &mut Ldap, email: &str) -> Result<Option<DirectoryEntry>> {
+        let filter = self
+            .config
+            .user_filter
+            .replace("{email}", &ldap_escape(email));
+
+        let (entries, _) = ldap
+            .search(&self.config.base_dn, Scope::Subtree, &filter, ATTRIBUTES)
+            .await?
+            .success()?;
+
+        match entries.len() {
+            0 => Ok(None),
+            1 => {
+                let entry = SearchEntry::construct(entries.into_iter().next().unwrap());
+                Ok(Some(DirectoryEntry::from_search(entry, email)))
+            }
+            _ => Err(anyhow!("more than one directory entry matches {}", email)),
+        }
+    }
+
+    async fn read_entry(&self, ldap: &mut Ldap, dn: &str) -> Result<Option<DirectoryEntry>> {
+        let result = ldap
+            .search(dn, Scope::Base, "(objectClass=*)", ATTRIBUTES)
+            .await?;
+
+        // noSuchObject, the entry was removed from the directory
+        if result.1.rc == 32 {
+            return Ok(None);
+        }
+
+        let (entries, _) = result.success()?;
+
+        Ok(entries
+            .into_iter()
+            .next()
+            .map(|e| DirectoryEntry::from_search(SearchEntry::construct(e), "")))
+    }
+
+    async fn is_admin(&self, ldap: &mut Ldap, dn: &str) -> Result<Option<bool>> {
+        let Some(group) = &self.config.admin_group else {
+            return Ok(None);
+        };
+
+        let filter = format!("(|(member={0})(uniqueMember={0}))", ldap_escape(dn));
+        let (entries, _) = ldap
+            .search(group, Scope::Base, &filter, ["dn"])
+            .await?
+            .success()?;
+
+        Ok(Some(!entries.is_empty()))
+    }
+
+    async fn login(&self, db: &Database, email: &str, password: &str) -> Result<String, AuthError> {
+        // an empty password makes an unauthenticated bind, which most servers accept
+        if password.is_empty() {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        let mut ldap = self.connect().await?;
+
+        let Some(entry) = self.find_user(&mut ldap, email).await? else {
+            return Err(AuthError::UnknownUser);
+        };
+
+        let user = database::users::find_user_by_email(db, &entry.email).await?;
+        // a local account that merely shares the address keeps its own password, otherwise
+        // whoever controls the directory entry could take it over
+        if let Some(user) = &user {
+            if !database::directory::is_directory_user(db, &user.id).await? {
+                let _ = ldap.unbind().await;
+                return Err(AuthError::UnknownUser);
+            }
+        }
+
+        let is_admin = self.is_admin(&mut ldap, &entry.dn).await?;
+
+        let bind = ldap
+            .simple_bind(&entry.dn, password)
+            .await
+            .map_err(anyhow::Error::from)?;
+        let _ = ldap.unbind().await;
+
+        if bind.rc == INVALID_CREDENTIALS {
+            return Err(AuthError::InvalidCredentials);
+        }
+        bind.success().map_err(anyhow::Error::from)?;
+
+        let user_id = match user {
+            Some(user) if user.is_bot => return Err(AuthError::InvalidCredentials),
+            Some(user) => {
+                database::directory::sync_directory_user(
+                    db,
+                    &user.id,
+                    &entry.name,
+                    entry.bio.as_deref(),
+                    is_admin,
+                )
+                .await?;
+
+                database::directory::link_directory_user(db, &user.id, &entry.dn).await?;
+
+                if !user.is_enabled {
+                    return Err(AuthError::UserNotEnabled(user.id));
+                }
+
+                user.id
+            }
+            None => {
+                // same approval rules as registering, only the very first account starts enabled
+                let has_admin = database::users::has_admin(db).await?;
+                let user_id = database::users::create_external_user(
+                    db,
+                    &entry.email,
+                    &entry.name,
+                    !has_admin,
+                    !has_admin,
+                    ExternalLink::Directory { dn: &entry.dn },
+                )
+                .await?;
+
+                database::directory::sync_directory_user(
+                    db,
+                    &user_id,
+                    &entry.name,
+                    entry.bio.as_deref(),
+                    is_admin.map(|is_admin| is_admin || !has_admin),
+                )
+                .await?;
+
+                if has_admin {
+                    return Err(AuthError::AwaitingApproval(user_id));
+                }
+
+                user_id
+            }
+        };
+
+        Ok(user_id)
+    }
+
+    /// Refreshes profiles and admin rights of every directory user, disabling those that are gone.
+    pub async fn sync(&self, db: &Database) -> Result<()> {
+        let users = database::directory::get_directory_users(db).await?;
+        if users.is_empty() {
+            return Ok(());
+        }
+
+        let mut ldap = self.connect().await?;
+
+        for user in users {
+            let Some(entry) = self.read_entry(&mut ldap, &user.dn).await? else {
+                tracing::info!("{} left the directory, disabling {}", user.dn, user.user_id);
+                database::users::deactivate_user(db, &user.user_id).await?;
+                continue;
+            };
+
+            let is_admin = self.is_admin(&mut ldap, &user.dn).await?;
+            database::directory::sync_directory_user(
+                db,
+                &user.user_id,
+                &entry.name,
+                entry.bio.as_deref(),
+                is_admin,
+            )
+            .await?;
+        }
+
+        let _ = ldap.unbind().await;
+
+        Ok(())
+    }
+
+    /// Runs `sync` in the background every `every`.
+    pub fn spawn_sync(self: Arc<Self>, db: Database, every: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.sync(&db).await {
+                    tracing::warn!("failed to sync directory users: {}", e);
+                }
+            }
+        });
+    }
+}
+
+impl AuthBackend for LdapBackend {
+    fn authenticate<'a>(
+        &'a self,
+        db: &'a Database,
+        email: &'a str,
+        password: &'a str,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        self.login(db, email, password).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use database::testing::{database, local_user};
+    use ldap3::asn1::{
+        parse_tag, parse_uint, write, ASNTag, Enumerated, Integer, OctetString, Sequence, Set,
+        StructureTag, Tag, TagClass, PL,
+    };
+    use parking_lot::Mutex;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::auth::PasswordBackend;
+
+    const BASE_DN: &str = "ou=people,dc=example,dc=org";
+    const READER_DN: &str = "cn=reader,dc=example,dc=org";
+    const ADMINS_DN: &str = "cn=admins,ou=groups,dc=example,dc=org";
+    const ADA_DN: &str = "uid=ada,ou=people,dc=example,dc=org";
+    const BOB_DN: &str = "uid=bob,ou=people,dc=example,dc=org";
+
+    struct Entry {
+        dn: String,
+        password: Option<String>,
+        attrs: Vec<(String, Vec<String>)>,
+    }
+
+    impl Entry {
+        fn new(dn: &str, password: Option<&str>, attrs: &[(&str, &str)]) -> Self {
+            let mut grouped: Vec<(String, Vec<String>)> = Vec::new();
+            for (name, value) in attrs {
+                match grouped.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, values)) => values.push(value.to_string()),
+                    None => grouped.push((name.to_string(), vec![value.to_string()])),
+                }
+            }
+
+            Self {
+                dn: dn.to_string(),
+                password: password.map(str::to_string),
+                attrs: grouped,
+            }
+        }
+
+        fn values(&self, attr: &[u8]) -> Option<&Vec<String>> {
+            let attr = String::from_utf8_lossy(attr);
+            self.attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&attr))
+                .map(|(_, values)| values)
+        }
+
+        fn matches(&self, filter: &StructureTag) -> bool {
+            match (filter.id, &filter.payload) {
+                (0, PL::C(filters)) => filters.iter().all(|f| self.matches(f)),
+                (1, PL::C(filters)) => filters.iter().any(|f| self.matches(f)),
+                (2, PL::C(filters)) => !self.matches(&filters[0]),
+                (3, PL::C(pair)) => {
+                    let value = String::from_utf8_lossy(primitive(&pair[1]));
+                    self.values(primitive(&pair[0]))
+                        .is_some_and(|values| values.iter().any(|v| v.eq_ignore_ascii_case(&value)))
+                }
+                (7, PL::P(attr)) => self.values(attr).is_some(),
+                _ => false,
+            }
+        }
+    }
+
+    fn primitive(tag: &StructureTag) -> &[u8] {
+        match &tag.payload {
+            PL::P(bytes) => bytes,
+            PL::C(_) => &[],
+        }
+    }
+
+    fn string(value: &str) -> Tag {
+        Tag::OctetString(OctetString {
+            inner: value.as_bytes().to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn application(id: u64, inner: Vec<Tag>) -> Tag {
+        Tag::Sequence(Sequence {
+            id,
+            class: TagClass::Application,
+            inner,
+        })
+    }
+
+    fn result(id: u64, rc: i64) -> Tag {
+        application(
+            id,
+            vec![
+                Tag::Enumerated(Enumerated {
+                    inner: rc,
+                    ..Default::default()
+                }),
+                string(""),
+                string(""),
+            ],
+        )
+    }
+
+    /// Stands in for OpenLDAP with simple binds, searches and unbinds over a fixed set of entries.
+    struct MockDirectory {
+        entries: Mutex<Vec<Entry>>,
+    }
+
+    impl MockDirectory {
+        async fn serve(&self, mut stream: TcpStream) -> Result<()> {
+            let mut buf = Vec::new();
+            loop {
+                let parsed = parse_tag(&buf)
+                    .ok()
+                    .map(|(rest, tag)| (tag, buf.len() - rest.len()));
+                let Some((message, used)) = parsed else {
+                    let mut chunk = [0; 4096];
+                    let n = stream.read(&mut chunk).await?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                    continue;
+                };
+                buf.drain(..used);
+
+                let PL::C(mut parts) = message.payload else {
+                    return Err(anyhow!("malformed message"));
+                };
+                let op = parts.remove(1);
+                let (_, id) = parse_uint(primitive(&parts[0])).map_err(|e| anyhow!("{}", e))?;
+
+                let responses = match (op.id, op.payload) {
+                    (0, PL::C(bind)) => vec![self.bind(&bind)],
+                    (2, _) => return Ok(()),
+                    (3, PL::C(search)) => self.search(&search),
+                    (other, _) => return Err(anyhow!("unexpected operation {}", other)),
+                };
+
+                let mut out = BytesMut::new();
+                for response in responses {
+                    let message = Tag::Sequence(Sequence {
+                        inner: vec![
+                            Tag::Integer(Integer {
+                                inner: id as i64,
+                                ..Default::default()
+                            }),
+                            response,
+                        ],
+                        ..Default::default()
+                    });
+                    write::encode_into(&mut out, message.into_structure())?;
+                }
+                stream.write_all(&out).await?;
+            }
+        }
+
+        fn bind(&self, request: &[StructureTag]) -> Tag {
+            let dn = String::from_utf8_lossy(primitive(&request[1]));
+            let password = String::from_utf8_lossy(primitive(&request[2]));
+
+            let entries = self.entries.lock();
+            let known = entries
+                .iter()
+                .any(|e| e.dn == dn && e.password.as_deref() == Some(&password));
+
+            result(1, if known { 0 } else { INVALID_CREDENTIALS as i64 })
+        }
+
+        fn search(&self, request: &[StructureTag]) -> Vec<Tag> {
+            let base = String::from_utf8_lossy(primitive(&request[0]));
+            let (_, scope) = parse_uint(primitive(&request[1])).unwrap();
+            let filter = &request[6];
+
+            let entries = self.entries.lock();
+            let in_scope = |e: &&Entry| match scope {
+                0 => e.dn == base,
+                _ => e.dn == base || e.dn.ends_with(&format!(",{}", base)),
+            };
+
+            if scope == 0 && !entries.iter().any(|e| e.dn == base) {
+                return vec![result(5, 32)];
+            }
+
+            let mut responses: Vec<Tag> = entries
+                .iter()
+                .filter(in_scope)
+                .filter(|e| e.matches(filter))
+                .map(|e| {
+                    let attrs = e
+                        .attrs
+                        .iter()
+                        .map(|(name, values)| {
+                            Tag::Sequence(Sequence {
+                                inner: vec![
+                                    string(name),
+                                    Tag::Set(Set {
+                                        inner: values.iter().map(|v| string(v)).collect(),
+                                        ..Default::default()
+                                    }),
+                                ],
+                                ..Default::default()
+                            })
+                        })
+                        .collect();
+
+                    application(
+                        4,
+                        vec![
+                            string(&e.dn),
+                            Tag::Sequence(Sequence {
+                                inner: attrs,
+                                ..Default::default()
+                            }),
+                        ],
+                    )
+                })
+                .collect();
+            responses.push(result(5, 0));
+
+            responses
+        }
+
+        fn remove(&self, dn: &str) {
+            self.entries.lock().retain(|e| e.dn != dn);
+        }
+    }
+
+    async fn directory() -> (Arc<MockDirectory>, LdapBackend) {
+        let directory = Arc::new(MockDirectory {
+            entries: Mutex::new(vec![
+                Entry::new(READER_DN, Some("reader"), &[("objectClass", "person")]),
+                Entry::new(
+                    ADA_DN,
+                    Some("secret"),
+                    &[
+                        ("objectClass", "person"),
+                        ("mail", "ada@example.com"),
+                        ("displayName", "Ada Lovelace"),
+                        ("description", "counts things"),
+                    ],
+                ),
+                Entry::new(
+                    BOB_DN,
+                    Some("hunter2"),
+                    &[
+                        ("objectClass", "person"),
+                        ("mail", "bob@example.com"),
+                        ("cn", "bob"),
+                    ],
+                ),
+                Entry::new(
+                    ADMINS_DN,
+                    None,
+                    &[("objectClass", "groupOfNames"), ("member", BOB_DN)],
+                ),
+            ]),
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let server = directory.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move { server.serve(stream).await });
+            }
+        });
+
+        let backend = LdapBackend::new(LdapConfig {
+            url,
+            bind_dn: Some(READER_DN.to_string()),
+            bind_password: Some("reader".to_string()),
+            base_dn: BASE_DN.to_string(),
+            user_filter: "(&(objectClass=person)(mail={email}))".to_string(),
+            admin_group: Some(ADMINS_DN.to_string()),
+        });
+
+        (directory, backend)
+    }
+
+    #[tokio::test]
+    async fn provisions_directory_users() {
+        let db = database("ldap-provisions").await;
+        let (_, backend) = directory().await;
+
+        let user_id = backend
+            .login(&db, "ada@example.com", "secret")
+            .await
+            .unwrap();
+        assert_eq!(
+            backend
+                .login(&db, "ada@example.com", "secret")
+                .await
+                .unwrap(),
+            user_id
+        );
+
+        let user = database::users::get_user_with_profile(&db, &user_id)
+            .await
+            .unwrap();
+        assert_eq!(user.display_name.as_deref(), Some("Ada Lovelace"));
+        assert_eq!(user.bio.as_deref(), Some("counts things"));
+
+        assert!(matches!(
+            backend.login(&db, "ada@example.com", "wrong").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            backend.login(&db, "eve@example.com", "secret").await,
+            Err(AuthError::UnknownUser)
+        ));
+    }
+
+    #[tokio::test]
+    async fn leaves_local_accounts_alone() {
+        let db = database("ldap-local").await;
+        let (_, backend) = directory().await;
+
+        let local_id = local_user(&db, "ada").await;
+
+        assert!(matches!(
+            backend.login(&db, "ada@example.com", "secret").await,
+            Err(AuthError::UnknownUser)
+        ));
+        assert!(!database::directory::is_directory_user(&db, &local_id)
+            .await
+            .unwrap());
+
+        let backends: Vec<Arc<dyn AuthBackend>> =
+            vec![Arc::new(backend), Arc::new(PasswordBackend)];
+        assert!(matches!(
+            crate::authenticate(&db, &backends, "ada@example.com", "secret").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        assert_eq!(
+            crate::authenticate(&db, &backends, "ada@example.com", "password")
+                .await
+                .unwrap(),
+            local_id
+        );
+    }
+
+    #[tokio::test]
+    async fn syncs_admin_group_and_departures() {
+        let db = database("ldap-sync").await;
+        let (directory, backend) = directory().await;
+
+        let ada_id = backend
+            .login(&db, "ada@example.com", "secret")
+            .await
+            .unwrap();
+        // only the first account starts out enabled
+        let Err(AuthError::AwaitingApproval(bob_id)) =
+            backend.login(&db, "bob@example.com", "hunter2").await
+        else {
+            panic!("bob should wait for approval");
+        };
+
+        let bob = database::users::get_user_with_profile(&db, &bob_id)
+            .await
+            .unwrap();
+        assert!(bob.is_admin);
+
+        directory.remove(BOB_DN);
+        backend.sync(&db).await.unwrap();
+
+        let ada = database::users::get_user_with_profile(&db, &ada_id)
+            .await
+            .unwrap();
+        assert!(ada.is_enabled);
+        // the admin group decides once it is configured
+        assert!(!ada.is_admin);
+
+        let bob = database::users::get_user_with_profile(&db, &bob_id)
+            .await
+            .unwrap();
+        assert!(!bob.is_enabled);
+    }
+}
@@ -0,0 +1,347 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use jwt::{SignWithKey, VerifyWithKey};
+use openidconnect::{
+    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
+    reqwest, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet,
+    EndpointNotSet, EndpointSet, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, Scope, TokenResponse,
+};
+use parking_lot::RwLock;
+use sha2::Sha256;
+use time::{Duration, OffsetDateTime};
+
+use crate::UserErrors;
+
+// how long someone may take at the identity provider before the callback is refused
+const AUTH_REQUEST_DURATION: Duration = Duration::minutes(10);
+
+type Client = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+pub struct OidcConfig {
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+pub struct OidcProvider {
+    config: OidcConfig,
+    redirect_url: String,
+    client: RwLock<Arc<Client>>,
+    http: reqwest::Client,
+}
+
+/// Who the identity provider says signed in.
+#[derive(Debug)]
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: String,
+    pub name: Option<String>,
+}
+
+// everything needed to finish the flow, kept signed in a cookie between redirect and callback
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AuthRequestClaims {
+    state: String,
+    nonce: String,
+    verifier: String,
+    exp: i64,
+}
+
+impl OidcProvider {
+    /// Fetches the provider metadata through discovery, the provider sends users back to `redirect_url`.
+    pub async fn discover(config: OidcConfig, redirect_url: &str) -> Result<Self> {
+        // following redirects would open the token requests up to ssrf
+        let http = reqwest::ClientBuilder::new()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        let client = discover_client(&http, &config, redirect_url).await?;
+
+        Ok(Self {
+            config,
+            redirect_url: redirect_url.to_string(),
+            client: RwLock::new(Arc::new(client)),
+            http,
+        })
+    }
+
+    /// The url to send the browser to and the signed state to keep until it comes back.
+    pub fn authorize_url(&self, secret: &str) -> Result<(String, String)> {
+        let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let (url, state, nonce) = self
+            .client
+            .read()
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("email".to_string()))
+            .add_scope(Scope::new("profile".to_string()))
+            .set_pkce_challenge(challenge)
+            .url();
+
+        let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
+        let claims = AuthRequestClaims {
+            state: state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            verifier: verifier.secret().clone(),
+            exp: (OffsetDateTime::now_utc() + AUTH_REQUEST_DURATION).unix_timestamp(),
+        };
+        let request = claims.sign_with_key(&key)?;
+
+        Ok((url.to_string(), request))
+    }
+
+    /// Redeems the authorization code from the callback and verifies the id token.
+    pub async fn exchange(
+        &self,
+        secret: &str,
+        request: &str,
+        state: &str,
+        code: &str,
+    ) -> Result<Identity> {
+        let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
+        let claims: AuthRequestClaims = request.verify_with_key(&key)?;
+
+        if claims.state != state || claims.exp <= OffsetDateTime::now_utc().unix_timestamp() {
+            return Err(UserErrors::SessionExpired.into());
+        }
+
+        let client = self.client.read().clone();
+        let response = client
+            .exchange_code(AuthorizationCode::new(code.to_string()))?
+            .set_pkce_verifier(PkceCodeVerifier::new(claims.verifier))
+            .request_async(&self.http)
+            .await?;
+
+        let id_token = response
+            .id_token()
+            .ok_or_else(|| anyhow!("identity provider did not return an id token"))?;
+        let nonce = Nonce::new(claims.nonce);
+        let id_claims = match id_token.claims(&client.id_token_verifier(), &nonce) {
+            Ok(id_claims) => id_claims.clone(),
+            Err(_) => {
+                // the provider may have rotated its signing keys since we last looked
+                let client =
+                    Arc::new(discover_client(&self.http, &self.config, &self.redirect_url).await?);
+                *self.client.write() = client.clone();
+                let id_claims = id_token
+                    .claims(&client.id_token_verifier(), &nonce)?
+                    .clone();
+                id_claims
+            }
+        };
+
+        // an account is linked by email the first time, so an unverified one could take over
+        // someone else's, and providers that leave the claim out are not trusted with it either
+        if id_claims.email_verified() != Some(true) {
+            return Err(anyhow!("email address is not verified"));
+        }
+
+        let email = id_claims
+            .email()
+            .ok_or_else(|| anyhow!("identity provider did not share an email address"))?
+            .to_string();
+
+        let name = id_claims
+            .preferred_username()
+            .map(|n| n.to_string())
+            .or_else(|| {
+                id_claims
+                    .name()
+                    .and_then(|n| n.get(None))
+                    .map(|n| n.to_string())
+            });
+
+        Ok(Identity {
+            issuer: id_claims.issuer().to_string(),
+            subject: id_claims.subject().to_string(),
+            email,
+            name,
+        })
+    }
+}
+
+async fn discover_client(
+    http: &reqwest::Client,
+    config: &OidcConfig,
+    redirect_url: &str,
+) -> Result<Client> {
+    let metadata =
+        CoreProviderMetadata::discover_async(IssuerUrl::new(config.issuer_url.clone())?, http)
+            .await?;
+
+    let client = CoreClient::from_provider_metadata(
+        metadata,
+        ClientId::new(config.client_id.clone()),
+        config.client_secret.clone().map(ClientSecret::new),
+    )
+    .set_redirect_uri(RedirectUrl::new(redirect_url.to_string())?);
+
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{extract::State, routing::get, routing::post, Json, Router};
+    use openidconnect::{
+        core::{
+            CoreIdToken, CoreIdTokenClaims, CoreIdTokenFields, CoreJsonWebKeySet,
+            CoreJwsSigningAlgorithm, CoreProviderMetadata, CoreResponseType,
+            CoreRsaPrivateSigningKey, CoreSubjectIdentifierType, CoreTokenResponse, CoreTokenType,
+        },
+        AccessToken, Audience, AuthUrl, EmptyAdditionalClaims, EmptyAdditionalProviderMetadata,
+        EmptyExtraTokenFields, EndUserEmail, JsonWebKeyId, JsonWebKeySetUrl, PrivateSigningKey,
+        ResponseTypes, StandardClaims, SubjectIdentifier, TokenUrl,
+    };
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    const CLIENT_ID: &str = "speakwith";
+    const SECRET: &str = "cookie secret";
+
+    /// Just enough of an identity provider for discovery, keys and the code exchange.
+    struct MockIdp {
+        issuer: String,
+        key: CoreRsaPrivateSigningKey,
+        email_verified: Option<bool>,
+        nonce: Mutex<Option<String>>,
+    }
+
+    async fn metadata(State(idp): State<Arc<MockIdp>>) -> Json<CoreProviderMetadata> {
+        let metadata = CoreProviderMetadata::new(
+            IssuerUrl::new(idp.issuer.clone()).unwrap(),
+            AuthUrl::new(format!("{}/authorize", idp.issuer)).unwrap(),
+            JsonWebKeySetUrl::new(format!("{}/jwks", idp.issuer)).unwrap(),
+            vec![ResponseTypes::new(vec![CoreResponseType::Code])],
+            vec![CoreSubjectIdentifierType::Public],
+            vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
+            EmptyAdditionalProviderMetadata {},
+        )
+        .set_token_endpoint(Some(
+            TokenUrl::new(format!("{}/token", idp.issuer)).unwrap(),
+        ));
+
+        Json(metadata)
+    }
+
+    async fn jwks(State(idp): State<Arc<MockIdp>>) -> Json<CoreJsonWebKeySet> {
+        Json(CoreJsonWebKeySet::new(vec![idp.key.as_verification_key()]))
+    }
+
+    async fn token(State(idp): State<Arc<MockIdp>>) -> Json<CoreTokenResponse> {
+        let now = chrono::Utc::now();
+        let claims = CoreIdTokenClaims::new(
+            IssuerUrl::new(idp.issuer.clone()).unwrap(),
+            vec![Audience::new(CLIENT_ID.to_string())],
+            now + chrono::Duration::minutes(5),
+            now,
+            StandardClaims::new(SubjectIdentifier::new("subject-1".to_string()))
+                .set_email(Some(EndUserEmail::new("ada@example.com".to_string())))
+                .set_email_verified(idp.email_verified),
+            EmptyAdditionalClaims {},
+        )
+        .set_nonce(idp.nonce.lock().clone().map(Nonce::new));
+
+        let access_token = AccessToken::new("access".to_string());
+        let id_token = CoreIdToken::new(
+            claims,
+            &idp.key,
+            CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            Some(&access_token),
+            None,
+        )
+        .unwrap();
+
+        Json(CoreTokenResponse::new(
+            access_token,
+            CoreTokenType::Bearer,
+            CoreIdTokenFields::new(Some(id_token), EmptyExtraTokenFields {}),
+        ))
+    }
+
+    /// Runs a whole sign in against a fresh mock provider.
+    async fn sign_in(email_verified: Option<bool>) -> Result<Identity> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let issuer = format!("http://{}", listener.local_addr()?);
+
+        let idp = Arc::new(MockIdp {
+            issuer: issuer.clone(),
+            key: CoreRsaPrivateSigningKey::from_pem(
+                include_str!("testdata/oidc-key.pem"),
+                Some(JsonWebKeyId::new("test".to_string())),
+            )
+            .map_err(|e| anyhow!(e))?,
+            email_verified,
+            nonce: Mutex::new(None),
+        });
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(metadata))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(idp.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let provider = OidcProvider::discover(
+            OidcConfig {
+                issuer_url: issuer,
+                client_id: CLIENT_ID.to_string(),
+                client_secret: Some("client secret".to_string()),
+            },
+            "http://localhost/auth/oidc/callback",
+        )
+        .await?;
+
+        let (url, request) = provider.authorize_url(SECRET)?;
+        let url = openidconnect::url::Url::parse(&url)?;
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.to_string())
+                .unwrap()
+        };
+        *idp.nonce.lock() = Some(param("nonce"));
+
+        provider
+            .exchange(SECRET, &request, &param("state"), "code")
+            .await
+    }
+
+    #[tokio::test]
+    async fn signs_in_with_a_verified_email() {
+        let identity = sign_in(Some(true)).await.unwrap();
+
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.email, "ada@example.com");
+        assert!(identity.issuer.starts_with("http://127.0.0.1:"));
+    }
+
+    #[tokio::test]
+    async fn refuses_unverified_emails() {
+        let error = sign_in(Some(false)).await.unwrap_err();
+        assert_eq!(error.to_string(), "email address is not verified");
+    }
+
+    #[tokio::test]
+    async fn refuses_tokens_without_email_verified() {
+        let error = sign_in(None).await.unwrap_err();
+        assert_eq!(error.to_string(), "email address is not verified");
+    }
+}
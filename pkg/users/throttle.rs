@@ -0,0 +1,156 @@
+use anyhow::Result;
+use database::{
+    login_attempts::{self, Failures},
+    Database,
+};
+use time::{Duration, OffsetDateTime};
+
+// failures older than this are forgotten
+const WINDOW: Duration = Duration::hours(24);
+const BASE_DELAY: Duration = Duration::seconds(30);
+const MAX_DELAY: Duration = Duration::minutes(30);
+
+// failures allowed before the backoff starts, an address may be shared by many people
+const ACCOUNT_FREE_ATTEMPTS: i64 = 5;
+const IP_FREE_ATTEMPTS: i64 = 20;
+
+/// Exponential backoff, each failure past the free ones doubles the wait.
+fn locked_until(failures: &Failures, free_attempts: i64) -> Option<OffsetDateTime> {
+    let over = failures.count - free_attempts;
+    if over < 0 {
+        return None;
+    }
+
+    let delay = BASE_DELAY
+        .checked_mul(1 << over.min(16))
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY);
+
+    failures
+        .last_at
+        .map(|last| last + delay)
+        .filter(|until| *until > OffsetDateTime::now_utc())
+}
+
+/// When the account or the address may try again, `None` if they may right now.
+pub async fn login_locked_until(
+    db: &Database,
+    email: &str,
+    ip: &str,
+) -> Result<Option<OffsetDateTime>> {
+    let since = OffsetDateTime::now_utc() - WINDOW;
+
+    let account = login_attempts::account_failures(db, email, since).await?;
+    let address = login_attempts::ip_failures(db, ip, since).await?;
+
+    Ok([
+        locked_until(&account, ACCOUNT_FREE_ATTEMPTS),
+        locked_until(&address, IP_FREE_ATTEMPTS),
+    ]
+    .into_iter()
+    .flatten()
+    .max())
+}
+
+#[cfg(test)]
+mod tests {
+    use database::{
+        login_attempts::{record_login_attempt, LoginOutcome},
+        testing::database,
+    };
+
+    use super::*;
+
+    fn failures(count: i64, ago: Duration) -> Failures {
+        Failures {
+            count,
+            last_at: Some(OffsetDateTime::now_utc() - ago),
+        }
+    }
+
+    fn wait(failures: &Failures) -> Option<Duration> {
+        locked_until(failures, ACCOUNT_FREE_ATTEMPTS).map(|until| until - failures.last_at.unwrap())
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(
+            wait(&failures(ACCOUNT_FREE_ATTEMPTS - 1, Duration::ZERO)),
+            None
+        );
+        assert_eq!(
+            wait(&failures(ACCOUNT_FREE_ATTEMPTS, Duration::ZERO)),
+            Some(BASE_DELAY)
+        );
+        assert_eq!(
+            wait(&failures(ACCOUNT_FREE_ATTEMPTS + 2, Duration::ZERO)),
+            Some(BASE_DELAY * 4)
+        );
+        assert_eq!(
+            wait(&failures(ACCOUNT_FREE_ATTEMPTS + 60, Duration::ZERO)),
+            Some(MAX_DELAY)
+        );
+
+        // the wait is over once the delay has passed since the last failure
+        assert_eq!(wait(&failures(ACCOUNT_FREE_ATTEMPTS, BASE_DELAY)), None);
+        assert_eq!(locked_until(&Failures::default(), 0), None);
+    }
+
+    async fn fail(db: &Database, email: &str, ip: &str, times: i64) {
+        for _ in 0..times {
+            record_login_attempt(db, email, ip, LoginOutcome::InvalidCredentials)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn locks_accounts_and_addresses() {
+        let db = database("throttle-locks").await;
+
+        fail(
+            &db,
+            "ada@example.com",
+            "10.0.0.1",
+            ACCOUNT_FREE_ATTEMPTS - 1,
+        )
+        .await;
+        assert!(login_locked_until(&db, "ada@example.com", "10.0.0.2")
+            .await
+            .unwrap()
+            .is_none());
+
+        // the account is locked from every address, others are not
+        fail(&db, "ada@example.com", "10.0.0.1", 1).await;
+        assert!(login_locked_until(&db, "ada@example.com", "10.0.0.2")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(login_locked_until(&db, "bob@example.com", "10.0.0.1")
+            .await
+            .unwrap()
+            .is_none());
+
+        // a success clears the account but not the address
+        record_login_attempt(&db, "ada@example.com", "10.0.0.1", LoginOutcome::Success)
+            .await
+            .unwrap();
+        assert!(login_locked_until(&db, "ada@example.com", "10.0.0.2")
+            .await
+            .unwrap()
+            .is_none());
+
+        // spraying many accounts from one address locks the address
+        for i in 0..IP_FREE_ATTEMPTS {
+            fail(&db, &format!("user{i}@example.com"), "10.0.0.3", 1).await;
+        }
+        assert!(login_locked_until(&db, "carol@example.com", "10.0.0.3")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(login_locked_until(&db, "carol@example.com", "10.0.0.4")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
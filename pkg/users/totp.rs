@@ -0,0 +1,123 @@
+use anyhow::Result;
+use qrcode::{render::svg, QrCode};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use time::OffsetDateTime;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::hash_api_token;
+
+const ISSUER: &str = "SpeakWith";
+const STEP: u64 = 30;
+const RECOVERY_CODES: usize = 10;
+
+/// A fresh base32 encoded secret for enrollment.
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, account: &str) -> Result<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow::anyhow!("invalid totp secret: {:?}", e))?;
+
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        STEP,
+        bytes,
+        Some(ISSUER.to_string()),
+        account.replace(':', ""),
+    )?;
+
+    Ok(totp)
+}
+
+/// The otpauth:// uri authenticator apps understand.
+pub fn otpauth_url(secret: &str, account: &str) -> Result<String> {
+    Ok(totp(secret, account)?.get_url())
+}
+
+pub fn qr_svg(url: &str) -> Result<String> {
+    let code = QrCode::new(url.as_bytes())?;
+
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// Checks a code against the current time step and its neighbours, returning the step it matched.
+pub fn verify_code(secret: &str, code: &str) -> Option<i64> {
+    let totp = totp(secret, "").ok()?;
+    let code = code.trim().replace(' ', "");
+    let now = OffsetDateTime::now_utc().unix_timestamp() as u64;
+
+    [now - STEP, now, now + STEP]
+        .into_iter()
+        .find(|time| totp.generate(*time) == code)
+        .map(|time| (time / STEP) as i64)
+}
+
+/// Plain recovery codes to show once, store them with `hash_recovery_code`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    hash_api_token(&code.trim().to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_at(secret: &str, time: u64) -> String {
+        totp(secret, "").unwrap().generate(time)
+    }
+
+    #[test]
+    fn accepts_codes_from_neighbouring_steps_only() {
+        let secret = generate_secret();
+        // stay clear of a step boundary so verify_code sees the same step
+        let mut now = OffsetDateTime::now_utc().unix_timestamp() as u64;
+        if now % STEP > STEP - 3 {
+            std::thread::sleep(std::time::Duration::from_secs(3));
+            now = OffsetDateTime::now_utc().unix_timestamp() as u64;
+        }
+        let step = (now / STEP) as i64;
+
+        // the step the code belongs to, which is what gets stored against replays
+        let code = code_at(&secret, now);
+        assert_eq!(verify_code(&secret, &code), Some(step));
+        // authenticator apps show codes in two halves
+        assert!(verify_code(&secret, &format!("{} {}", &code[..3], &code[3..])).is_some());
+
+        // a slow clock on the phone still works, a code from minutes ago does not
+        let late = code_at(&secret, now - STEP);
+        assert!(verify_code(&secret, &late).is_some_and(|s| s <= step));
+        let stale = code_at(&secret, now - 10 * STEP);
+        if ![late.as_str(), code.as_str()].contains(&stale.as_str()) {
+            assert_eq!(verify_code(&secret, &stale), None);
+        }
+
+        assert_eq!(verify_code(&generate_secret(), "12345"), None);
+    }
+
+    #[test]
+    fn recovery_codes_ignore_case_and_spacing() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert_eq!(
+            hash_recovery_code(&codes[0]),
+            hash_recovery_code(&format!(" {} ", codes[0].to_uppercase()))
+        );
+        assert_ne!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[1]));
+    }
+}
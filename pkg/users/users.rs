@@ -1,11 +1,19 @@
-use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use anyhow::Result;
+use auth::{AuthBackend, AuthError};
 use database::Database;
 use hmac::{Hmac, Mac};
 use jwt::{SignWithKey, VerifyWithKey};
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+
+pub mod auth;
+pub mod ldap;
+pub mod oidc;
+pub mod throttle;
+pub mod totp;
 
 #[derive(serde::Deserialize)]
 pub struct RegisterForm {
@@ -20,30 +28,193 @@ pub struct LoginForm {
     pub password: String,
 }
 
-pub async fn login_user(db: &Database, secret: &str, form: LoginForm) -> Result<String> {
-    let user_id = database::users::verify_userpassword(db, &form.email, &form.password).await?;
+// sessions slide forward as long as they are used at least once per period
+pub const SESSION_DURATION: Duration = Duration::days(14);
+const SESSION_REFRESH: Duration = Duration::days(1);
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub sid: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl Claims {
+    pub fn needs_refresh(&self) -> bool {
+        OffsetDateTime::now_utc().unix_timestamp() - self.iat > SESSION_REFRESH.whole_seconds()
+    }
+}
+
+pub async fn login_user(
+    db: &Database,
+    secret: &str,
+    backends: &[Arc<dyn AuthBackend>],
+    form: LoginForm,
+) -> Result<(String, OffsetDateTime)> {
+    let user_id = authenticate(db, backends, &form.email, &form.password).await?;
+
+    start_session(db, secret, &user_id).await
+}
+
+/// Asks each backend in turn, the first one that knows the user decides.
+pub async fn authenticate(
+    db: &Database,
+    backends: &[Arc<dyn AuthBackend>],
+    email: &str,
+    password: &str,
+) -> Result<String, AuthError> {
+    for backend in backends {
+        match backend.authenticate(db, email, password).await {
+            Err(AuthError::UnknownUser) => continue,
+            result => return result,
+        }
+    }
+
+    Err(AuthError::InvalidCredentials)
+}
+
+/// Opens a new session for the user and returns its token and expiry.
+pub async fn start_session(
+    db: &Database,
+    secret: &str,
+    user_id: &str,
+) -> Result<(String, OffsetDateTime)> {
+    let expires_at = OffsetDateTime::now_utc() + SESSION_DURATION;
+    let session_id = database::sessions::create_session(db, user_id, expires_at).await?;
 
-    generate_token(secret, &user_id)
+    let token = generate_token(secret, user_id, &session_id, expires_at)?;
+
+    Ok((token, expires_at))
+}
+
+/// Extends a still active session and returns a fresh token for it.
+pub async fn refresh_session(
+    db: &Database,
+    secret: &str,
+    claims: &Claims,
+) -> Result<(String, OffsetDateTime)> {
+    let expires_at = OffsetDateTime::now_utc() + SESSION_DURATION;
+    database::sessions::extend_session(db, &claims.sid, &claims.sub, expires_at).await?;
+
+    let token = generate_token(secret, &claims.sub, &claims.sid, expires_at)?;
+
+    Ok((token, expires_at))
 }
 
-pub fn generate_token(secret: &str, user_id: &str) -> Result<String> {
+pub fn generate_token(
+    secret: &str,
+    user_id: &str,
+    session_id: &str,
+    expires_at: OffsetDateTime,
+) -> Result<String> {
     let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
-    let mut claims = BTreeMap::new();
-    claims.insert("sub", user_id);
+    let claims = Claims {
+        sub: user_id.to_string(),
+        sid: session_id.to_string(),
+        iat: OffsetDateTime::now_utc().unix_timestamp(),
+        exp: expires_at.unix_timestamp(),
+    };
     let token = claims.sign_with_key(&key)?;
 
     Ok(token)
 }
 
-pub fn extract_sub(secret: &str, token: &str) -> Result<Option<String>> {
+/// Verifies the signature and expiry of a token, the session itself may still be revoked.
+pub fn extract_sub(secret: &str, token: &str) -> Result<Claims> {
     let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
-    let claims: BTreeMap<String, String> = token.verify_with_key(&key)?;
-    let sub = claims.get("sub").cloned();
-    Ok(sub)
+    let claims: Claims = token.verify_with_key(&key)?;
+
+    if claims.exp <= OffsetDateTime::now_utc().unix_timestamp() {
+        return Err(UserErrors::SessionExpired.into());
+    }
+
+    Ok(claims)
+}
+
+/// Claims of a token whose session is still active.
+pub async fn validate_session(db: &Database, secret: &str, token: &str) -> Result<Claims> {
+    let claims = extract_sub(secret, token)?;
+    database::sessions::verify_session(db, &claims.sid, &claims.sub).await?;
+
+    Ok(claims)
+}
+
+// only good for finishing a login with the second factor
+const PENDING_LOGIN_DURATION: Duration = Duration::minutes(5);
+const PENDING_LOGIN_STAGE: &str = "two_factor";
+
+// only good for reading how the registration stands
+const APPROVAL_STATUS_DURATION: Duration = Duration::hours(1);
+const APPROVAL_STATUS_STAGE: &str = "approval";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PendingLoginClaims {
+    sub: String,
+    stage: String,
+    exp: i64,
+}
+
+fn generate_stage_token(
+    secret: &str,
+    user_id: &str,
+    stage: &str,
+    duration: Duration,
+) -> Result<String> {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
+    let claims = PendingLoginClaims {
+        sub: user_id.to_string(),
+        stage: stage.to_string(),
+        exp: (OffsetDateTime::now_utc() + duration).unix_timestamp(),
+    };
+    let token = claims.sign_with_key(&key)?;
+
+    Ok(token)
+}
+
+fn extract_stage_token(secret: &str, token: &str, stage: &str) -> Result<String> {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
+    let claims: PendingLoginClaims = token.verify_with_key(&key)?;
+
+    if claims.stage != stage || claims.exp <= OffsetDateTime::now_utc().unix_timestamp() {
+        return Err(UserErrors::SessionExpired.into());
+    }
+
+    Ok(claims.sub)
+}
+
+/// Token for someone who passed the password check but still owes a second factor.
+pub fn generate_pending_login_token(secret: &str, user_id: &str) -> Result<String> {
+    generate_stage_token(secret, user_id, PENDING_LOGIN_STAGE, PENDING_LOGIN_DURATION)
+}
+
+pub fn extract_pending_login(secret: &str, token: &str) -> Result<String> {
+    extract_stage_token(secret, token, PENDING_LOGIN_STAGE)
+}
+
+/// Token for someone whose account is not enabled, it only lets them see why.
+pub fn generate_approval_status_token(secret: &str, user_id: &str) -> Result<String> {
+    generate_stage_token(
+        secret,
+        user_id,
+        APPROVAL_STATUS_STAGE,
+        APPROVAL_STATUS_DURATION,
+    )
+}
+
+pub fn extract_approval_status(secret: &str, token: &str) -> Result<String> {
+    extract_stage_token(secret, token, APPROVAL_STATUS_STAGE)
+}
+
+/// Api tokens are only ever stored hashed, the plain token is shown once on creation.
+pub fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
 #[derive(Error, Debug)]
 pub enum UserErrors {
     #[error("internal error: {0}")]
     InternalError(anyhow::Error),
+    #[error("session expired")]
+    SessionExpired,
 }